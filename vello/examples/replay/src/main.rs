@@ -0,0 +1,98 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Replays a trace captured with `Renderer::capture` and writes the result as a PPM image.
+//!
+//! Usage: `replay <trace> <output.ppm>`
+
+use std::num::NonZeroU32;
+
+use vello::{util::RenderContext, Renderer, Trace};
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.len() != 3 {
+        eprintln!("usage: {} <trace> <output.ppm>", args[0]);
+        std::process::exit(1);
+    }
+    let data = std::fs::read(&args[1]).expect("failed to read trace");
+    let trace = Trace::from_bytes(data).expect("failed to load trace");
+    let image = pollster::block_on(replay(&trace)).expect("failed to replay trace");
+    std::fs::write(&args[2], image).expect("failed to write image");
+}
+
+async fn replay(trace: &Trace) -> vello::Result<Vec<u8>> {
+    let render_cx = RenderContext::new().await?;
+    let device = &render_cx.device;
+    let queue = &render_cx.queue;
+    let mut renderer = Renderer::with_options(device, *trace.options())?;
+    let (width, height) = (trace.width(), trace.height());
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    renderer.replay(device, queue, trace, &view, width, height)?;
+
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let padded_byte_width = (width * 4 + align - 1) / align * align;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: padded_byte_width as u64 * height as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_byte_width),
+                rows_per_image: None,
+            },
+        },
+        size,
+    );
+    queue.submit(Some(encoder.finish()));
+    let slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().map_err(|_| vello::Error::DeviceLost)??;
+
+    let mapped = slice.get_mapped_range();
+    let mut image = format!("P6\n{width} {height}\n255\n").into_bytes();
+    for row in mapped.chunks_exact(padded_byte_width as usize) {
+        for pixel in row[..width as usize * 4].chunks_exact(4) {
+            image.extend_from_slice(&pixel[..3]);
+        }
+    }
+    Ok(image)
+}
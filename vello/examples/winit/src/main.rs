@@ -27,6 +27,10 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
     let size = window.inner_size();
     let mut surface = render_cx.create_surface(&window, size.width, size.height);
     let mut renderer = Renderer::new(&render_cx.device).unwrap();
+    #[cfg(feature = "hot_reload")]
+    renderer
+        .watch_shaders()
+        .expect("failed to watch shader directory");
     let mut simple_text = simple_text::SimpleText::new();
     let mut current_frame = 0usize;
     let mut scene_ix = 0usize;
@@ -70,6 +74,12 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                 _ => test_scene::render_scene(&mut builder),
             }
             builder.finish();
+            #[cfg(feature = "hot_reload")]
+            match renderer.reload_shaders(&render_cx.device) {
+                Ok(true) => eprintln!("reloaded shaders"),
+                Ok(false) => {}
+                Err(e) => eprintln!("{e}"),
+            }
             let surface_texture = surface
                 .surface
                 .get_current_texture()
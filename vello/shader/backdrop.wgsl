@@ -14,13 +14,11 @@ var<uniform> config: Config;
 @group(0) @binding(1)
 var<storage, read_write> tiles: array<Tile>;
 
-let WG_SIZE = 64u;
-
 var<workgroup> sh_backdrop: array<i32, WG_SIZE>;
 
 // Each workgroup computes the inclusive prefix sum of the backdrops
 // in one row of tiles.
-@compute @workgroup_size(64)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(local_invocation_id) local_id: vec3<u32>,
     @builtin(workgroup_id) wg_id: vec3<u32>,
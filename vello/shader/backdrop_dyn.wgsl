@@ -14,13 +14,11 @@ var<storage> paths: array<Path>;
 @group(0) @binding(2)
 var<storage, read_write> tiles: array<Tile>;
 
-let WG_SIZE = 256u;
-
 var<workgroup> sh_row_width: array<u32, WG_SIZE>;
 var<workgroup> sh_row_count: array<u32, WG_SIZE>;
 var<workgroup> sh_offset: array<u32, WG_SIZE>;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
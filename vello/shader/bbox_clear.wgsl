@@ -17,7 +17,7 @@ struct PathBbox {
 @group(0) @binding(1)
 var<storage, read_write> path_bboxes: array<PathBbox>;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
 ) {
@@ -37,28 +37,34 @@ struct BinHeader {
 @group(0) @binding(7)
 var<storage, read_write> bin_header: array<BinHeader>;
 
-// conversion factors from coordinates to bin
-let SX = 0.00390625;
-let SY = 0.00390625;
-//let SX = 1.0 / f32(N_TILE_X * TILE_WIDTH);
-//let SY = 1.0 / f32(N_TILE_Y * TILE_HEIGHT);
+// Atomic view of the coarse dispatch arguments in IndirectArgs.
+struct AtomicIndirectArgs {
+    path_coarse_x: u32,
+    path_coarse_y: u32,
+    path_coarse_z: u32,
+    coarse_x: atomic<u32>,
+    coarse_y: atomic<u32>,
+    coarse_z: u32,
+}
 
-let WG_SIZE = 256u;
-let N_SLICE = 8u;
-//let N_SLICE = WG_SIZE / 32u;
-let N_SUBSLICE = 4u;
+@group(0) @binding(8)
+var<storage, read_write> indirect: AtomicIndirectArgs;
 
 var<workgroup> sh_bitmaps: array<array<atomic<u32>, N_TILE>, N_SLICE>;
 // store count values packed two u16's to a u32
 var<workgroup> sh_count: array<array<u32, N_TILE>, N_SUBSLICE>;
 var<workgroup> sh_chunk_offset: array<u32, N_TILE>;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
     @builtin(workgroup_id) wg_id: vec3<u32>,
 ) {
+    // conversion factors from coordinates to bin
+    let SX = 1.0 / f32(N_TILE_X * TILE_WIDTH);
+    let SY = 1.0 / f32(N_TILE_Y * TILE_HEIGHT);
+
     for (var i = 0u; i < N_SLICE; i += 1u) {
         atomicStore(&sh_bitmaps[i][local_id.x], 0u);
     }
@@ -102,6 +108,11 @@ fn main(
     if x0 == x1 {
         y1 = y0;
     }
+    // Extend the coarse dispatch to the bins touched by this draw object.
+    if y1 > y0 {
+        atomicMax(&indirect.coarse_x, u32(x1));
+        atomicMax(&indirect.coarse_y, u32(y1));
+    }
     var x = x0;
     var y = y0;
     let my_slice = local_id.x / 32u;
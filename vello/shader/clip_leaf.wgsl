@@ -26,7 +26,6 @@ var<storage, read_write> draw_monoids: array<DrawMonoid>;
 @group(0) @binding(6)
 var<storage, read_write> clip_bboxes: array<vec4<f32>>;
 
-let WG_SIZE = 256u;
 var<workgroup> sh_bic: array<Bic, 510 >;
 var<workgroup> sh_stack: array<u32, WG_SIZE>;
 var<workgroup> sh_stack_bbox: array<vec4<f32>, WG_SIZE>;
@@ -76,7 +75,7 @@ fn load_clip_path(ix: u32) -> i32 {
     }
 }
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
@@ -45,9 +45,6 @@ var<storage, read_write> ptcl: array<u32>;
 
 // Much of this code assumes WG_SIZE == N_TILE. If these diverge, then
 // a fair amount of fixup is needed.
-let WG_SIZE = 256u;
-//let N_SLICE = WG_SIZE / 32u;
-let N_SLICE = 8u;
 
 var<workgroup> sh_bitmaps: array<array<atomic<u32>, N_TILE>, N_SLICE>;
 var<workgroup> sh_part_count: array<u32, WG_SIZE>;
@@ -117,6 +114,17 @@ fn write_grad(ty: u32, index: u32, info_offset: u32) {
     cmd_offset += 3u;
 }
 
+// Tag the following draw with its path index for the object ID output,
+// if enabled.
+fn write_draw_id(path_ix: u32) {
+    if config.object_id_threshold >= 0.0 {
+        alloc_cmd(2u);
+        ptcl[cmd_offset] = CMD_DRAW_ID;
+        ptcl[cmd_offset + 1u] = path_ix;
+        cmd_offset += 2u;
+    }
+}
+
 fn write_begin_clip() {
     alloc_cmd(1u);
     ptcl[cmd_offset] = CMD_BEGIN_CLIP;
@@ -131,7 +139,7 @@ fn write_end_clip(end_clip: CmdEndClip) {
     cmd_offset += 3u;            
 }
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(local_invocation_id) local_id: vec3<u32>,
     @builtin(workgroup_id) wg_id: vec3<u32>,
@@ -323,6 +331,7 @@ fn main(
                     // DRAWTAG_FILL_COLOR
                     case 0x44u: {
                         let linewidth = bitcast<f32>(info_bin_data[di]);
+                        write_draw_id(dm.path_ix);
                         write_path(tile, linewidth);
                         let rgba_color = scene[dd];
                         write_color(CmdColor(rgba_color));
@@ -330,6 +339,7 @@ fn main(
                     // DRAWTAG_FILL_LIN_GRADIENT
                     case 0x114u: {
                         let linewidth = bitcast<f32>(info_bin_data[di]);
+                        write_draw_id(dm.path_ix);
                         write_path(tile, linewidth);
                         let index = scene[dd];
                         let info_offset = di + 1u;
@@ -338,6 +348,7 @@ fn main(
                     // DRAWTAG_FILL_RAD_GRADIENT
                     case 0x2dcu: {
                         let linewidth = bitcast<f32>(info_bin_data[di]);
+                        write_draw_id(dm.path_ix);
                         write_path(tile, linewidth);
                         let index = scene[dd];
                         let info_offset = di + 1u;
@@ -28,8 +28,6 @@ var<storage, read_write> info: array<u32>;
 @group(0) @binding(6)
 var<storage, read_write> clip_inp: array<ClipInp>;
 
-let WG_SIZE = 256u;
-
 // Possibly dedup?
 struct Transform {
     matrx: vec4<f32>,
@@ -51,7 +49,7 @@ fn read_transform(transform_base: u32, ix: u32) -> Transform {
 
 var<workgroup> sh_scratch: array<DrawMonoid, WG_SIZE>;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
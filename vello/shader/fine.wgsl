@@ -41,6 +41,16 @@ var gradients: texture_2d<f32>;
 @group(0) @binding(6)
 var<storage> info: array<u32>;
 
+// Path index of the topmost draw object covering each pixel, or 0xffffffff
+// for none. Only written when config.object_id_threshold is non-negative.
+@group(0) @binding(7)
+var<storage, read_write> object_ids: array<u32>;
+
+#import indirect
+
+@group(0) @binding(8)
+var<storage> indirect: IndirectArgs;
+
 fn read_fill(cmd_ix: u32) -> CmdFill {
     let tile = ptcl[cmd_ix + 1u];
     let backdrop = i32(ptcl[cmd_ix + 2u]);
@@ -82,6 +92,11 @@ fn read_rad_grad(cmd_ix: u32) -> CmdRadGrad {
     return CmdRadGrad(index, matrx, xlat, c1, ra, roff);
 }
 
+fn read_draw_id(cmd_ix: u32) -> CmdDrawId {
+    let path_ix = ptcl[cmd_ix + 1u];
+    return CmdDrawId(path_ix);
+}
+
 fn read_end_clip(cmd_ix: u32) -> CmdEndClip {
     let blend = ptcl[cmd_ix + 1u];
     let alpha = bitcast<f32>(ptcl[cmd_ix + 2u]);
@@ -169,7 +184,7 @@ fn stroke_path(seg: u32, half_width: f32, xy: vec2<f32>) -> array<f32, PIXELS_PE
     return df;
 }
 
-@compute @workgroup_size(4, 16)
+@compute @workgroup_size(WG_SIZE_X, WG_SIZE_Y)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
@@ -183,9 +198,22 @@ fn main(
     var clip_depth = 0u;
     var area: array<f32, PIXELS_PER_THREAD>;
     var cmd_ix = tile_ix * PTCL_INITIAL_ALLOC;
+    // Object ids, tracked alongside rgba when the output is enabled
+    let threshold = config.object_id_threshold;
+    var draw_id = 0xffffffffu;
+    var ids: array<u32, PIXELS_PER_THREAD>;
+    var id_stack: array<array<u32, BLEND_STACK_SPLIT>, PIXELS_PER_THREAD>;
+    for (var i = 0u; i < PIXELS_PER_THREAD; i += 1u) {
+        ids[i] = 0xffffffffu;
+    }
+
+    // Coarse only runs on the bins touched by draw objects, and doesn't write
+    // the ptcl of the others.
+    let bin = wg_id.xy / vec2(N_TILE_X, N_TILE_Y);
+    let is_empty = bin.x >= indirect.coarse_x || bin.y >= indirect.coarse_y;
 
     // main interpretation loop
-    while true {
+    while !is_empty {
         let tag = ptcl[cmd_ix];
         if tag == CMD_END {
             break;
@@ -218,6 +246,9 @@ fn main(
                 for (var i = 0u; i < PIXELS_PER_THREAD; i += 1u) {
                     let fg_i = fg * area[i];
                     rgba[i] = rgba[i] * (1.0 - fg_i.a) + fg_i;
+                    if fg_i.a > threshold {
+                        ids[i] = draw_id;
+                    }
                 }
                 cmd_ix += 2u;
             }
@@ -231,6 +262,9 @@ fn main(
                     let fg_rgba = textureLoad(gradients, vec2(x, i32(lin.index)), 0);
                     let fg_i = fg_rgba * area[i];
                     rgba[i] = rgba[i] * (1.0 - fg_i.a) + fg_i;
+                    if fg_i.a > threshold {
+                        ids[i] = draw_id;
+                    }
                 }
                 cmd_ix += 3u;
             }
@@ -248,6 +282,9 @@ fn main(
                     let fg_rgba = textureLoad(gradients, vec2(x, i32(rad.index)), 0);
                     let fg_i = fg_rgba * area[i];
                     rgba[i] = rgba[i] * (1.0 - fg_i.a) + fg_i;
+                    if fg_i.a > threshold {
+                        ids[i] = draw_id;
+                    }
                 }
                 cmd_ix += 3u;
             }
@@ -256,6 +293,7 @@ fn main(
                 if clip_depth < BLEND_STACK_SPLIT {
                     for (var i = 0u; i < PIXELS_PER_THREAD; i += 1u) {
                         blend_stack[clip_depth][i] = pack4x8unorm(rgba[i]);
+                        id_stack[clip_depth][i] = ids[i];
                         rgba[i] = vec4(0.0);
                     }
                 } else {
@@ -270,13 +308,20 @@ fn main(
                 clip_depth -= 1u;
                 for (var i = 0u; i < PIXELS_PER_THREAD; i += 1u) {
                     var bg_rgba: u32;
+                    var bg_id = 0xffffffffu;
                     if clip_depth < BLEND_STACK_SPLIT {
                         bg_rgba = blend_stack[clip_depth][i];
+                        bg_id = id_stack[clip_depth][i];
                     } else {
                         // load from memory
                     }
                     let bg = unpack4x8unorm(bg_rgba);
                     let fg = rgba[i] * area[i] * end_clip.alpha;
+                    // Draws inside the layer only count where the layer
+                    // itself is visible
+                    if area[i] * end_clip.alpha <= threshold {
+                        ids[i] = bg_id;
+                    }
                     rgba[i] = blend_mix_compose(bg, fg, end_clip.blend);
                 }
                 cmd_ix += 3u;
@@ -285,6 +330,11 @@ fn main(
             case 11u: {
                 cmd_ix = ptcl[cmd_ix + 1u];
             }
+            // CMD_DRAW_ID
+            case 12u: {
+                draw_id = read_draw_id(cmd_ix).path_ix;
+                cmd_ix += 2u;
+            }
             default: {}
         }
     }
@@ -297,6 +347,9 @@ fn main(
             let a_inv = 1.0 / max(fg.a, 1e-6);
             let rgba_sep = vec4(fg.rgb * a_inv, fg.a);            
             textureStore(output, vec2<i32>(coords), rgba_sep);
+            if threshold >= 0.0 {
+                object_ids[coords.y * config.target_width + coords.x] = ids[i];
+            }
         }
     } 
 #else
@@ -109,7 +109,7 @@ fn alloc_segment() -> u32 {
 
 let MAX_QUADS = 16u;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
@@ -3,7 +3,6 @@
 // Path coarse rasterization for the full implementation.
 
 #import config
-#import pathtag
 #import tile
 #import segment
 #import cubic
@@ -13,15 +12,9 @@
 var<uniform> config: Config;
 
 @group(0) @binding(1)
-var<storage> scene: array<u32>;
-
-@group(0) @binding(2)
-var<storage> tag_monoids: array<TagMonoid>;
-
-@group(0) @binding(3)
 var<storage> cubics: array<Cubic>;
 
-@group(0) @binding(4)
+@group(0) @binding(2)
 var<storage> paths: array<Path>;
 
 // We don't get this from import as it's the atomic version
@@ -30,13 +23,13 @@ struct AtomicTile {
     segments: atomic<u32>,
 }
 
-@group(0) @binding(5)
+@group(0) @binding(3)
 var<storage, read_write> bump: BumpAllocators;
 
-@group(0) @binding(6)
+@group(0) @binding(4)
 var<storage, read_write> tiles: array<AtomicTile>;
 
-@group(0) @binding(7)
+@group(0) @binding(5)
 var<storage, read_write> segments: array<Segment>;
 
 struct SubdivResult {
@@ -98,16 +91,12 @@ fn alloc_segment() -> u32 {
 
 let MAX_QUADS = 16u;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
 ) {
-    let ix = global_id.x;
-    let tag_word = scene[config.pathtag_base + (ix >> 2u)];
-    let shift = (ix & 3u) * 8u;
-    var tag_byte = (tag_word >> shift) & 0xffu;
-
-    if (tag_byte & PATH_TAG_SEG_TYPE) != 0u {
+    // The dispatch is rounded up to whole workgroups.
+    if global_id.x < atomicLoad(&bump.cubics) {
         // Discussion question: it might actually be cheaper to do the path segment
         // decoding & transform again rather than store the result in a buffer;
         // classic memory vs ALU tradeoff.
@@ -14,6 +14,7 @@
 #import config
 #import pathtag
 #import cubic
+#import bump
 
 @group(0) @binding(0)
 var<uniform> config: Config;
@@ -40,6 +41,9 @@ var<storage, read_write> path_bboxes: array<AtomicPathBbox>;
 @group(0) @binding(4)
 var<storage, read_write> cubics: array<Cubic>;
 
+@group(0) @binding(5)
+var<storage, read_write> bump: BumpAllocators;
+
 // Monoid is yagni, for future optimization
 
 // struct BboxMonoid {
@@ -115,7 +119,7 @@ fn round_up(x: f32) -> i32 {
     return i32(ceil(x));
 }
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
@@ -137,26 +141,28 @@ fn main(
     // Decode path data
     let seg_type = tag_byte & PATH_TAG_SEG_TYPE;
     if seg_type != 0u {
+        // The delta of the transform is nonzero for instances sharing path data
+        let pathseg_offset = tm.pathseg_offset + scene[config.pathseg_delta_base + tm.trans_ix];
         var p0: vec2<f32>;
         var p1: vec2<f32>;
         var p2: vec2<f32>;
         var p3: vec2<f32>;
         if (tag_byte & PATH_TAG_F32) != 0u {
-            p0 = read_f32_point(tm.pathseg_offset);
-            p1 = read_f32_point(tm.pathseg_offset + 2u);
+            p0 = read_f32_point(pathseg_offset);
+            p1 = read_f32_point(pathseg_offset + 2u);
             if seg_type >= PATH_TAG_QUADTO {
-                p2 = read_f32_point(tm.pathseg_offset + 4u);
+                p2 = read_f32_point(pathseg_offset + 4u);
                 if seg_type == PATH_TAG_CUBICTO {
-                    p3 = read_f32_point(tm.pathseg_offset + 6u);
+                    p3 = read_f32_point(pathseg_offset + 6u);
                 }
             }
         } else {
-            p0 = read_i16_point(tm.pathseg_offset);
-            p1 = read_i16_point(tm.pathseg_offset + 1u);
+            p0 = read_i16_point(pathseg_offset);
+            p1 = read_i16_point(pathseg_offset + 1u);
             if seg_type >= PATH_TAG_QUADTO {
-                p2 = read_i16_point(tm.pathseg_offset + 2u);
+                p2 = read_i16_point(pathseg_offset + 2u);
                 if seg_type == PATH_TAG_CUBICTO {
-                    p3 = read_i16_point(tm.pathseg_offset + 3u);
+                    p3 = read_i16_point(pathseg_offset + 3u);
                 }
             }
         }
@@ -190,7 +196,9 @@ fn main(
             bbox += vec4(-stroke, stroke);
         }
         let flags = u32(linewidth >= 0.0);
-        cubics[global_id.x] = Cubic(p0, p1, p2, p3, stroke, tm.path_ix, flags);
+        // Cubics are packed so that path_coarse only runs on segments.
+        let cubic_ix = atomicAdd(&bump.cubics, 1u);
+        cubics[cubic_ix] = Cubic(p0, p1, p2, p3, stroke, tm.path_ix, flags);
         // Update bounding box using atomics only. Computing a monoid is a
         // potential future optimization.
         if bbox.z > bbox.x || bbox.w > bbox.y {
@@ -15,14 +15,11 @@ var<storage> reduced: array<TagMonoid>;
 @group(0) @binding(3)
 var<storage, read_write> tag_monoids: array<TagMonoid>;
 
-let LG_WG_SIZE = 8u;
-let WG_SIZE = 256u;
-
 var<workgroup> sh_parent: array<TagMonoid, WG_SIZE>;
 // These could be combined?
 var<workgroup> sh_monoid: array<TagMonoid, WG_SIZE>;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
@@ -33,7 +30,7 @@ fn main(
         agg = reduced[local_id.x];
     }
     sh_parent[local_id.x] = agg;
-    for (var i = 0u; i < LG_WG_SIZE; i += 1u) {
+    for (var i = 0u; i < firstTrailingBit(WG_SIZE); i += 1u) {
         workgroupBarrier();
         if local_id.x + (1u << i) < WG_SIZE {
             let other = sh_parent[local_id.x + (1u << i)];
@@ -47,7 +44,7 @@ fn main(
     let tag_word = scene[config.pathtag_base + ix];
     agg = reduce_tag(tag_word);
     sh_monoid[local_id.x] = agg;
-    for (var i = 0u; i < LG_WG_SIZE; i += 1u) {
+    for (var i = 0u; i < firstTrailingBit(WG_SIZE); i += 1u) {
         workgroupBarrier();
         if local_id.x >= 1u << i {
             let other = sh_monoid[local_id.x - (1u << i)];
@@ -6,4 +6,6 @@ struct BumpAllocators {
     ptcl: atomic<u32>,
     tile: atomic<u32>,
     segments: atomic<u32>,
+    // Number of cubics written by pathseg.
+    cubics: atomic<u32>,
 }
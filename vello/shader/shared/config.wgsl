@@ -25,14 +25,23 @@ struct Config {
 
     transform_base: u32,
     linewidth_base: u32,
+    // Per transform offset added to the path segment offsets, for instances
+    // sharing path data
+    pathseg_delta_base: u32,
+
+    // Coverage threshold for the object ID output of fine. A draw object
+    // is recorded for a pixel when its alpha there exceeds the threshold.
+    // Negative when the output is disabled.
+    object_id_threshold: f32,
 }
 
-// Geometry of tiles and bins
-
-let TILE_WIDTH = 16u;
-let TILE_HEIGHT = 16u;
-// Number of tiles per bin
-let N_TILE_X = 16u;
-let N_TILE_Y = 16u;
-//let N_TILE = N_TILE_X * N_TILE_Y;
-let N_TILE = 256u;
+// Geometry of tiles and bins, and workgroup sizes, are supplied by the
+// renderer as preprocessor defines (see `RendererOptions`):
+//
+// TILE_WIDTH, TILE_HEIGHT: size of a tile in pixels
+// N_TILE_X, N_TILE_Y: number of tiles per bin
+// N_TILE: N_TILE_X * N_TILE_Y
+// N_SLICE: N_TILE / 32, the number of words in a bitmap with a bit per tile
+// N_SUBSLICE: N_SLICE / 2
+// WG_SIZE: workgroup size of the shader (WG_SIZE_X and WG_SIZE_Y for fine)
+// PATH_COARSE_WG: workgroup size of path_coarse, for sizing its dispatch
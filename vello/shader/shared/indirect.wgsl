@@ -0,0 +1,16 @@
+// SPDX-License-Identifier: Apache-2.0 OR MIT OR Unlicense
+
+// Workgroup counts of the dispatches that are sized on the GPU. The renderer
+// uploads (0, 1, 1, 0, 0, 1) and dispatches path_coarse and coarse with
+// dispatch_indirect at offsets 0 and 12.
+struct IndirectArgs {
+    // Enough workgroups to cover the cubics, written by tile_alloc.
+    path_coarse_x: u32,
+    path_coarse_y: u32,
+    path_coarse_z: u32,
+    // Extent in bins of the draw objects, maximized by binning. Bins outside
+    // of it are empty and are skipped by coarse and fine.
+    coarse_x: u32,
+    coarse_y: u32,
+    coarse_z: u32,
+}
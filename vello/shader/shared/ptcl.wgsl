@@ -19,6 +19,7 @@ let CMD_RAD_GRAD = 7u;
 let CMD_BEGIN_CLIP = 9u;
 let CMD_END_CLIP = 10u;
 let CMD_JUMP = 11u;
+let CMD_DRAW_ID = 12u;
 
 // The individual PTCL structs are written here, but read/write is by
 // hand in the relevant shaders
@@ -37,6 +38,10 @@ struct CmdJump {
     new_ix: u32,
 }
 
+struct CmdDrawId {
+    path_ix: u32,
+}
+
 struct CmdColor {
     rgba_color: u32,
 }
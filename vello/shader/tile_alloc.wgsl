@@ -6,6 +6,7 @@
 #import bump
 #import drawtag
 #import tile
+#import indirect
 
 @group(0) @binding(0)
 var<uniform> config: Config;
@@ -25,12 +26,13 @@ var<storage, read_write> paths: array<Path>;
 @group(0) @binding(5)
 var<storage, read_write> tiles: array<Tile>;
 
-let WG_SIZE = 256u;
+@group(0) @binding(6)
+var<storage, read_write> indirect: IndirectArgs;
 
 var<workgroup> sh_tile_count: array<u32, WG_SIZE>;
 var<workgroup> sh_tile_offset: u32;
 
-@compute @workgroup_size(256)
+@compute @workgroup_size(WG_SIZE)
 fn main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>,
@@ -41,6 +43,10 @@ fn main(
     let SY = 1.0 / f32(TILE_HEIGHT);
 
     let drawobj_ix = global_id.x;
+    if drawobj_ix == 0u {
+        // pathseg has finished, so the number of cubics is known.
+        indirect.path_coarse_x = (atomicLoad(&bump.cubics) + PATH_COARSE_WG - 1u) / PATH_COARSE_WG;
+    }
     var drawtag = DRAWTAG_NOP;
     if drawobj_ix < config.n_drawobj {
         drawtag = scene[config.drawtag_base + drawobj_ix];
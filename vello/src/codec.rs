@@ -0,0 +1,161 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Little endian reader and writer shared by the binary formats: traces, shader
+//! bundles and serialized scenes.
+
+use crate::{Error, RendererOptions};
+
+#[derive(Default)]
+pub struct Writer {
+    pub data: Vec<u8>,
+}
+
+impl Writer {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f32(&mut self, value: f32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes an element count.
+    pub fn len(&mut self, len: usize) {
+        self.u64(len as u64);
+    }
+
+    /// Writes a byte string prefixed with its length.
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn options(&mut self, options: &RendererOptions) {
+        for value in options_to_array(options) {
+            self.u32(value);
+        }
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    /// Creates the error of the format being read.
+    invalid: fn(&str) -> Error,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8], invalid: fn(&str) -> Error) -> Self {
+        Self { data, invalid }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.data.len() {
+            return Err((self.invalid)("unexpected end of data"));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, Error> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| (self.invalid)("value out of range"))
+    }
+
+    /// Reads an element count, checking that the remaining data can hold that many
+    /// elements of at least `min_size` bytes before anything is allocated.
+    pub fn len(&mut self, min_size: usize) -> Result<usize, Error> {
+        let len = self.usize()?;
+        match len.checked_mul(min_size) {
+            Some(size) if size <= self.data.len() => Ok(len),
+            _ => Err((self.invalid)("unexpected end of data")),
+        }
+    }
+
+    /// Reads a byte string prefixed with its length.
+    pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.len(1)?;
+        self.take(len)
+    }
+
+    pub fn string(&mut self) -> Result<String, Error> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| (self.invalid)("invalid UTF-8"))
+    }
+
+    pub fn options(&mut self) -> Result<RendererOptions, Error> {
+        let mut values = [0; 8];
+        for value in &mut values {
+            *value = self.u32()?;
+        }
+        Ok(options_from_array(values))
+    }
+}
+
+/// Returns the fields of the options in the order they are serialized.
+pub fn options_to_array(options: &RendererOptions) -> [u32; 8] {
+    [
+        options.tile_width,
+        options.tile_height,
+        options.n_tile_x,
+        options.n_tile_y,
+        options.pathtag_reduce_wg,
+        options.path_bbox_wg,
+        options.path_coarse_wg,
+        options.clip_reduce_wg,
+    ]
+}
+
+fn options_from_array(values: [u32; 8]) -> RendererOptions {
+    RendererOptions {
+        tile_width: values[0],
+        tile_height: values[1],
+        n_tile_x: values[2],
+        n_tile_y: values[3],
+        pathtag_reduce_wg: values[4],
+        path_bbox_wg: values[5],
+        path_coarse_wg: values[6],
+        clip_reduce_wg: values[7],
+    }
+}
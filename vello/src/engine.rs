@@ -16,20 +16,24 @@
 
 use std::{
     borrow::Cow,
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
+    future::Future,
     num::{NonZeroU32, NonZeroU64},
     sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
 };
 
 use futures_intrusive::channel::shared::GenericOneshotReceiver;
 use parking_lot::RawMutex;
 use wgpu::{
     util::DeviceExt, BindGroup, BindGroupLayout, Buffer, BufferAsyncError, BufferSlice, BufferView,
-    ComputePipeline, Device, Queue, Texture, TextureAspect, TextureFormat, TextureUsages,
-    TextureView, TextureViewDimension,
+    CommandEncoder, ComputePipeline, Device, Queue, Texture, TextureAspect, TextureFormat,
+    TextureUsages, TextureView, TextureViewDimension,
 };
 
-pub type Error = Box<dyn std::error::Error>;
+use crate::Error;
+
+pub mod trace;
 
 #[derive(Clone, Copy)]
 pub struct ShaderId(usize);
@@ -40,10 +44,34 @@ pub struct Id(NonZeroU64);
 static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 pub struct Engine {
-    shaders: Vec<Shader>,
+    /// Shaders indexed by [ShaderId].
+    shaders: Vec<ShaderSlot>,
+}
+
+enum ShaderSlot {
+    Compiled(Shader),
+    /// A shader added with [Engine::add_shader_deferred] that is not yet compiled.
+    Pending(PendingShader),
+}
+
+impl ShaderSlot {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Compiled(shader) => shader.label,
+            Self::Pending(shader) => shader.label,
+        }
+    }
+}
+
+struct PendingShader {
+    label: &'static str,
+    wgsl: Cow<'static, str>,
+    layout: Vec<BindType>,
 }
 
 struct Shader {
+    label: &'static str,
+    layout: Vec<BindType>,
     pipeline: ComputePipeline,
     bind_group_layout: BindGroupLayout,
 }
@@ -59,7 +87,7 @@ pub struct BufProxy {
     id: Id,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ImageFormat {
     Rgba8,
     Bgra8,
@@ -92,6 +120,11 @@ pub enum Command {
     // Maybe use tricks to make more ergonomic?
     // Alternative: provide bufs & images as separate sequences, like piet-gpu.
     Dispatch(ShaderId, (u32, u32, u32), Vec<ResourceProxy>),
+    /// Dispatch with workgroup counts read from a buffer at the given byte offset.
+    ///
+    /// The buffer contains three consecutive u32 values (x, y, z), usually written
+    /// by a previous dispatch.
+    DispatchIndirect(ShaderId, BufProxy, u64, Vec<ResourceProxy>),
     Download(BufProxy),
     Clear(BufProxy, u64, Option<NonZeroU64>),
 }
@@ -117,10 +150,19 @@ pub enum BindType {
     // TODO: Uniform, Sampler, maybe others
 }
 
+/// Resources created while recording commands into an external encoder.
+///
+/// These must outlive the submission of the recorded commands.
+pub struct TransientResources {
+    _bind_map: BindMap,
+}
+
 #[derive(Default)]
 struct BindMap {
     buf_map: HashMap<Id, Buffer>,
     image_map: HashMap<Id, (Texture, TextureView)>,
+    /// Buffers that hold the workgroup counts of indirect dispatches.
+    indirect_bufs: HashSet<Id>,
 }
 
 impl Engine {
@@ -130,19 +172,171 @@ impl Engine {
 
     /// Add a shader.
     ///
-    /// This function is somewhat limited, it doesn't apply a label, only allows one bind group,
-    /// doesn't support push constants, and entry point is hardcoded as "main".
+    /// This function is somewhat limited, it only allows one bind group, doesn't support push
+    /// constants, and entry point is hardcoded as "main".
     ///
     /// Maybe should do template instantiation here? But shader compilation pipeline feels maybe
     /// a bit separate.
     pub fn add_shader(
         &mut self,
         device: &Device,
+        label: &'static str,
         wgsl: Cow<'static, str>,
         layout: &[BindType],
     ) -> Result<ShaderId, Error> {
+        let shader = Self::create_shader(device, label, wgsl, layout)?;
+        let id = self.shaders.len();
+        self.shaders.push(ShaderSlot::Compiled(shader));
+        Ok(ShaderId(id))
+    }
+
+    /// Replace the source of a shader, keeping its label and layout.
+    ///
+    /// If compilation fails, the previous pipeline is kept and the error is returned.
+    #[cfg(feature = "hot_reload")]
+    pub fn replace_shader(
+        &mut self,
+        device: &Device,
+        id: ShaderId,
+        wgsl: Cow<'static, str>,
+    ) -> Result<(), Error> {
+        let old = self.shader(id)?;
+        let shader = Self::create_shader(device, old.label, wgsl, &old.layout)?;
+        self.shaders[id.0] = ShaderSlot::Compiled(shader);
+        Ok(())
+    }
+
+    /// Add a shader without compiling it.
+    ///
+    /// The id is reserved immediately, but it can only be used after the next call to
+    /// [Engine::compile_pending].
+    pub fn add_shader_deferred(
+        &mut self,
+        label: &'static str,
+        wgsl: Cow<'static, str>,
+        layout: &[BindType],
+    ) -> ShaderId {
+        let id = self.shaders.len();
+        self.shaders.push(ShaderSlot::Pending(PendingShader {
+            label,
+            wgsl,
+            layout: layout.to_vec(),
+        }));
+        ShaderId(id)
+    }
+
+    /// Compile the shaders added with [Engine::add_shader_deferred].
+    ///
+    /// On native targets, the pipelines are created concurrently on scoped threads.
+    /// Unlike [Engine::add_shader], this waits for the device to report validation
+    /// errors, which is required on the web. If compilation fails, the shaders remain
+    /// pending, so that their ids stay valid once they are compiled.
+    pub async fn compile_pending(&mut self, device: &Device) -> Result<(), Error> {
+        let pending = self
+            .shaders
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, slot)| match slot {
+                ShaderSlot::Pending(shader) => Some((ix, shader)),
+                ShaderSlot::Compiled(_) => None,
+            })
+            .collect::<Vec<_>>();
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        #[cfg(not(target_arch = "wasm32"))]
+        let shaders = std::thread::scope(|scope| {
+            let handles = pending
+                .iter()
+                .map(|(_, shader)| {
+                    scope.spawn(|| {
+                        Self::build_shader(
+                            device,
+                            shader.label,
+                            shader.wgsl.clone(),
+                            &shader.layout,
+                        )
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect::<Vec<_>>()
+        });
+        #[cfg(target_arch = "wasm32")]
+        let shaders = pending
+            .iter()
+            .map(|(_, shader)| {
+                Self::build_shader(device, shader.label, shader.wgsl.clone(), &shader.layout)
+            })
+            .collect::<Vec<_>>();
+        if let Some(error) = device.pop_error_scope().await {
+            // The error scope doesn't tell which shader failed, so compile them again one
+            // at a time to find out.
+            for (_, shader) in pending {
+                device.push_error_scope(wgpu::ErrorFilter::Validation);
+                Self::build_shader(device, shader.label, shader.wgsl.clone(), &shader.layout);
+                if let Some(error) = device.pop_error_scope().await {
+                    return Err(shader_error(shader.label, error));
+                }
+            }
+            return Err(error.into());
+        }
+        let ids = pending.into_iter().map(|(ix, _)| ix).collect::<Vec<_>>();
+        for (ix, shader) in ids.into_iter().zip(shaders) {
+            self.shaders[ix] = ShaderSlot::Compiled(shader);
+        }
+        Ok(())
+    }
+
+    /// Returns the label of a shader, which is known before it is compiled.
+    fn label(&self, id: ShaderId) -> Result<&'static str, Error> {
+        self.shaders
+            .get(id.0)
+            .map(ShaderSlot::label)
+            .ok_or_else(|| Error::InvalidShader(format!("shader id {} is out of range", id.0)))
+    }
+
+    /// Returns the compiled shader for an id.
+    fn shader(&self, id: ShaderId) -> Result<&Shader, Error> {
+        match self.shaders.get(id.0) {
+            Some(ShaderSlot::Compiled(shader)) => Ok(shader),
+            Some(ShaderSlot::Pending(shader)) => Err(Error::InvalidShader(format!(
+                "shader `{}` has not been compiled",
+                shader.label
+            ))),
+            None => Err(Error::InvalidShader(format!(
+                "shader id {} is out of range",
+                id.0
+            ))),
+        }
+    }
+
+    fn create_shader(
+        device: &Device,
+        label: &'static str,
+        wgsl: Cow<'static, str>,
+        layout: &[BindType],
+    ) -> Result<Shader, Error> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let shader = Self::build_shader(device, label, wgsl, layout);
+        match poll_now(device.pop_error_scope()).flatten() {
+            Some(error) => Err(shader_error(label, error)),
+            None => Ok(shader),
+        }
+    }
+
+    fn build_shader(
+        device: &Device,
+        label: &'static str,
+        wgsl: Cow<'static, str>,
+        layout: &[BindType],
+    ) -> Shader {
         let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: None,
+            label: Some(label),
             source: wgpu::ShaderSource::Wgsl(wgsl),
         });
         let entries = layout
@@ -191,32 +385,30 @@ impl Engine {
                         count: None,
                     }
                 }
-                _ => todo!(),
             })
             .collect::<Vec<_>>();
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: None,
+            label: Some(label),
             entries: &entries,
         });
         let compute_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
+                label: Some(label),
                 bind_group_layouts: &[&bind_group_layout],
                 push_constant_ranges: &[],
             });
         let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: None,
+            label: Some(label),
             layout: Some(&compute_pipeline_layout),
             module: &shader_module,
             entry_point: "main",
         });
-        let shader = Shader {
+        Shader {
+            label,
+            layout: layout.to_vec(),
             pipeline,
             bind_group_layout,
-        };
-        let id = self.shaders.len();
-        self.shaders.push(shader);
-        Ok(ShaderId(id))
+        }
     }
 
     pub fn run_recording(
@@ -226,26 +418,47 @@ impl Engine {
         recording: &Recording,
         external_resources: &[ExternalResource],
     ) -> Result<Downloads, Error> {
+        let mut encoder = device.create_command_encoder(&Default::default());
+        let (downloads, _resources) =
+            self.record_into(device, &mut encoder, recording, external_resources)?;
+        queue.submit(Some(encoder.finish()));
+        Ok(downloads)
+    }
+
+    /// Records the commands into an encoder owned by the caller.
+    ///
+    /// Nothing is submitted to the queue. The returned resources back the recorded
+    /// commands and must be kept alive until the command buffer built from `encoder`
+    /// has been submitted. Downloads are only available after that submission.
+    pub fn record_into(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        recording: &Recording,
+        external_resources: &[ExternalResource],
+    ) -> Result<(Downloads, TransientResources), Error> {
         let mut bind_map = BindMap::default();
         let mut downloads = Downloads::default();
+        for command in &recording.commands {
+            if let Command::DispatchIndirect(_, proxy, ..) = command {
+                bind_map.indirect_bufs.insert(proxy.id);
+            }
+        }
 
-        let mut encoder = device.create_command_encoder(&Default::default());
         for command in &recording.commands {
             match command {
                 Command::Upload(buf_proxy, bytes) => {
                     let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                         label: None,
-                        contents: &bytes,
-                        usage: wgpu::BufferUsages::STORAGE
-                            | wgpu::BufferUsages::COPY_DST
-                            | wgpu::BufferUsages::COPY_SRC,
+                        contents: bytes,
+                        usage: bind_map.buf_usages(buf_proxy.id),
                     });
                     bind_map.insert_buf(buf_proxy.id, buf);
                 }
                 Command::UploadUniform(buf_proxy, bytes) => {
                     let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                         label: None,
-                        contents: &bytes,
+                        contents: bytes,
                         usage: wgpu::BufferUsages::UNIFORM,
                     });
                     bind_map.insert_buf(buf_proxy.id, buf);
@@ -253,7 +466,7 @@ impl Engine {
                 Command::UploadImage(image_proxy, bytes) => {
                     let buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                         label: None,
-                        contents: &bytes,
+                        contents: bytes,
                         usage: wgpu::BufferUsages::COPY_SRC,
                     });
                     let texture = device.create_texture(&wgpu::TextureDescriptor {
@@ -304,20 +517,32 @@ impl Engine {
                 }
                 Command::Dispatch(shader_id, wg_size, bindings) => {
                     // println!("dispatching {:?} with {} bindings", wg_size, bindings.len());
-                    let shader = &self.shaders[shader_id.0];
-                    let bind_group = bind_map.create_bind_group(
-                        device,
-                        &shader.bind_group_layout,
-                        bindings,
-                        external_resources,
-                    )?;
+                    let shader = self.shader(*shader_id)?;
+                    let bind_group =
+                        bind_map.create_bind_group(device, shader, bindings, external_resources)?;
                     let mut cpass = encoder.begin_compute_pass(&Default::default());
                     cpass.set_pipeline(&shader.pipeline);
                     cpass.set_bind_group(0, &bind_group, &[]);
                     cpass.dispatch_workgroups(wg_size.0, wg_size.1, wg_size.2);
                 }
+                Command::DispatchIndirect(shader_id, proxy, offset, bindings) => {
+                    let shader = self.shader(*shader_id)?;
+                    let bind_group =
+                        bind_map.create_bind_group(device, shader, bindings, external_resources)?;
+                    let buf = match find_buf(external_resources, proxy) {
+                        Some(buf) => buf,
+                        None => bind_map.get_or_create(*proxy, device)?,
+                    };
+                    let mut cpass = encoder.begin_compute_pass(&Default::default());
+                    cpass.set_pipeline(&shader.pipeline);
+                    cpass.set_bind_group(0, &bind_group, &[]);
+                    cpass.dispatch_workgroups_indirect(buf, *offset);
+                }
                 Command::Download(proxy) => {
-                    let src_buf = bind_map.buf_map.get(&proxy.id).ok_or("buffer not in map")?;
+                    let src_buf = bind_map
+                        .buf_map
+                        .get(&proxy.id)
+                        .ok_or(Error::BufferNotFound)?;
                     let buf = device.create_buffer(&wgpu::BufferDescriptor {
                         label: None,
                         size: proxy.size,
@@ -333,8 +558,12 @@ impl Engine {
                 }
             }
         }
-        queue.submit(Some(encoder.finish()));
-        Ok(downloads)
+        Ok((
+            downloads,
+            TransientResources {
+                _bind_map: bind_map,
+            },
+        ))
     }
 }
 
@@ -382,6 +611,25 @@ impl Recording {
         ));
     }
 
+    /// Dispatches a shader with workgroup counts taken from `buf` at `offset`.
+    pub fn dispatch_indirect<R>(
+        &mut self,
+        shader: ShaderId,
+        buf: BufProxy,
+        offset: u64,
+        resources: R,
+    ) where
+        R: IntoIterator,
+        R::Item: Into<ResourceProxy>,
+    {
+        self.push(Command::DispatchIndirect(
+            shader,
+            buf,
+            offset,
+            resources.into_iter().map(|r| r.into()).collect(),
+        ));
+    }
+
     pub fn download(&mut self, buf: BufProxy) {
         self.push(Command::Download(buf));
     }
@@ -391,6 +639,192 @@ impl Recording {
     }
 }
 
+impl Recording {
+    /// Checks the dispatches in the recording against the bind layouts of their shaders.
+    ///
+    /// This catches mismatched bindings before they surface as wgpu validation errors at
+    /// submission time. Each resource must match the kind, access and format of its slot,
+    /// and buffers must be uploaded, cleared or written by an earlier dispatch before they
+    /// are bound read only, unless they are supplied as external resources. External
+    /// buffers may be bound to storage and uniform slots alike, and images that are not
+    /// uploaded are assumed to be external. The workgroup counts of an indirect dispatch
+    /// must likewise be produced by an earlier command.
+    pub fn validate(
+        &self,
+        engine: &Engine,
+        external_resources: &[ExternalResource],
+    ) -> Result<(), Error> {
+        let external_bufs = external_resources
+            .iter()
+            .filter_map(|resource| match resource {
+                ExternalResource::Buf(proxy, _) => Some(proxy.id),
+                ExternalResource::Image(..) => None,
+            });
+        self.validate_layouts(
+            |id| {
+                let shader = engine.shader(id)?;
+                Ok((shader.label, &shader.layout))
+            },
+            external_bufs,
+        )
+    }
+
+    /// Implements [Recording::validate], given the label and layout of each shader.
+    fn validate_layouts<'a>(
+        &self,
+        shader: impl Fn(ShaderId) -> Result<(&'static str, &'a [BindType]), Error>,
+        external_bufs: impl IntoIterator<Item = Id>,
+    ) -> Result<(), Error> {
+        // Buffers produced so far, mapped to whether they are uniform buffers. The kind
+        // of external buffers is unknown, so they are accepted for either kind of slot.
+        let mut produced: HashMap<Id, Option<bool>> = HashMap::new();
+        for id in external_bufs {
+            produced.insert(id, None);
+        }
+        for command in &self.commands {
+            match command {
+                Command::Upload(proxy, _) => {
+                    produced.insert(proxy.id, Some(false));
+                }
+                Command::UploadUniform(proxy, _) => {
+                    produced.insert(proxy.id, Some(true));
+                }
+                Command::UploadImage(..) => {}
+                Command::Dispatch(shader_id, _, bindings)
+                | Command::DispatchIndirect(shader_id, _, _, bindings) => {
+                    let (label, layout) = shader(*shader_id)?;
+                    let invalid = |binding: usize, message: String| Error::InvalidBinding {
+                        shader: label,
+                        binding: binding as u32,
+                        message,
+                    };
+                    if let Command::DispatchIndirect(_, proxy, offset, _) = command {
+                        check_indirect(label, layout, &produced, proxy, *offset, bindings)?;
+                    }
+                    if bindings.len() != layout.len() {
+                        return Err(invalid(
+                            bindings.len().min(layout.len()),
+                            format!(
+                                "expected {} bindings, found {}",
+                                layout.len(),
+                                bindings.len()
+                            ),
+                        ));
+                    }
+                    for (i, (bind_type, proxy)) in layout.iter().zip(bindings).enumerate() {
+                        match (bind_type, proxy) {
+                            (BindType::Uniform, ResourceProxy::Buf(buf)) => {
+                                if !matches!(produced.get(&buf.id), Some(Some(true) | None)) {
+                                    return Err(invalid(
+                                        i,
+                                        "uniform slot requires a buffer from upload_uniform".into(),
+                                    ));
+                                }
+                            }
+                            (BindType::Buffer | BindType::BufReadOnly, ResourceProxy::Buf(buf)) => {
+                                match produced.get(&buf.id) {
+                                    Some(Some(true)) => {
+                                        return Err(invalid(
+                                            i,
+                                            "uniform buffer bound to a storage slot".into(),
+                                        ))
+                                    }
+                                    None if *bind_type == BindType::BufReadOnly => {
+                                        return Err(invalid(
+                                            i,
+                                            "buffer is read before it is produced".into(),
+                                        ))
+                                    }
+                                    _ => {}
+                                }
+                                let aliased = bindings[..i].iter().any(|other| {
+                                    matches!(other, ResourceProxy::Buf(other) if other.id == buf.id)
+                                });
+                                if aliased {
+                                    return Err(invalid(
+                                        i,
+                                        "storage buffer is bound more than once".into(),
+                                    ));
+                                }
+                            }
+                            (
+                                BindType::Image(format) | BindType::ImageRead(format),
+                                ResourceProxy::Image(image),
+                            ) => {
+                                if image.format != *format {
+                                    return Err(invalid(
+                                        i,
+                                        format!(
+                                            "expected image format {:?}, found {:?}",
+                                            format, image.format
+                                        ),
+                                    ));
+                                }
+                            }
+                            (BindType::Image(_) | BindType::ImageRead(_), _) => {
+                                return Err(invalid(i, "expected an image, found a buffer".into()));
+                            }
+                            (_, _) => {
+                                return Err(invalid(i, "expected a buffer, found an image".into()));
+                            }
+                        }
+                    }
+                    for (bind_type, proxy) in layout.iter().zip(bindings) {
+                        if let (BindType::Buffer, ResourceProxy::Buf(buf)) = (bind_type, proxy) {
+                            produced.insert(buf.id, Some(false));
+                        }
+                    }
+                }
+                Command::Download(proxy) => {
+                    if !produced.contains_key(&proxy.id) {
+                        return Err(Error::BufferNotFound);
+                    }
+                }
+                Command::Clear(proxy, ..) => {
+                    produced.entry(proxy.id).or_insert(Some(false));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks that the workgroup counts of an indirect dispatch are produced by an earlier
+/// command and are not written by the dispatch itself.
+fn check_indirect(
+    label: &'static str,
+    layout: &[BindType],
+    produced: &HashMap<Id, Option<bool>>,
+    proxy: &BufProxy,
+    offset: u64,
+    bindings: &[ResourceProxy],
+) -> Result<(), Error> {
+    let invalid = |message: &str| Error::InvalidIndirectBuffer {
+        shader: label,
+        message: message.into(),
+    };
+    match produced.get(&proxy.id) {
+        Some(Some(false) | None) => {}
+        Some(Some(true)) => return Err(invalid("uniform buffers can't hold workgroup counts")),
+        None => {
+            return Err(invalid(
+                "workgroup counts are not written by an earlier command",
+            ))
+        }
+    }
+    if offset % 4 != 0 || offset + 12 > proxy.size {
+        return Err(invalid("workgroup counts are out of range of the buffer"));
+    }
+    let is_written = layout.iter().zip(bindings).any(|(bind_type, binding)| {
+        *bind_type == BindType::Buffer
+            && matches!(binding, ResourceProxy::Buf(buf) if buf.id == proxy.id)
+    });
+    if is_written {
+        return Err(invalid("buffer is also bound for writing by the dispatch"));
+    }
+    Ok(())
+}
+
 impl BufProxy {
     pub fn new(size: u64) -> Self {
         let id = Id::next();
@@ -420,6 +854,14 @@ impl ImageProxy {
             id,
         }
     }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
 }
 
 impl ResourceProxy {
@@ -431,16 +873,17 @@ impl ResourceProxy {
         Self::Image(ImageProxy::new(width, height, format))
     }
 
+    #[allow(unused)]
     pub fn as_buf(&self) -> Option<&BufProxy> {
         match self {
-            Self::Buf(proxy) => Some(&proxy),
+            Self::Buf(proxy) => Some(proxy),
             _ => None,
         }
     }
 
     pub fn as_image(&self) -> Option<&ImageProxy> {
         match self {
-            Self::Image(proxy) => Some(&proxy),
+            Self::Image(proxy) => Some(proxy),
             _ => None,
         }
     }
@@ -478,53 +921,22 @@ impl BindMap {
     fn create_bind_group(
         &mut self,
         device: &Device,
-        layout: &BindGroupLayout,
+        shader: &Shader,
         bindings: &[ResourceProxy],
         external_resources: &[ExternalResource],
     ) -> Result<BindGroup, Error> {
-        // These functions are ugly and linear, but the remap array should generally be
-        // small. Should find a better solution for this.
-        fn find_buf<'a>(
-            resources: &[ExternalResource<'a>],
-            proxy: &BufProxy,
-        ) -> Option<&'a Buffer> {
-            for resource in resources {
-                match resource {
-                    ExternalResource::Buf(p, buf) if p.id == proxy.id => {
-                        return Some(buf);
-                    }
-                    _ => {}
-                }
-            }
-            None
-        }
-        fn find_image<'a>(
-            resources: &[ExternalResource<'a>],
-            proxy: &ImageProxy,
-        ) -> Option<&'a TextureView> {
-            for resource in resources {
-                match resource {
-                    ExternalResource::Image(p, view) if p.id == proxy.id => {
-                        return Some(view);
-                    }
-                    _ => {}
-                }
-            }
-            None
-        }
-        for proxy in bindings {
+        for (i, proxy) in bindings.iter().enumerate() {
             match proxy {
                 ResourceProxy::Buf(proxy) => {
                     if find_buf(external_resources, proxy).is_some() {
                         continue;
                     }
+                    let usage = self.buf_usages(proxy.id);
                     if let Entry::Vacant(v) = self.buf_map.entry(proxy.id) {
                         let buf = device.create_buffer(&wgpu::BufferDescriptor {
                             label: None,
                             size: proxy.size,
-                            usage: wgpu::BufferUsages::STORAGE
-                                | wgpu::BufferUsages::COPY_DST
-                                | wgpu::BufferUsages::COPY_SRC,
+                            usage,
                             mapped_at_creation: false,
                         });
                         v.insert(buf);
@@ -534,6 +946,16 @@ impl BindMap {
                     if find_image(external_resources, proxy).is_some() {
                         continue;
                     }
+                    // Storage images can't be created on demand as they are the output
+                    // of the pipeline and must be supplied by the caller.
+                    if matches!(shader.layout.get(i), Some(BindType::Image(_)))
+                        && !self.image_map.contains_key(&proxy.id)
+                    {
+                        return Err(Error::MissingExternalResource {
+                            shader: shader.label,
+                            binding: i as u32,
+                        });
+                    }
                     if let Entry::Vacant(v) = self.image_map.entry(proxy.id) {
                         let texture = device.create_texture(&wgpu::TextureDescriptor {
                             label: None,
@@ -589,22 +1011,33 @@ impl BindMap {
             .collect::<Result<Vec<_>, Error>>()?;
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
-            layout,
+            layout: &shader.bind_group_layout,
             entries: &entries,
         });
         Ok(bind_group)
     }
 
+    /// Returns the usages of a storage buffer created for the recording.
+    fn buf_usages(&self, id: Id) -> wgpu::BufferUsages {
+        let usages = wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC;
+        if self.indirect_bufs.contains(&id) {
+            usages | wgpu::BufferUsages::INDIRECT
+        } else {
+            usages
+        }
+    }
+
     fn get_or_create(&mut self, proxy: BufProxy, device: &Device) -> Result<&Buffer, Error> {
+        let usage = self.buf_usages(proxy.id);
         match self.buf_map.entry(proxy.id) {
             Entry::Occupied(occupied) => Ok(occupied.into_mut()),
             Entry::Vacant(vacant) => {
                 let buf = device.create_buffer(&wgpu::BufferDescriptor {
                     label: None,
                     size: proxy.size,
-                    usage: wgpu::BufferUsages::STORAGE
-                        | wgpu::BufferUsages::COPY_DST
-                        | wgpu::BufferUsages::COPY_SRC,
+                    usage,
                     mapped_at_creation: false,
                 });
                 Ok(vacant.insert(buf))
@@ -613,19 +1046,42 @@ impl BindMap {
     }
 }
 
-pub struct DownloadsMapped<'a>(
-    HashMap<
-        Id,
-        (
-            BufferSlice<'a>,
-            GenericOneshotReceiver<RawMutex, Result<(), BufferAsyncError>>,
-        ),
-    >,
-);
+// These functions are ugly and linear, but the remap array should generally be
+// small. Should find a better solution for this.
+fn find_buf<'a>(resources: &[ExternalResource<'a>], proxy: &BufProxy) -> Option<&'a Buffer> {
+    for resource in resources {
+        match resource {
+            ExternalResource::Buf(p, buf) if p.id == proxy.id => {
+                return Some(buf);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn find_image<'a>(
+    resources: &[ExternalResource<'a>],
+    proxy: &ImageProxy,
+) -> Option<&'a TextureView> {
+    for resource in resources {
+        match resource {
+            ExternalResource::Image(p, view) if p.id == proxy.id => {
+                return Some(view);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+type MapReceiver = GenericOneshotReceiver<RawMutex, Result<(), BufferAsyncError>>;
+
+pub struct DownloadsMapped<'a>(HashMap<Id, (BufferSlice<'a>, MapReceiver)>);
 
 impl Downloads {
     // Discussion: should API change so we get one buffer, rather than mapping all?
-    pub fn map(&self) -> DownloadsMapped {
+    pub fn map(&self) -> DownloadsMapped<'_> {
         let mut map = HashMap::new();
         for (id, buf) in &self.buf_map {
             let buf_slice = buf.slice(..);
@@ -638,13 +1094,232 @@ impl Downloads {
 }
 
 impl<'a> DownloadsMapped<'a> {
-    pub async fn get_mapped(&self, proxy: BufProxy) -> Result<BufferView, Error> {
-        let (slice, recv) = self.0.get(&proxy.id).ok_or("buffer not in map")?;
+    pub async fn get_mapped(&self, proxy: BufProxy) -> Result<BufferView<'_>, Error> {
+        let (slice, recv) = self.0.get(&proxy.id).ok_or(Error::BufferNotFound)?;
         if let Some(recv_result) = recv.receive().await {
             recv_result?;
         } else {
-            return Err("channel was closed".into());
+            // The callback is dropped without being called when the device is lost.
+            return Err(Error::DeviceLost);
         }
         Ok(slice.get_mapped_range())
     }
 }
+
+/// Polls a future once, returning its output if it is immediately ready.
+///
+/// Error scopes are resolved synchronously on native backends. On the web the result
+/// arrives later and errors are reported through the uncaptured error handler instead.
+fn poll_now<F: Future>(future: F) -> Option<F::Output> {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    // Safety: the vtable functions do nothing and never dereference the data pointer.
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+    match Box::pin(future).as_mut().poll(&mut cx) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+
+/// Converts a validation error reported while compiling a shader.
+fn shader_error(label: &'static str, error: wgpu::Error) -> Error {
+    match error {
+        wgpu::Error::Validation { description, .. } => Error::ShaderCompilation {
+            shader: label,
+            location: None,
+            message: description,
+        },
+        e => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WGSL: &str = "@compute @workgroup_size(1)\nfn main() {}\n";
+
+    /// Creates a device, or returns `None` if no adapter is available.
+    fn device() -> Option<Device> {
+        let instance = wgpu::Instance::new(wgpu::Backends::all());
+        let adapter = poll_now(instance.request_adapter(&Default::default())).flatten()?;
+        let (device, _queue) = poll_now(adapter.request_device(&Default::default(), None))?.ok()?;
+        Some(device)
+    }
+
+    /// Validates a recording that dispatches `ShaderId(i)` with `layouts[i]`.
+    fn validate(
+        recording: &Recording,
+        layouts: &[&[BindType]],
+        external: &[BufProxy],
+    ) -> Result<(), Error> {
+        recording.validate_layouts(
+            |id| Ok(("test", layouts[id.0])),
+            external.iter().map(|buf| buf.id),
+        )
+    }
+
+    fn binding_error(result: Result<(), Error>) -> (u32, String) {
+        match result {
+            Err(Error::InvalidBinding {
+                binding, message, ..
+            }) => (binding, message),
+            other => panic!("expected a binding error, found {other:?}"),
+        }
+    }
+
+    #[test]
+    fn validate_wrong_kind() {
+        let mut recording = Recording::default();
+        let storage = recording.upload(vec![0; 16]);
+        recording.dispatch(ShaderId(0), (1, 1, 1), [storage]);
+        assert_eq!(
+            binding_error(validate(&recording, &[&[BindType::Uniform]], &[])),
+            (
+                0,
+                "uniform slot requires a buffer from upload_uniform".into()
+            )
+        );
+
+        let mut recording = Recording::default();
+        let uniform = recording.upload_uniform(vec![0; 16]);
+        recording.dispatch(ShaderId(0), (1, 1, 1), [uniform]);
+        assert_eq!(
+            binding_error(validate(&recording, &[&[BindType::BufReadOnly]], &[])),
+            (0, "uniform buffer bound to a storage slot".into())
+        );
+
+        let mut recording = Recording::default();
+        let image = ImageProxy::new(4, 4, ImageFormat::Rgba8);
+        recording.dispatch(ShaderId(0), (1, 1, 1), [image]);
+        assert_eq!(
+            binding_error(validate(&recording, &[&[BindType::Buffer]], &[])),
+            (0, "expected a buffer, found an image".into())
+        );
+
+        let mut recording = Recording::default();
+        let storage = recording.upload(vec![0; 16]);
+        recording.dispatch(ShaderId(0), (1, 1, 1), [storage]);
+        let layout = [BindType::Image(ImageFormat::Rgba8)];
+        assert_eq!(
+            binding_error(validate(&recording, &[&layout], &[])),
+            (0, "expected an image, found a buffer".into())
+        );
+    }
+
+    #[test]
+    fn validate_wrong_image_format() {
+        let mut recording = Recording::default();
+        let image = ImageProxy::new(4, 4, ImageFormat::Bgra8);
+        recording.dispatch(ShaderId(0), (1, 1, 1), [image]);
+        let layout = [BindType::Image(ImageFormat::Rgba8)];
+        assert_eq!(
+            binding_error(validate(&recording, &[&layout], &[])),
+            (0, "expected image format Rgba8, found Bgra8".into())
+        );
+        let layout = [BindType::Image(ImageFormat::Bgra8)];
+        assert!(validate(&recording, &[&layout], &[]).is_ok());
+    }
+
+    #[test]
+    fn validate_read_before_write() {
+        let layouts: [&[BindType]; 2] = [&[BindType::Buffer], &[BindType::BufReadOnly]];
+        let buf = BufProxy::new(16);
+        let mut recording = Recording::default();
+        recording.dispatch(ShaderId(1), (1, 1, 1), [buf]);
+        recording.dispatch(ShaderId(0), (1, 1, 1), [buf]);
+        assert_eq!(
+            binding_error(validate(&recording, &layouts, &[])),
+            (0, "buffer is read before it is produced".into())
+        );
+
+        let mut recording = Recording::default();
+        recording.dispatch(ShaderId(0), (1, 1, 1), [buf]);
+        recording.dispatch(ShaderId(1), (1, 1, 1), [buf]);
+        assert!(validate(&recording, &layouts, &[]).is_ok());
+
+        let mut recording = Recording::default();
+        recording.clear_all(buf);
+        recording.dispatch(ShaderId(1), (1, 1, 1), [buf]);
+        assert!(validate(&recording, &layouts, &[]).is_ok());
+    }
+
+    #[test]
+    fn validate_aliasing() {
+        let mut recording = Recording::default();
+        let buf = recording.upload(vec![0; 16]);
+        recording.dispatch(ShaderId(0), (1, 1, 1), [buf, buf]);
+        let layout = [BindType::BufReadOnly, BindType::Buffer];
+        assert_eq!(
+            binding_error(validate(&recording, &[&layout], &[])),
+            (1, "storage buffer is bound more than once".into())
+        );
+    }
+
+    #[test]
+    fn validate_external_buffers() {
+        let layouts: [&[BindType]; 2] = [&[BindType::Uniform], &[BindType::BufReadOnly]];
+        let external = BufProxy::new(16);
+        let mut recording = Recording::default();
+        recording.dispatch(ShaderId(0), (1, 1, 1), [external]);
+        recording.dispatch(ShaderId(1), (1, 1, 1), [external]);
+        recording.dispatch_indirect(ShaderId(1), external, 0, [external]);
+        assert!(validate(&recording, &layouts, &[external]).is_ok());
+        assert_eq!(
+            binding_error(validate(&recording, &layouts, &[])),
+            (
+                0,
+                "uniform slot requires a buffer from upload_uniform".into()
+            )
+        );
+    }
+
+    #[test]
+    fn deferred_ids_are_reserved() {
+        let mut engine = Engine::new();
+        let first = engine.add_shader_deferred("first", WGSL.into(), &[]);
+        let second = engine.add_shader_deferred("second", WGSL.into(), &[]);
+        assert_eq!((first.0, second.0), (0, 1));
+        let mut recording = Recording::default();
+        recording.dispatch(second, (1, 1, 1), [] as [ResourceProxy; 0]);
+        assert!(matches!(
+            recording.validate(&engine, &[]),
+            Err(Error::InvalidShader(message)) if message == "shader `second` has not been compiled"
+        ));
+        assert!(matches!(
+            engine.shader(ShaderId(2)),
+            Err(Error::InvalidShader(message)) if message == "shader id 2 is out of range"
+        ));
+    }
+
+    #[test]
+    fn mixed_deferred_and_immediate() {
+        let Some(device) = device() else {
+            return;
+        };
+        let mut engine = Engine::new();
+        let deferred = engine.add_shader_deferred("deferred", WGSL.into(), &[]);
+        let immediate = engine
+            .add_shader(&device, "immediate", WGSL.into(), &[])
+            .unwrap();
+        let layout = [BindType::Buffer];
+        let last = engine.add_shader_deferred("last", WGSL.into(), &layout);
+        assert_eq!(engine.shader(immediate).unwrap().label, "immediate");
+        assert!(engine.shader(deferred).is_err());
+        poll_now(engine.compile_pending(&device))
+            .expect("compilation is synchronous on native targets")
+            .unwrap();
+        assert_eq!(engine.shader(deferred).unwrap().label, "deferred");
+        assert_eq!(engine.shader(immediate).unwrap().label, "immediate");
+        let last = engine.shader(last).unwrap();
+        assert_eq!(last.label, "last");
+        assert!(last.layout == layout);
+    }
+}
@@ -0,0 +1,438 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Binary serialization of recordings for capture and replay.
+//!
+//! A trace starts with the magic bytes `VTRC` and a version, followed by the options of the
+//! renderer, the image that receives the output, a table of the labels of the dispatched
+//! shaders and the commands.
+//! All integers are little endian. Resource ids are only meaningful within a trace: they
+//! are numbered in order of first use when encoding and replaced with fresh ids when
+//! decoding.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::ops::{Deref, DerefMut};
+
+use super::{BufProxy, Command, Engine, Id, ImageFormat, ImageProxy, Recording, ResourceProxy};
+use crate::codec::{Reader, Writer};
+use crate::{Error, RendererOptions};
+
+const MAGIC: &[u8; 4] = b"VTRC";
+const VERSION: u32 = 1;
+
+const CMD_UPLOAD: u8 = 0;
+const CMD_UPLOAD_UNIFORM: u8 = 1;
+const CMD_UPLOAD_IMAGE: u8 = 2;
+const CMD_DISPATCH: u8 = 3;
+const CMD_DISPATCH_INDIRECT: u8 = 4;
+const CMD_DOWNLOAD: u8 = 5;
+const CMD_CLEAR: u8 = 6;
+
+const RESOURCE_BUF: u8 = 0;
+const RESOURCE_IMAGE: u8 = 1;
+
+/// Serializes a recording and the image that receives its output.
+///
+/// The options are those of the renderer that produced the recording, as the dispatch
+/// sizes depend on them. Fails if the recording dispatches a shader that is unknown to
+/// the engine.
+pub fn encode(
+    recording: &Recording,
+    engine: &Engine,
+    options: &RendererOptions,
+    target: &ImageProxy,
+) -> Result<Vec<u8>, Error> {
+    let mut labels = vec![];
+    let mut shader_map = HashMap::new();
+    for command in &recording.commands {
+        if let Command::Dispatch(shader_id, ..) | Command::DispatchIndirect(shader_id, ..) = command
+        {
+            if let Entry::Vacant(entry) = shader_map.entry(shader_id.0) {
+                labels.push(engine.label(*shader_id)?);
+                entry.insert(labels.len() as u32 - 1);
+            }
+        }
+    }
+    let mut w = TraceWriter::default();
+    w.data.extend_from_slice(MAGIC);
+    w.u32(VERSION);
+    w.options(options);
+    w.image(target);
+    w.u32(labels.len() as u32);
+    for label in labels {
+        w.bytes(label.as_bytes());
+    }
+    w.u32(recording.commands.len() as u32);
+    for command in &recording.commands {
+        match command {
+            Command::Upload(proxy, bytes) => {
+                w.u8(CMD_UPLOAD);
+                w.buf(proxy);
+                w.bytes(bytes);
+            }
+            Command::UploadUniform(proxy, bytes) => {
+                w.u8(CMD_UPLOAD_UNIFORM);
+                w.buf(proxy);
+                w.bytes(bytes);
+            }
+            Command::UploadImage(proxy, bytes) => {
+                w.u8(CMD_UPLOAD_IMAGE);
+                w.image(proxy);
+                w.bytes(bytes);
+            }
+            Command::Dispatch(shader_id, wg_size, bindings) => {
+                w.u8(CMD_DISPATCH);
+                w.u32(shader_map[&shader_id.0]);
+                w.u32(wg_size.0);
+                w.u32(wg_size.1);
+                w.u32(wg_size.2);
+                w.resources(bindings);
+            }
+            Command::DispatchIndirect(shader_id, proxy, offset, bindings) => {
+                w.u8(CMD_DISPATCH_INDIRECT);
+                w.u32(shader_map[&shader_id.0]);
+                w.buf(proxy);
+                w.u64(*offset);
+                w.resources(bindings);
+            }
+            Command::Download(proxy) => {
+                w.u8(CMD_DOWNLOAD);
+                w.buf(proxy);
+            }
+            Command::Clear(proxy, offset, size) => {
+                w.u8(CMD_CLEAR);
+                w.buf(proxy);
+                w.u64(*offset);
+                w.u64(size.map(|size| size.get()).unwrap_or(0));
+            }
+        }
+    }
+    Ok(w.w.data)
+}
+
+/// Reads the renderer options and the output image from the header of a trace.
+pub fn decode_header(bytes: &[u8]) -> Result<(RendererOptions, ImageProxy), Error> {
+    let mut r = TraceReader::new(bytes)?;
+    Ok((r.options()?, r.image()?))
+}
+
+/// Deserializes a recording, resolving shaders by label against the engine.
+///
+/// Returns the recording and the image that receives its output.
+pub fn decode(bytes: &[u8], engine: &Engine) -> Result<(Recording, ImageProxy), Error> {
+    let mut r = TraceReader::new(bytes)?;
+    r.options()?;
+    let target = r.image()?;
+    let n_shaders = r.u32()?;
+    let mut shaders = Vec::with_capacity(n_shaders.min(64) as usize);
+    for _ in 0..n_shaders {
+        let label = std::str::from_utf8(r.bytes()?)
+            .map_err(|_| invalid("shader label is not valid UTF-8"))?;
+        let ix = engine
+            .shaders
+            .iter()
+            .position(|slot| slot.label() == label)
+            .ok_or_else(|| Error::InvalidTrace(format!("unknown shader `{label}`")))?;
+        shaders.push(super::ShaderId(ix));
+    }
+    let shader = |r: &mut TraceReader| {
+        let ix = r.u32()? as usize;
+        shaders
+            .get(ix)
+            .copied()
+            .ok_or_else(|| invalid("shader index out of range"))
+    };
+    let n_commands = r.u32()?;
+    let mut recording = Recording::default();
+    for _ in 0..n_commands {
+        let command = match r.u8()? {
+            CMD_UPLOAD => Command::Upload(r.buf()?, r.bytes()?.to_vec()),
+            CMD_UPLOAD_UNIFORM => Command::UploadUniform(r.buf()?, r.bytes()?.to_vec()),
+            CMD_UPLOAD_IMAGE => Command::UploadImage(r.image()?, r.bytes()?.to_vec()),
+            CMD_DISPATCH => {
+                let shader_id = shader(&mut r)?;
+                let wg_size = (r.u32()?, r.u32()?, r.u32()?);
+                Command::Dispatch(shader_id, wg_size, r.resources()?)
+            }
+            CMD_DISPATCH_INDIRECT => {
+                let shader_id = shader(&mut r)?;
+                let proxy = r.buf()?;
+                let offset = r.u64()?;
+                Command::DispatchIndirect(shader_id, proxy, offset, r.resources()?)
+            }
+            CMD_DOWNLOAD => Command::Download(r.buf()?),
+            CMD_CLEAR => {
+                let proxy = r.buf()?;
+                let offset = r.u64()?;
+                Command::Clear(proxy, offset, NonZeroU64::new(r.u64()?))
+            }
+            _ => return Err(invalid("unknown command")),
+        };
+        recording.push(command);
+    }
+    Ok((recording, target))
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidTrace(message.into())
+}
+
+fn image_format_to_u8(format: ImageFormat) -> u8 {
+    match format {
+        ImageFormat::Rgba8 => 0,
+        ImageFormat::Bgra8 => 1,
+    }
+}
+
+fn image_format_from_u8(value: u8) -> Option<ImageFormat> {
+    match value {
+        0 => Some(ImageFormat::Rgba8),
+        1 => Some(ImageFormat::Bgra8),
+        _ => None,
+    }
+}
+
+/// Writes a trace, numbering the resources in order of first use.
+///
+/// Dereferences to the underlying writer for the primitive types.
+#[derive(Default)]
+struct TraceWriter {
+    w: Writer,
+    /// Map from resource ids to their ids in the trace.
+    ids: HashMap<Id, u64>,
+}
+
+impl Deref for TraceWriter {
+    type Target = Writer;
+
+    fn deref(&self) -> &Writer {
+        &self.w
+    }
+}
+
+impl DerefMut for TraceWriter {
+    fn deref_mut(&mut self) -> &mut Writer {
+        &mut self.w
+    }
+}
+
+impl TraceWriter {
+    fn id(&mut self, id: Id) {
+        let next = self.ids.len() as u64 + 1;
+        let id = *self.ids.entry(id).or_insert(next);
+        self.u64(id);
+    }
+
+    fn buf(&mut self, proxy: &BufProxy) {
+        self.id(proxy.id);
+        self.u64(proxy.size);
+    }
+
+    fn image(&mut self, proxy: &ImageProxy) {
+        self.id(proxy.id);
+        self.u32(proxy.width);
+        self.u32(proxy.height);
+        self.u8(image_format_to_u8(proxy.format));
+    }
+
+    fn resources(&mut self, resources: &[ResourceProxy]) {
+        self.u32(resources.len() as u32);
+        for resource in resources {
+            match resource {
+                ResourceProxy::Buf(proxy) => {
+                    self.u8(RESOURCE_BUF);
+                    self.buf(proxy);
+                }
+                ResourceProxy::Image(proxy) => {
+                    self.u8(RESOURCE_IMAGE);
+                    self.image(proxy);
+                }
+            }
+        }
+    }
+}
+
+/// Reads a trace, allocating fresh ids for the resources.
+///
+/// Dereferences to the underlying reader for the primitive types.
+struct TraceReader<'a> {
+    r: Reader<'a>,
+    /// Map from ids in the trace to freshly allocated ids.
+    ids: HashMap<u64, Id>,
+}
+
+impl<'a> Deref for TraceReader<'a> {
+    type Target = Reader<'a>;
+
+    fn deref(&self) -> &Reader<'a> {
+        &self.r
+    }
+}
+
+impl<'a> DerefMut for TraceReader<'a> {
+    fn deref_mut(&mut self) -> &mut Reader<'a> {
+        &mut self.r
+    }
+}
+
+impl<'a> TraceReader<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(data, invalid);
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a vello trace"));
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(Error::InvalidTrace(format!(
+                "unsupported trace version {version}"
+            )));
+        }
+        Ok(Self {
+            r,
+            ids: HashMap::new(),
+        })
+    }
+
+    fn id(&mut self) -> Result<Id, Error> {
+        let raw = self.u64()?;
+        Ok(*self.ids.entry(raw).or_insert_with(Id::next))
+    }
+
+    fn buf(&mut self) -> Result<BufProxy, Error> {
+        let id = self.id()?;
+        let size = self.u64()?;
+        Ok(BufProxy { size, id })
+    }
+
+    fn image(&mut self) -> Result<ImageProxy, Error> {
+        let id = self.id()?;
+        let width = self.u32()?;
+        let height = self.u32()?;
+        let format = image_format_from_u8(self.u8()?).ok_or_else(|| invalid("bad image format"))?;
+        Ok(ImageProxy {
+            width,
+            height,
+            format,
+            id,
+        })
+    }
+
+    fn resources(&mut self) -> Result<Vec<ResourceProxy>, Error> {
+        let len = self.u32()?;
+        let mut resources = Vec::with_capacity(len.min(64) as usize);
+        for _ in 0..len {
+            let resource = match self.u8()? {
+                RESOURCE_BUF => ResourceProxy::Buf(self.buf()?),
+                RESOURCE_IMAGE => ResourceProxy::Image(self.image()?),
+                _ => return Err(invalid("unknown resource kind")),
+            };
+            resources.push(resource);
+        }
+        Ok(resources)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ShaderId;
+    use super::*;
+
+    /// Builds an engine with uncompiled shaders, which is enough to resolve labels, and
+    /// a recording using every command.
+    fn recording() -> (Engine, Recording, ImageProxy) {
+        let mut engine = Engine::new();
+        let first = engine.add_shader_deferred("first", "".into(), &[]);
+        let second = engine.add_shader_deferred("second", "".into(), &[]);
+        let target = ImageProxy::new(64, 32, ImageFormat::Rgba8);
+        let mut recording = Recording::default();
+        let config = recording.upload_uniform(vec![1, 2, 3, 4]);
+        let scene = recording.upload(vec![5; 12]);
+        let image = recording.upload_image(2, 2, ImageFormat::Bgra8, vec![6; 16]);
+        let indirect = BufProxy::new(12);
+        recording.clear_all(indirect);
+        recording.dispatch(
+            second,
+            (3, 2, 1),
+            [
+                ResourceProxy::Buf(config),
+                ResourceProxy::Buf(scene),
+                ResourceProxy::Buf(indirect),
+                ResourceProxy::Image(image),
+            ],
+        );
+        let output = BufProxy::new(16);
+        recording.push(Command::Clear(output, 4, NonZeroU64::new(8)));
+        recording.dispatch_indirect(first, indirect, 0, [scene, output]);
+        recording.dispatch(first, (1, 1, 1), [target]);
+        recording.download(output);
+        (engine, recording, target)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (engine, recording, target) = recording();
+        let options = RendererOptions::downlevel();
+        let bytes = encode(&recording, &engine, &options, &target).unwrap();
+        let (header_options, header_target) = decode_header(&bytes).unwrap();
+        assert_eq!(header_options, options);
+        assert_eq!(
+            (header_target.width(), header_target.height()),
+            (target.width(), target.height())
+        );
+        let (decoded, decoded_target) = decode(&bytes, &engine).unwrap();
+        assert_eq!(decoded.commands.len(), recording.commands.len());
+        let reencoded = encode(&decoded, &engine, &options, &decoded_target).unwrap();
+        assert_eq!(reencoded, bytes);
+    }
+
+    #[test]
+    fn unknown_shader() {
+        let (engine, recording, target) = recording();
+        let bytes = encode(&recording, &engine, &RendererOptions::default(), &target).unwrap();
+        let mut other = Engine::new();
+        other.add_shader_deferred("first", "".into(), &[]);
+        assert!(matches!(
+            decode(&bytes, &other),
+            Err(Error::InvalidTrace(message)) if message == "unknown shader `second`"
+        ));
+        let mut recording = Recording::default();
+        recording.dispatch(ShaderId(2), (1, 1, 1), [target]);
+        assert!(matches!(
+            encode(&recording, &engine, &RendererOptions::default(), &target),
+            Err(Error::InvalidShader(_))
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let (engine, recording, target) = recording();
+        let bytes = encode(&recording, &engine, &RendererOptions::default(), &target).unwrap();
+        for len in [0, 8, bytes.len() / 2, bytes.len() - 1] {
+            assert!(matches!(
+                decode(&bytes[..len], &engine),
+                Err(Error::InvalidTrace(_))
+            ));
+        }
+        let mut bytes = bytes;
+        bytes[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(
+            decode(&bytes, &engine),
+            Err(Error::InvalidTrace(message)) if message == "unsupported trace version 2"
+        ));
+    }
+}
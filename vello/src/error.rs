@@ -0,0 +1,224 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+use std::fmt;
+
+/// Errors that can occur while setting up or running the renderer.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// No adapter compatible with the requested options was found.
+    NoCompatibleAdapter,
+    /// The device could not be created.
+    RequestDevice(wgpu::RequestDeviceError),
+    /// The device was lost before a pending operation completed.
+    DeviceLost,
+    /// The device ran out of memory.
+    OutOfMemory,
+    /// A shader module or its compute pipeline failed validation.
+    ShaderCompilation {
+        /// Name of the shader.
+        shader: &'static str,
+        /// Location of the error in the original shader sources, if known.
+        location: Option<SourceLocation>,
+        /// Diagnostic reported by the shader preprocessor or compiler.
+        message: String,
+    },
+    /// A validation error reported by wgpu outside of shader compilation.
+    Validation(String),
+    /// The scene does not fit in one of the fixed size intermediate buffers.
+    BufferOverflow {
+        /// Name of the buffer.
+        buffer: &'static str,
+        /// Number of bytes required by the scene.
+        required: u64,
+        /// Number of bytes available.
+        capacity: u64,
+    },
+    /// The render target spans more bins than the renderer supports.
+    TargetTooLarge {
+        /// Width of the target in pixels.
+        width: u32,
+        /// Height of the target in pixels.
+        height: u32,
+    },
+    /// The renderer options are not supported by the shaders or the device.
+    InvalidOptions(String),
+    /// A shader bundle is malformed or doesn't contain a required shader.
+    InvalidBundle(String),
+    /// Serialized scene data is malformed or inconsistent.
+    InvalidScene(String),
+    /// The scene contains a layer that was pushed but never popped, or a pop
+    /// without a matching push.
+    UnbalancedLayers,
+    /// A checked scene builder recorded misuse while encoding the scene.
+    SceneDiagnostics(Vec<crate::Diagnostic>),
+    /// A shader writes to an image that was not provided as an external resource.
+    MissingExternalResource {
+        /// Name of the shader.
+        shader: &'static str,
+        /// Binding slot of the image.
+        binding: u32,
+    },
+    /// A dispatch in a recording binds a resource that doesn't match the layout
+    /// of the shader.
+    InvalidBinding {
+        /// Name of the shader.
+        shader: &'static str,
+        /// Binding slot of the resource.
+        binding: u32,
+        /// Description of the mismatch.
+        message: String,
+    },
+    /// The workgroup counts of an indirect dispatch are not produced before the
+    /// dispatch, or are out of range of their buffer.
+    InvalidIndirectBuffer {
+        /// Name of the shader.
+        shader: &'static str,
+        /// Description of the problem.
+        message: String,
+    },
+    /// A trace could not be decoded or refers to an unknown shader.
+    InvalidTrace(String),
+    /// A shader id doesn't belong to the engine, or refers to a deferred shader that
+    /// has not been compiled yet.
+    InvalidShader(String),
+    /// A buffer was not created by the recording, or was not downloaded.
+    BufferNotFound,
+    /// Mapping a buffer for reading failed.
+    BufferMap(wgpu::BufferAsyncError),
+    /// Reading or watching files on disk failed.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoCompatibleAdapter => write!(f, "no compatible adapter found"),
+            Self::RequestDevice(e) => write!(f, "failed to create device: {e}"),
+            Self::DeviceLost => write!(f, "device lost"),
+            Self::OutOfMemory => write!(f, "out of device memory"),
+            Self::ShaderCompilation {
+                shader,
+                location: Some(location),
+                message,
+            } => write!(
+                f,
+                "failed to compile shader `{shader}` at {location}: {message}"
+            ),
+            Self::ShaderCompilation {
+                shader, message, ..
+            } => write!(f, "failed to compile shader `{shader}`: {message}"),
+            Self::Validation(message) => write!(f, "validation error: {message}"),
+            Self::BufferOverflow {
+                buffer,
+                required,
+                capacity,
+            } => write!(
+                f,
+                "buffer `{buffer}` overflow: {required} bytes required, {capacity} available"
+            ),
+            Self::TargetTooLarge { width, height } => {
+                write!(f, "render target of {width}x{height} is too large")
+            }
+            Self::InvalidOptions(message) => write!(f, "invalid renderer options: {message}"),
+            Self::InvalidBundle(message) => write!(f, "invalid shader bundle: {message}"),
+            Self::InvalidScene(message) => write!(f, "invalid scene data: {message}"),
+            Self::UnbalancedLayers => write!(f, "unbalanced push/pop of layers"),
+            Self::SceneDiagnostics(diagnostics) => {
+                write!(f, "scene builder reported {} problems", diagnostics.len())?;
+                if let Some(first) = diagnostics.first() {
+                    write!(f, ", first at {first}")?;
+                }
+                Ok(())
+            }
+            Self::MissingExternalResource { shader, binding } => write!(
+                f,
+                "missing external image for binding {binding} of shader `{shader}`"
+            ),
+            Self::InvalidBinding {
+                shader,
+                binding,
+                message,
+            } => write!(
+                f,
+                "invalid binding {binding} for shader `{shader}`: {message}"
+            ),
+            Self::InvalidIndirectBuffer { shader, message } => write!(
+                f,
+                "invalid indirect dispatch of shader `{shader}`: {message}"
+            ),
+            Self::InvalidTrace(message) => write!(f, "invalid trace: {message}"),
+            Self::InvalidShader(message) => write!(f, "invalid shader: {message}"),
+            Self::BufferNotFound => write!(f, "buffer not in map"),
+            Self::BufferMap(e) => write!(f, "failed to map buffer: {e}"),
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+/// A line in the shader sources, before preprocessing.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SourceLocation {
+    /// Path of the file relative to the shader directory.
+    pub file: String,
+    /// 1-based line number.
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::RequestDevice(e) => Some(e),
+            Self::BufferMap(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<wgpu::RequestDeviceError> for Error {
+    fn from(value: wgpu::RequestDeviceError) -> Self {
+        Self::RequestDevice(value)
+    }
+}
+
+impl From<wgpu::BufferAsyncError> for Error {
+    fn from(value: wgpu::BufferAsyncError) -> Self {
+        Self::BufferMap(value)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<wgpu::Error> for Error {
+    fn from(value: wgpu::Error) -> Self {
+        match value {
+            wgpu::Error::OutOfMemory { .. } => Self::OutOfMemory,
+            wgpu::Error::Validation { description, .. } => Self::Validation(description),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Conversion of scenes to vector formats.
+//!
+//! Exporters work from the commands decoded from the scene streams (see
+//! [SceneData::commands](crate::SceneData::commands)), so they apply to scenes built
+//! by any code, including appended fragments.
+
+mod pdf;
+mod svg;
+
+use peniko::kurbo::{Rect, Shape, Size};
+
+use crate::decode::Command;
+
+pub use pdf::pdf;
+pub use svg::svg;
+
+/// Returns the size of a page reaching from the origin to the far corner of the
+/// content. Layers only clip, so they don't contribute.
+///
+/// The page is at least 1×1, as SVG viewers and PDF readers reject empty pages.
+fn page_size(commands: &[Command]) -> Size {
+    let mut bounds: Option<Rect> = None;
+    for command in commands {
+        let rect = match command {
+            Command::Fill {
+                transform, path, ..
+            } => transform.transform_rect_bbox(path.bounding_box()),
+            Command::Stroke {
+                style,
+                transform,
+                path,
+                ..
+            } => {
+                let half_width = style.width as f64 * 0.5;
+                transform.transform_rect_bbox(path.bounding_box().inflate(half_width, half_width))
+            }
+            _ => continue,
+        };
+        bounds = Some(bounds.map_or(rect, |bounds| bounds.union(rect)));
+    }
+    let bounds = bounds.unwrap_or_default();
+    Size::new(bounds.x1.max(1.0).ceil(), bounds.y1.max(1.0).ceil())
+}
+
+/// Returns a scene with a fill, a stroke, a gradient and nested layers, for snapshot
+/// tests of the exporters.
+#[cfg(test)]
+fn test_scene() -> crate::Scene {
+    use peniko::kurbo::{Affine, Line};
+    use peniko::{Color, Fill, LinearGradient, Mix, Stroke};
+
+    use crate::{Scene, SceneBuilder};
+
+    let mut scene = Scene::new();
+    let mut builder = SceneBuilder::for_scene(&mut scene);
+    let red = Color::rgb8(255, 0, 0);
+    builder.fill(
+        Fill::NonZero,
+        Affine::IDENTITY,
+        red,
+        None,
+        &Rect::new(10.0, 10.0, 50.0, 50.0),
+    );
+    let blue = Color::rgba8(0, 0, 255, 128);
+    let line = Line::new((0.0, 0.0), (40.0, 0.0));
+    let transform = Affine::translate((10.0, 60.0));
+    builder.stroke(&Stroke::new(4.0), transform, blue, None, &line);
+    let gradient = LinearGradient {
+        start: (60.0, 0.0).into(),
+        end: (100.0, 0.0).into(),
+        stops: [
+            (0.0, Color::rgb8(255, 255, 0)).into(),
+            (1.0, Color::rgb8(0, 128, 0)).into(),
+        ]
+        .into_iter()
+        .collect(),
+        extend: Default::default(),
+    };
+    builder.fill(
+        Fill::NonZero,
+        Affine::IDENTITY,
+        &gradient,
+        None,
+        &Rect::new(60.0, 10.0, 100.0, 50.0),
+    );
+    builder.push_layer(
+        Mix::Normal,
+        1.0,
+        Affine::IDENTITY,
+        &Rect::new(0.0, 0.0, 80.0, 80.0),
+    );
+    builder.push_layer(
+        Mix::Multiply,
+        0.5,
+        Affine::IDENTITY,
+        &Rect::new(40.0, 40.0, 120.0, 120.0),
+    );
+    let green = Color::rgb8(0, 255, 0);
+    builder.fill(
+        Fill::NonZero,
+        Affine::IDENTITY,
+        green,
+        None,
+        &Rect::new(30.0, 30.0, 90.0, 90.0),
+    );
+    builder.pop_layer();
+    builder.pop_layer();
+    builder.finish();
+    scene
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::Size;
+
+    #[test]
+    fn empty_page_size() {
+        assert_eq!(super::page_size(&[]), Size::new(1.0, 1.0));
+    }
+}
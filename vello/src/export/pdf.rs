@@ -0,0 +1,661 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+use std::fmt::{self, Write};
+
+use peniko::kurbo::{Affine, BezPath, PathEl, Point, Size};
+use peniko::{BlendMode, Brush, Color, ColorStop, Mix};
+
+use super::page_size;
+use crate::decode::Command;
+use crate::Scene;
+
+// Objects with fixed numbers. The rest are numbered in order of creation.
+const CATALOG: usize = 1;
+const PAGES: usize = 2;
+const PAGE: usize = 3;
+const RESOURCES: usize = 4;
+const CONTENTS: usize = 5;
+const FIRST_OBJECT: usize = 6;
+
+/// Converts a scene to a single page PDF document.
+///
+/// The page covers the scene from the origin to the far corner of its content, with
+/// one unit per point. Glyphs are encoded as paths in the scene, so they are written
+/// as outlines. Layers with a blend mode or alpha become transparency groups, and
+/// gradients with translucent stops are drawn with a soft mask. Compose modes other
+/// than source over have no PDF equivalent and are ignored.
+///
+/// Streams are not compressed and objects are written in the order they are created,
+/// so the output only depends on the scene.
+pub fn pdf(scene: &Scene) -> Vec<u8> {
+    let commands: Vec<Command> = scene.data().commands().collect();
+    let mut writer = PdfWriter::new(page_size(&commands));
+    for command in &commands {
+        writer.command(command).unwrap();
+    }
+    writer.finish()
+}
+
+struct PdfWriter {
+    size: Size,
+    /// Maps scene coordinates to the default PDF space, which has y pointing up.
+    flip: Affine,
+    /// Objects after the fixed ones.
+    objects: Vec<String>,
+    /// Content stream of the page, followed by those of open transparency groups.
+    contents: Vec<String>,
+    /// For each open layer, the graphics state of its transparency group, if any.
+    layers: Vec<Option<usize>>,
+    /// Graphics state dictionaries and their objects, named `/GS{index}`.
+    ext_gstates: Vec<(String, usize)>,
+    /// Pattern objects, named `/P{index}`.
+    patterns: Vec<usize>,
+    /// Form objects, named `/X{index}`.
+    xobjects: Vec<usize>,
+}
+
+impl PdfWriter {
+    fn new(size: Size) -> Self {
+        Self {
+            size,
+            flip: Affine::new([1.0, 0.0, 0.0, -1.0, 0.0, size.height]),
+            objects: vec![],
+            contents: vec![String::new()],
+            layers: vec![],
+            ext_gstates: vec![],
+            patterns: vec![],
+            xobjects: vec![],
+        }
+    }
+
+    fn command(&mut self, command: &Command) -> fmt::Result {
+        match command {
+            Command::Fill {
+                transform,
+                brush,
+                brush_transform,
+                path,
+            } => {
+                let brush_transform = *transform * brush_transform.unwrap_or(Affine::IDENTITY);
+                let paint = match self.paint(brush, brush_transform, false) {
+                    Some(paint) => paint,
+                    None => return Ok(()),
+                };
+                // Fills are transformed up front, which keeps the current transform at
+                // identity for patterns and soft masks.
+                let path = self.flip * *transform * path.clone();
+                let out = self.content();
+                writeln!(out, "q {paint}")?;
+                write_path(out, &path)?;
+                writeln!(out, "f Q")
+            }
+            Command::Stroke {
+                style,
+                transform,
+                brush,
+                brush_transform,
+                path,
+            } => {
+                let brush_transform = *transform * brush_transform.unwrap_or(Affine::IDENTITY);
+                let paint = match self.paint(brush, brush_transform, true) {
+                    Some(paint) => paint,
+                    None => return Ok(()),
+                };
+                // The path is transformed by the current transform instead, so that
+                // the line width scales with it.
+                let matrix = Coeffs(self.flip * *transform);
+                let out = self.content();
+                writeln!(
+                    out,
+                    "q {paint} {matrix} cm {} w 1 J 1 j",
+                    Num(style.width as f64)
+                )?;
+                write_path(out, path)?;
+                writeln!(out, "S Q")
+            }
+            Command::PushLayer {
+                blend,
+                alpha,
+                transform,
+                path,
+            } => {
+                let path = self.flip * *transform * path.clone();
+                let out = self.content();
+                writeln!(out, "q")?;
+                write_path(out, &path)?;
+                writeln!(out, "W n")?;
+                let group = if blend.mix == Mix::Clip || (blend.mix == Mix::Normal && *alpha >= 1.0)
+                {
+                    None
+                } else {
+                    let alpha = Num(*alpha as f64);
+                    let mode = blend_mode(blend);
+                    self.contents.push(String::new());
+                    Some(self.ext_gstate(format!("<< /BM /{mode} /ca {alpha} /CA {alpha} >>")))
+                };
+                self.layers.push(group);
+                Ok(())
+            }
+            Command::PopLayer => self.pop_layer(),
+        }
+    }
+
+    fn pop_layer(&mut self) -> fmt::Result {
+        match self.layers.pop() {
+            Some(Some(gs)) => {
+                let content = self.contents.pop().unwrap_or_default();
+                let form = self.form(&content, "/S /Transparency /I true /CS /DeviceRGB");
+                let xobject = self.xobjects.len();
+                self.xobjects.push(form);
+                writeln!(self.content(), "/GS{gs} gs /X{xobject} Do Q")
+            }
+            Some(None) => writeln!(self.content(), "Q"),
+            None => Ok(()),
+        }
+    }
+
+    fn content(&mut self) -> &mut String {
+        self.contents.last_mut().unwrap()
+    }
+
+    /// Returns the operators that select the brush as fill or stroke paint, or `None`
+    /// if there is nothing to draw.
+    fn paint(&mut self, brush: &Brush, brush_transform: Affine, stroke: bool) -> Option<String> {
+        let (color_op, alpha_key) = if stroke { ("RG", "CA") } else { ("rg", "ca") };
+        let (shading, stops) = match brush {
+            Brush::Solid(color) => {
+                let mut paint = format!("{} {color_op}", rgb(*color));
+                if color.a != 255 {
+                    let alpha = Num(color.a as f64 / 255.0);
+                    let gs = self.ext_gstate(format!("<< /{alpha_key} {alpha} >>"));
+                    paint = format!("/GS{gs} gs {paint}");
+                }
+                return Some(paint);
+            }
+            Brush::LinearGradient(gradient) => (
+                format!(
+                    "/ShadingType 2 /Coords [{} {} {} {}]",
+                    Num(gradient.start.x),
+                    Num(gradient.start.y),
+                    Num(gradient.end.x),
+                    Num(gradient.end.y)
+                ),
+                &gradient.stops,
+            ),
+            Brush::RadialGradient(gradient) => (
+                format!(
+                    "/ShadingType 3 /Coords [{} {} {} {} {} {}]",
+                    Num(gradient.start_center.x),
+                    Num(gradient.start_center.y),
+                    Num(gradient.start_radius as f64),
+                    Num(gradient.end_center.x),
+                    Num(gradient.end_center.y),
+                    Num(gradient.end_radius as f64)
+                ),
+                &gradient.stops,
+            ),
+            // Sweep gradients are never encoded.
+            Brush::SweepGradient(_) => return None,
+        };
+        if stops.is_empty() {
+            return None;
+        }
+        // Patterns are positioned in the default space of the page, not by the current
+        // transform.
+        let matrix = self.flip * brush_transform;
+        let pattern = self.pattern(&shading, "/DeviceRGB", matrix, stops, rgb);
+        let (color_space, color_op) = if stroke { ("CS", "SCN") } else { ("cs", "scn") };
+        let mut paint = format!("/Pattern {color_space} /P{pattern} {color_op}");
+        if stops.iter().any(|stop| stop.color.a != 255) {
+            // Shadings are opaque, so translucent stops become a luminosity mask drawn
+            // with the same geometry.
+            let mask = self.pattern(&shading, "/DeviceGray", matrix, stops, |color| {
+                format!("{}", Num(color.a as f64 / 255.0))
+            });
+            let Size { width, height } = self.size;
+            let form = self.form(
+                &format!("/Pattern cs /P{mask} scn 0 0 {width} {height} re f"),
+                "/S /Transparency /CS /DeviceGray",
+            );
+            let gs = self.ext_gstate(format!(
+                "<< /SMask << /Type /Mask /S /Luminosity /G {form} 0 R >> >>"
+            ));
+            paint = format!("/GS{gs} gs {paint}");
+        }
+        Some(paint)
+    }
+
+    /// Adds a shading pattern and returns its index.
+    fn pattern(
+        &mut self,
+        shading: &str,
+        color_space: &str,
+        matrix: Affine,
+        stops: &[ColorStop],
+        color: impl Fn(Color) -> String,
+    ) -> usize {
+        let function = stops_function(stops, color);
+        let object = self.object(format!(
+            "<< /Type /Pattern /PatternType 2 /Matrix [{}] /Shading << {shading} \
+             /ColorSpace {color_space} /Function {function} /Extend [true true] >> >>",
+            Coeffs(matrix)
+        ));
+        self.patterns.push(object);
+        self.patterns.len() - 1
+    }
+
+    /// Adds a form covering the page and returns its object number.
+    fn form(&mut self, content: &str, group: &str) -> usize {
+        let Size { width, height } = self.size;
+        self.object(stream(
+            &format!(
+                "/Type /XObject /Subtype /Form /BBox [0 0 {width} {height}] \
+                 /Group << {group} >> /Resources {RESOURCES} 0 R"
+            ),
+            content,
+        ))
+    }
+
+    /// Returns the index of a graphics state dictionary, adding it if needed.
+    fn ext_gstate(&mut self, dict: String) -> usize {
+        if let Some(index) = self.ext_gstates.iter().position(|(d, _)| *d == dict) {
+            return index;
+        }
+        let object = self.object(dict.clone());
+        self.ext_gstates.push((dict, object));
+        self.ext_gstates.len() - 1
+    }
+
+    fn object(&mut self, body: String) -> usize {
+        self.objects.push(body);
+        FIRST_OBJECT + self.objects.len() - 1
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        while !self.layers.is_empty() {
+            self.pop_layer().unwrap();
+        }
+        let Size { width, height } = self.size;
+        let mut resources = String::from("<<");
+        write_names(
+            &mut resources,
+            "ExtGState",
+            "GS",
+            self.ext_gstates.iter().map(|e| e.1),
+        );
+        write_names(
+            &mut resources,
+            "Pattern",
+            "P",
+            self.patterns.iter().copied(),
+        );
+        write_names(
+            &mut resources,
+            "XObject",
+            "X",
+            self.xobjects.iter().copied(),
+        );
+        resources.push_str(" >>");
+        let fixed = [
+            format!("<< /Type /Catalog /Pages {PAGES} 0 R >>"),
+            format!("<< /Type /Pages /Kids [{PAGE} 0 R] /Count 1 >>"),
+            format!(
+                "<< /Type /Page /Parent {PAGES} 0 R /MediaBox [0 0 {width} {height}] \
+                 /Group << /S /Transparency /CS /DeviceRGB >> \
+                 /Resources {RESOURCES} 0 R /Contents {CONTENTS} 0 R >>"
+            ),
+            resources,
+            stream("", &self.contents[0]),
+        ];
+        let mut out = String::from("%PDF-1.4\n");
+        let mut offsets = vec![];
+        for (i, body) in fixed.iter().chain(&self.objects).enumerate() {
+            offsets.push(out.len());
+            let _ = write!(out, "{} 0 obj\n{body}\nendobj\n", i + CATALOG);
+        }
+        let xref = out.len();
+        let _ = writeln!(out, "xref\n0 {}", offsets.len() + 1);
+        out.push_str("0000000000 65535 f \n");
+        for offset in offsets.iter() {
+            let _ = writeln!(out, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            out,
+            "trailer\n<< /Size {} /Root {CATALOG} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+            offsets.len() + 1
+        );
+        out.into_bytes()
+    }
+}
+
+/// Builds a function mapping the gradient parameter to colors, as a type 2 function for
+/// each pair of stops joined by a type 3 function. Offsets before the first and after
+/// the last stop take the color of that stop.
+fn stops_function(stops: &[ColorStop], color: impl Fn(Color) -> String) -> String {
+    let mut stops = stops.to_vec();
+    if let Some(first) = stops.first().copied() {
+        if first.offset > 0.0 {
+            stops.insert(
+                0,
+                ColorStop {
+                    offset: 0.0,
+                    ..first
+                },
+            );
+        }
+    }
+    if let Some(last) = stops.last().copied() {
+        if last.offset < 1.0 || stops.len() == 1 {
+            stops.push(ColorStop {
+                offset: 1.0,
+                ..last
+            });
+        }
+    }
+    let interpolate = |c0: Color, c1: Color| {
+        format!(
+            "<< /FunctionType 2 /Domain [0 1] /C0 [{}] /C1 [{}] /N 1 >>",
+            color(c0),
+            color(c1)
+        )
+    };
+    if stops.len() == 2 {
+        return interpolate(stops[0].color, stops[1].color);
+    }
+    let mut functions = String::new();
+    let mut bounds = String::new();
+    let mut encode = String::new();
+    for (i, pair) in stops.windows(2).enumerate() {
+        if i != 0 {
+            functions.push(' ');
+            encode.push(' ');
+            let _ = write!(
+                bounds,
+                "{}{}",
+                if i == 1 { "" } else { " " },
+                Num(pair[0].offset as f64)
+            );
+        }
+        functions.push_str(&interpolate(pair[0].color, pair[1].color));
+        encode.push_str("0 1");
+    }
+    format!(
+        "<< /FunctionType 3 /Domain [0 1] /Functions [{functions}] /Bounds [{bounds}] \
+         /Encode [{encode}] >>"
+    )
+}
+
+fn write_path(out: &mut String, path: &BezPath) -> fmt::Result {
+    let mut last = Point::ZERO;
+    for el in path.elements() {
+        match *el {
+            PathEl::MoveTo(p) => {
+                writeln!(out, "{} {} m", Num(p.x), Num(p.y))?;
+                last = p;
+            }
+            PathEl::LineTo(p) => {
+                writeln!(out, "{} {} l", Num(p.x), Num(p.y))?;
+                last = p;
+            }
+            PathEl::QuadTo(p1, p2) => {
+                // PDF has no quadratic segments, so raise the degree.
+                let c1 = last + (p1 - last) * (2.0 / 3.0);
+                let c2 = p2 + (p1 - p2) * (2.0 / 3.0);
+                writeln!(
+                    out,
+                    "{} {} {} {} {} {} c",
+                    Num(c1.x),
+                    Num(c1.y),
+                    Num(c2.x),
+                    Num(c2.y),
+                    Num(p2.x),
+                    Num(p2.y)
+                )?;
+                last = p2;
+            }
+            PathEl::CurveTo(p1, p2, p3) => {
+                writeln!(
+                    out,
+                    "{} {} {} {} {} {} c",
+                    Num(p1.x),
+                    Num(p1.y),
+                    Num(p2.x),
+                    Num(p2.y),
+                    Num(p3.x),
+                    Num(p3.y)
+                )?;
+                last = p3;
+            }
+            PathEl::ClosePath => writeln!(out, "h")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_names(
+    out: &mut String,
+    category: &str,
+    prefix: &str,
+    objects: impl Iterator<Item = usize>,
+) {
+    let mut objects = objects.enumerate().peekable();
+    if objects.peek().is_none() {
+        return;
+    }
+    let _ = write!(out, " /{category} <<");
+    for (i, object) in objects {
+        let _ = write!(out, " /{prefix}{i} {object} 0 R");
+    }
+    out.push_str(" >>");
+}
+
+fn stream(entries: &str, content: &str) -> String {
+    let separator = if entries.is_empty() { "" } else { " " };
+    format!(
+        "<< {entries}{separator}/Length {} >>\nstream\n{content}\nendstream",
+        content.len()
+    )
+}
+
+fn rgb(color: Color) -> String {
+    format!(
+        "{} {} {}",
+        Num(color.r as f64 / 255.0),
+        Num(color.g as f64 / 255.0),
+        Num(color.b as f64 / 255.0)
+    )
+}
+
+fn blend_mode(blend: &BlendMode) -> &'static str {
+    match blend.mix {
+        Mix::Normal | Mix::Clip => "Normal",
+        Mix::Multiply => "Multiply",
+        Mix::Screen => "Screen",
+        Mix::Overlay => "Overlay",
+        Mix::Darken => "Darken",
+        Mix::Lighten => "Lighten",
+        Mix::ColorDodge => "ColorDodge",
+        Mix::ColorBurn => "ColorBurn",
+        Mix::HardLight => "HardLight",
+        Mix::SoftLight => "SoftLight",
+        Mix::Difference => "Difference",
+        Mix::Exclusion => "Exclusion",
+        Mix::Hue => "Hue",
+        Mix::Saturation => "Saturation",
+        Mix::Color => "Color",
+        Mix::Luminosity => "Luminosity",
+    }
+}
+
+/// A number in PDF syntax, which has no exponent notation. Values are rounded to
+/// single precision, which is what the scene holds.
+struct Num(f64);
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self.0 as f32;
+        if value.is_finite() {
+            write!(f, "{}", value)
+        } else {
+            write!(f, "0")
+        }
+    }
+}
+
+struct Coeffs(Affine);
+
+impl fmt::Display for Coeffs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0.as_coeffs().map(Num);
+        write!(f, "{a} {b} {c} {d} {e} {g}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Scene;
+
+    const SNAPSHOT: &str = r#"%PDF-1.4
+1 0 obj
+<< /Type /Catalog /Pages 2 0 R >>
+endobj
+2 0 obj
+<< /Type /Pages /Kids [3 0 R] /Count 1 >>
+endobj
+3 0 obj
+<< /Type /Page /Parent 2 0 R /MediaBox [0 0 100 90] /Group << /S /Transparency /CS /DeviceRGB >> /Resources 4 0 R /Contents 5 0 R >>
+endobj
+4 0 obj
+<< /ExtGState << /GS0 6 0 R /GS1 8 0 R >> /Pattern << /P0 7 0 R >> /XObject << /X0 9 0 R >> >>
+endobj
+5 0 obj
+<< /Length 301 >>
+stream
+q 1 0 0 rg
+10 80 m
+50 80 l
+50 40 l
+10 40 l
+10 80 l
+f Q
+q /GS0 gs 0 0 1 RG 1 0 0 -1 10 30 cm 4 w 1 J 1 j
+0 0 m
+40 0 l
+S Q
+q /Pattern cs /P0 scn
+60 80 m
+100 80 l
+100 40 l
+60 40 l
+60 80 l
+f Q
+q
+0 90 m
+80 90 l
+80 10 l
+0 10 l
+0 90 l
+W n
+q
+40 50 m
+120 50 l
+120 -30 l
+40 -30 l
+40 50 l
+W n
+/GS1 gs /X0 Do Q
+Q
+
+endstream
+endobj
+6 0 obj
+<< /CA 0.5019608 >>
+endobj
+7 0 obj
+<< /Type /Pattern /PatternType 2 /Matrix [1 0 0 -1 0 90] /Shading << /ShadingType 2 /Coords [60 0 100 0] /ColorSpace /DeviceRGB /Function << /FunctionType 2 /Domain [0 1] /C0 [1 1 0] /C1 [0 0.5019608 0] /N 1 >> /Extend [true true] >> >>
+endobj
+8 0 obj
+<< /BM /Multiply /ca 0.5 /CA 0.5 >>
+endobj
+9 0 obj
+<< /Type /XObject /Subtype /Form /BBox [0 0 100 90] /Group << /S /Transparency /I true /CS /DeviceRGB >> /Resources 4 0 R /Length 53 >>
+stream
+q 0 1 0 rg
+30 60 m
+90 60 l
+90 0 l
+30 0 l
+30 60 l
+f Q
+
+endstream
+endobj
+xref
+0 10
+0000000000 65535 f 
+0000000009 00000 n 
+0000000058 00000 n 
+0000000115 00000 n 
+0000000263 00000 n 
+0000000373 00000 n 
+0000000725 00000 n 
+0000000760 00000 n 
+0000001012 00000 n 
+0000001063 00000 n 
+trailer
+<< /Size 10 /Root 1 0 R >>
+startxref
+1285
+%%EOF
+"#;
+
+    /// Checks that each entry of the cross-reference table points at its object, and
+    /// that `startxref` points at the table.
+    fn check_xref(pdf: &str) {
+        let xref = pdf.rfind("\nxref\n").unwrap() + 1;
+        let startxref = pdf.rfind("startxref\n").unwrap();
+        assert_eq!(
+            pdf[startxref..].lines().nth(1),
+            Some(xref.to_string().as_str())
+        );
+        let mut lines = pdf[xref..].lines().skip(1);
+        let count: usize = lines.next().unwrap()["0 ".len()..].parse().unwrap();
+        assert_eq!(lines.next(), Some("0000000000 65535 f "));
+        for n in 1..count {
+            let entry = lines.next().unwrap();
+            assert!(entry.ends_with(" 00000 n "), "{entry}");
+            let offset: usize = entry[..10].parse().unwrap();
+            let object = format!("{n} 0 obj\n");
+            assert!(pdf[offset..].starts_with(&object), "object {n} at {offset}");
+        }
+        assert_eq!(lines.next(), Some("trailer"));
+    }
+
+    #[test]
+    fn snapshot() {
+        let pdf = super::pdf(&super::super::test_scene());
+        assert_eq!(String::from_utf8(pdf).unwrap(), SNAPSHOT);
+    }
+
+    #[test]
+    fn xref_offsets() {
+        check_xref(SNAPSHOT);
+        let empty = String::from_utf8(super::pdf(&Scene::new())).unwrap();
+        check_xref(&empty);
+        assert!(empty.contains("/MediaBox [0 0 1 1]"));
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+use std::fmt::{self, Write};
+
+use peniko::kurbo::{Affine, BezPath, PathEl, Size};
+use peniko::{BlendMode, Brush, Color, ColorStop, Mix};
+
+use super::page_size;
+use crate::decode::Command;
+use crate::Scene;
+
+/// Converts a scene to an SVG document.
+///
+/// The document size covers the scene from the origin to the far corner of its
+/// content, so coordinates match the rendered image. Strokes use round joins and caps,
+/// which is how they are rendered. Compose modes other than source over have no SVG
+/// equivalent and are ignored.
+pub fn svg(scene: &Scene) -> String {
+    let commands: Vec<Command> = scene.data().commands().collect();
+    let mut writer = SvgWriter::default();
+    for command in &commands {
+        writer.command(command).unwrap();
+    }
+    writer.finish(page_size(&commands))
+}
+
+/// Value of a fill or stroke attribute.
+struct Paint {
+    value: String,
+    opacity: f32,
+}
+
+#[derive(Default)]
+struct SvgWriter {
+    body: String,
+    n_gradient: usize,
+    n_clip: usize,
+    n_layer: usize,
+}
+
+impl SvgWriter {
+    fn command(&mut self, command: &Command) -> fmt::Result {
+        match command {
+            Command::Fill {
+                transform,
+                brush,
+                brush_transform,
+                path,
+            } => {
+                let paint = self.paint(brush, brush_transform)?;
+                write!(self.body, "<path")?;
+                write_path_attributes(&mut self.body, transform, path)?;
+                write_paint(&mut self.body, "fill", &paint)?;
+                writeln!(self.body, "/>")
+            }
+            Command::Stroke {
+                style,
+                transform,
+                brush,
+                brush_transform,
+                path,
+            } => {
+                let paint = self.paint(brush, brush_transform)?;
+                write!(self.body, "<path")?;
+                write_path_attributes(&mut self.body, transform, path)?;
+                write!(self.body, " fill=\"none\"")?;
+                write_paint(&mut self.body, "stroke", &paint)?;
+                writeln!(
+                    self.body,
+                    " stroke-width=\"{}\" stroke-linejoin=\"round\" stroke-linecap=\"round\"/>",
+                    style.width
+                )
+            }
+            Command::PushLayer {
+                blend,
+                alpha,
+                transform,
+                path,
+            } => {
+                let id = self.n_clip;
+                self.n_clip += 1;
+                write!(self.body, "<clipPath id=\"clip{id}\"><path")?;
+                write_path_attributes(&mut self.body, transform, path)?;
+                writeln!(self.body, "/></clipPath>")?;
+                write!(self.body, "<g clip-path=\"url(#clip{id})\"")?;
+                if *alpha < 1.0 {
+                    write!(self.body, " opacity=\"{alpha}\"")?;
+                }
+                if let Some(mode) = blend_mode(blend) {
+                    write!(self.body, " style=\"mix-blend-mode:{mode}\"")?;
+                }
+                self.n_layer += 1;
+                writeln!(self.body, ">")
+            }
+            Command::PopLayer => {
+                if self.n_layer > 0 {
+                    self.n_layer -= 1;
+                    writeln!(self.body, "</g>")?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the definition of a gradient if needed and returns the paint.
+    fn paint(
+        &mut self,
+        brush: &Brush,
+        brush_transform: &Option<Affine>,
+    ) -> Result<Paint, fmt::Error> {
+        let (name, attributes, stops) = match brush {
+            Brush::Solid(color) => {
+                return Ok(Paint {
+                    value: hex(*color),
+                    opacity: color.a as f32 / 255.0,
+                })
+            }
+            Brush::LinearGradient(gradient) => (
+                "linearGradient",
+                format!(
+                    "x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"",
+                    gradient.start.x as f32,
+                    gradient.start.y as f32,
+                    gradient.end.x as f32,
+                    gradient.end.y as f32
+                ),
+                &gradient.stops,
+            ),
+            // SVG radial gradients are defined by a focal circle inside the end circle,
+            // which matches the start circle of a two point gradient.
+            Brush::RadialGradient(gradient) => (
+                "radialGradient",
+                format!(
+                    "fx=\"{}\" fy=\"{}\" fr=\"{}\" cx=\"{}\" cy=\"{}\" r=\"{}\"",
+                    gradient.start_center.x as f32,
+                    gradient.start_center.y as f32,
+                    gradient.start_radius,
+                    gradient.end_center.x as f32,
+                    gradient.end_center.y as f32,
+                    gradient.end_radius
+                ),
+                &gradient.stops,
+            ),
+            // Sweep gradients are never encoded.
+            Brush::SweepGradient(_) => {
+                return Ok(Paint {
+                    value: "none".into(),
+                    opacity: 1.0,
+                })
+            }
+        };
+        let id = self.n_gradient;
+        self.n_gradient += 1;
+        write!(
+            self.body,
+            "<{name} id=\"gradient{id}\" {attributes} gradientUnits=\"userSpaceOnUse\""
+        )?;
+        if let Some(brush_transform) = brush_transform {
+            write!(
+                self.body,
+                " gradientTransform=\"{}\"",
+                Matrix(brush_transform)
+            )?;
+        }
+        writeln!(self.body, ">")?;
+        write_stops(&mut self.body, stops)?;
+        writeln!(self.body, "</{name}>")?;
+        Ok(Paint {
+            value: format!("url(#gradient{id})"),
+            opacity: 1.0,
+        })
+    }
+
+    fn finish(mut self, size: Size) -> String {
+        for _ in 0..self.n_layer {
+            self.body.push_str("</g>\n");
+        }
+        let (width, height) = (size.width, size.height);
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\">\n{}</svg>\n",
+            self.body
+        )
+    }
+}
+
+fn write_path_attributes(out: &mut String, transform: &Affine, path: &BezPath) -> fmt::Result {
+    write!(out, " d=\"")?;
+    for (i, el) in path.elements().iter().enumerate() {
+        if i != 0 {
+            write!(out, " ")?;
+        }
+        match el {
+            PathEl::MoveTo(p) => write!(out, "M{} {}", p.x as f32, p.y as f32)?,
+            PathEl::LineTo(p) => write!(out, "L{} {}", p.x as f32, p.y as f32)?,
+            PathEl::QuadTo(p1, p2) => write!(
+                out,
+                "Q{} {} {} {}",
+                p1.x as f32, p1.y as f32, p2.x as f32, p2.y as f32
+            )?,
+            PathEl::CurveTo(p1, p2, p3) => write!(
+                out,
+                "C{} {} {} {} {} {}",
+                p1.x as f32, p1.y as f32, p2.x as f32, p2.y as f32, p3.x as f32, p3.y as f32
+            )?,
+            PathEl::ClosePath => write!(out, "Z")?,
+        }
+    }
+    write!(out, "\"")?;
+    if *transform != Affine::IDENTITY {
+        write!(out, " transform=\"{}\"", Matrix(transform))?;
+    }
+    Ok(())
+}
+
+fn write_paint(out: &mut String, attribute: &str, paint: &Paint) -> fmt::Result {
+    write!(out, " {attribute}=\"{}\"", paint.value)?;
+    if paint.opacity < 1.0 {
+        write!(out, " {attribute}-opacity=\"{}\"", paint.opacity)?;
+    }
+    Ok(())
+}
+
+fn write_stops(out: &mut String, stops: &[ColorStop]) -> fmt::Result {
+    for stop in stops {
+        write!(
+            out,
+            "<stop offset=\"{}\" stop-color=\"{}\"",
+            stop.offset,
+            hex(stop.color)
+        )?;
+        if stop.color.a != 255 {
+            write!(out, " stop-opacity=\"{}\"", stop.color.a as f32 / 255.0)?;
+        }
+        writeln!(out, "/>")?;
+    }
+    Ok(())
+}
+
+fn hex(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+fn blend_mode(blend: &BlendMode) -> Option<&'static str> {
+    Some(match blend.mix {
+        Mix::Normal | Mix::Clip => return None,
+        Mix::Multiply => "multiply",
+        Mix::Screen => "screen",
+        Mix::Overlay => "overlay",
+        Mix::Darken => "darken",
+        Mix::Lighten => "lighten",
+        Mix::ColorDodge => "color-dodge",
+        Mix::ColorBurn => "color-burn",
+        Mix::HardLight => "hard-light",
+        Mix::SoftLight => "soft-light",
+        Mix::Difference => "difference",
+        Mix::Exclusion => "exclusion",
+        Mix::Hue => "hue",
+        Mix::Saturation => "saturation",
+        Mix::Color => "color",
+        Mix::Luminosity => "luminosity",
+    })
+}
+
+struct Matrix<'a>(&'a Affine);
+
+impl fmt::Display for Matrix<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0.as_coeffs().map(|value| value as f32);
+        write!(f, "matrix({a} {b} {c} {d} {e} {g})")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    const SNAPSHOT: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="90" viewBox="0 0 100 90">
+<path d="M10 10 L50 10 L50 50 L10 50 L10 10" fill="#ff0000"/>
+<path d="M0 0 L40 0" transform="matrix(1 0 0 1 10 60)" fill="none" stroke="#0000ff" stroke-opacity="0.5019608" stroke-width="4" stroke-linejoin="round" stroke-linecap="round"/>
+<linearGradient id="gradient0" x1="60" y1="0" x2="100" y2="0" gradientUnits="userSpaceOnUse">
+<stop offset="0" stop-color="#ffff00"/>
+<stop offset="1" stop-color="#008000"/>
+</linearGradient>
+<path d="M60 10 L100 10 L100 50 L60 50 L60 10" fill="url(#gradient0)"/>
+<clipPath id="clip0"><path d="M0 0 L80 0 L80 80 L0 80 L0 0"/></clipPath>
+<g clip-path="url(#clip0)">
+<clipPath id="clip1"><path d="M40 40 L120 40 L120 120 L40 120 L40 40"/></clipPath>
+<g clip-path="url(#clip1)" opacity="0.5" style="mix-blend-mode:multiply">
+<path d="M30 30 L90 30 L90 90 L30 90 L30 30" fill="#00ff00"/>
+</g>
+</g>
+</svg>
+"##;
+
+    #[test]
+    fn snapshot() {
+        assert_eq!(super::svg(&super::super::test_scene()), SNAPSHOT);
+    }
+}
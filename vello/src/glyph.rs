@@ -94,7 +94,7 @@ impl<'a> GlyphProvider<'a> {
             None,
             &convert_path(path.elements()),
         );
-        builder.finish();
+        finish_glyph(builder, gid);
         Some(fragment)
     }
 
@@ -188,11 +188,18 @@ impl<'a> GlyphProvider<'a> {
                 }
             }
         }
-        builder.finish();
+        finish_glyph(builder, gid);
         Some(fragment)
     }
 }
 
+/// Finishes the fragment of a glyph. Diagnostics from a checked builder come from the
+/// font data and the glyph is encoded regardless, so they only fail debug builds.
+fn finish_glyph(builder: SceneBuilder, gid: u16) {
+    let result = builder.finish_checked();
+    debug_assert!(result.is_ok(), "glyph {gid}: {}", result.unwrap_err());
+}
+
 fn convert_path(path: impl Iterator<Item = moscato::Element> + Clone) -> peniko::kurbo::BezPath {
     let mut result = peniko::kurbo::BezPath::new();
     for el in path {
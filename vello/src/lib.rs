@@ -14,7 +14,9 @@
 //
 // Also licensed under MIT license, at your choice.
 
+mod codec;
 mod engine;
+mod error;
 mod ramp;
 mod render;
 mod scene;
@@ -25,42 +27,258 @@ pub use peniko;
 /// 2D geometry, with a focus on curves.
 pub use peniko::kurbo;
 
+pub mod export;
 pub mod glyph;
 pub mod util;
 
-pub use scene::{ResourceBundle, ResourcePatch, Scene, SceneBuilder, SceneData, SceneFragment};
+pub use engine::TransientResources;
+pub use error::{Error, SourceLocation};
+pub use scene::decode;
+pub use scene::{
+    Diagnostic, DiagnosticKind, DrawId, FragmentHandle, GpuMemory, ResourceBundle, ResourcePatch,
+    Scene, SceneBuilder, SceneData, SceneFragment, SceneStats, StreamSizes,
+};
+pub use shaders::{BundleTargets, BundledShader, ShaderBundle, ShaderCache};
 
-use engine::{Engine, ExternalResource};
-use shaders::FullShaders;
+use std::ops::Range;
 
-use wgpu::{Device, Queue, SurfaceTexture, TextureFormat, TextureView};
+use engine::{BufProxy, Downloads, Engine, ExternalResource};
+use shaders::FullShaders;
 
-/// Catch-all error type.
-pub type Error = Box<dyn std::error::Error>;
+use wgpu::{Buffer, CommandEncoder, Device, Queue, SurfaceTexture, TextureFormat, TextureView};
 
-/// Specialization of `Result` for our catch-all error type.
+/// Specialization of `Result` for our error type.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Tile geometry and workgroup sizes used by the renderer.
+///
+/// These are fixed when the renderer is created, as they are compiled into the shaders.
+/// The defaults suit most desktop GPUs; [RendererOptions::downlevel] fits within
+/// [wgpu::Limits::downlevel_defaults].
+///
+/// Workgroup sizes and the number of tiles per bin must be powers of two. The
+/// stages that process draw objects (draw monoid scan, binning and coarse
+/// rasterization) run with a workgroup per bin, so their workgroup size is
+/// `n_tile_x * n_tile_y`, which must be at least 64.
+///
+/// The scans over path tags and draw objects are single level, so a scene may
+/// contain at most `4 * pathtag_reduce_wg^2` path tags and `(n_tile_x * n_tile_y)^2`
+/// draw objects. A render target may span at most `n_tile_x * n_tile_y` bins.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RendererOptions {
+    /// Width of a tile in pixels. Must be a multiple of 4.
+    pub tile_width: u32,
+    /// Height of a tile in pixels.
+    pub tile_height: u32,
+    /// Number of tiles per bin horizontally.
+    pub n_tile_x: u32,
+    /// Number of tiles per bin vertically.
+    pub n_tile_y: u32,
+    /// Workgroup size of the path tag reduction and scan.
+    pub pathtag_reduce_wg: u32,
+    /// Workgroup size of the stages that process paths: bounding box clear,
+    /// tile allocation and backdrop.
+    pub path_bbox_wg: u32,
+    /// Workgroup size of path segment processing and coarse path rasterization.
+    pub path_coarse_wg: u32,
+    /// Workgroup size of the clip reduction and leaf stages.
+    pub clip_reduce_wg: u32,
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        Self {
+            tile_width: 16,
+            tile_height: 16,
+            n_tile_x: 16,
+            n_tile_y: 16,
+            pathtag_reduce_wg: 256,
+            path_bbox_wg: 256,
+            path_coarse_wg: 256,
+            clip_reduce_wg: 256,
+        }
+    }
+}
+
+impl RendererOptions {
+    /// Options for devices limited to [wgpu::Limits::downlevel_defaults].
+    ///
+    /// This halves the number of tiles per bin so that coarse rasterization fits in
+    /// 16KB of workgroup storage.
+    pub fn downlevel() -> Self {
+        Self {
+            n_tile_y: 8,
+            ..Default::default()
+        }
+    }
+
+    /// Number of tiles in a bin.
+    pub fn n_tile(&self) -> u32 {
+        self.n_tile_x * self.n_tile_y
+    }
+
+    /// Checks the options against the constraints of the shaders and, if limits are
+    /// given, the device.
+    fn validate(&self, limits: Option<&wgpu::Limits>) -> Result<()> {
+        let invalid = |message: String| Err(Error::InvalidOptions(message));
+        let (max_invocations, max_size_x, max_size_y) = match limits {
+            Some(limits) => (
+                limits.max_compute_invocations_per_workgroup,
+                limits.max_compute_workgroup_size_x,
+                limits.max_compute_workgroup_size_y,
+            ),
+            None => (u32::MAX, u32::MAX, u32::MAX),
+        };
+        let workgroup_sizes = [
+            ("pathtag_reduce_wg", self.pathtag_reduce_wg),
+            ("path_bbox_wg", self.path_bbox_wg),
+            ("path_coarse_wg", self.path_coarse_wg),
+            ("clip_reduce_wg", self.clip_reduce_wg),
+            ("n_tile_x * n_tile_y", self.n_tile()),
+        ];
+        for (name, size) in workgroup_sizes {
+            if !size.is_power_of_two() {
+                return invalid(format!("{name} ({size}) must be a power of two"));
+            }
+            if size > max_invocations || size > max_size_x {
+                return invalid(format!(
+                    "{name} ({size}) exceeds the maximum workgroup size of the device"
+                ));
+            }
+        }
+        if !self.n_tile_x.is_power_of_two() || self.n_tile() < 64 {
+            return invalid(format!(
+                "{}x{} tiles per bin is not supported",
+                self.n_tile_x, self.n_tile_y
+            ));
+        }
+        let fine_wg_x = self.tile_width / shaders::FINE_PIXELS_PER_THREAD;
+        if fine_wg_x == 0 || self.tile_width % shaders::FINE_PIXELS_PER_THREAD != 0 {
+            return invalid(format!(
+                "tile_width ({}) must be a positive multiple of {}",
+                self.tile_width,
+                shaders::FINE_PIXELS_PER_THREAD
+            ));
+        }
+        if self.tile_height == 0
+            || fine_wg_x * self.tile_height > max_invocations
+            || fine_wg_x > max_size_x
+            || self.tile_height > max_size_y
+        {
+            return invalid(format!(
+                "{}x{} tiles exceed the maximum workgroup size of the device",
+                self.tile_width, self.tile_height
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// Renders a scene into a texture or surface.
 pub struct Renderer {
     engine: Engine,
     shaders: FullShaders,
     blit: BlitPipeline,
     target: Option<TargetTexture>,
+    object_id_threshold: Option<f32>,
+    object_ids: Option<PendingObjectIds>,
+    keep_scene_resident: bool,
+    resident_scene: Option<ResidentScene>,
+    #[cfg(feature = "hot_reload")]
+    shader_watcher: Option<shaders::ShaderWatcher>,
 }
 
 impl Renderer {
     /// Creates a new renderer for the specified device.
     pub fn new(device: &Device) -> Result<Self> {
+        Self::with_options(device, RendererOptions::default())
+    }
+
+    /// Creates a new renderer for the specified device with the given tile geometry and
+    /// workgroup sizes.
+    pub fn with_options(device: &Device, options: RendererOptions) -> Result<Self> {
+        Self::from_parts(device, options, None)
+    }
+
+    /// Creates a new renderer from a prebuilt shader bundle.
+    ///
+    /// This skips preprocessing at startup; the renderer uses the options the bundle
+    /// was built with.
+    pub fn with_bundle(device: &Device, bundle: &ShaderBundle) -> Result<Self> {
+        Self::from_parts(device, *bundle.options(), Some(bundle))
+    }
+
+    /// Creates a new renderer, compiling the pipelines concurrently.
+    ///
+    /// If a cache is given, the preprocessed shaders are loaded from it, or stored in it
+    /// for the next launch. This also waits for the device to report compilation
+    /// errors, which [Renderer::with_options] can't do on the web.
+    pub async fn new_async(
+        device: &Device,
+        options: RendererOptions,
+        cache: Option<&ShaderCache>,
+    ) -> Result<Self> {
+        options.validate(Some(&device.limits()))?;
+        let bundle = match cache {
+            Some(cache) => Some(cache.load_or_build(&options)?),
+            None => None,
+        };
         let mut engine = Engine::new();
-        let shaders = shaders::full_shaders(device, &mut engine)?;
+        let shaders =
+            shaders::full_shaders_async(device, &mut engine, &options, bundle.as_ref()).await?;
+        Ok(Self::from_shaders(device, engine, shaders))
+    }
+
+    fn from_parts(
+        device: &Device,
+        options: RendererOptions,
+        bundle: Option<&ShaderBundle>,
+    ) -> Result<Self> {
+        options.validate(Some(&device.limits()))?;
+        let mut engine = Engine::new();
+        let shaders = shaders::full_shaders(device, &mut engine, &options, bundle)?;
+        Ok(Self::from_shaders(device, engine, shaders))
+    }
+
+    fn from_shaders(device: &Device, engine: Engine, shaders: FullShaders) -> Self {
         let blit = BlitPipeline::new(device, TextureFormat::Bgra8Unorm);
-        Ok(Self {
+        Self {
             engine,
             shaders,
             blit,
             target: None,
-        })
+            object_id_threshold: None,
+            object_ids: None,
+            keep_scene_resident: false,
+            resident_scene: None,
+            #[cfg(feature = "hot_reload")]
+            shader_watcher: None,
+        }
+    }
+
+    /// Watches the `shader` directory of this crate for changes.
+    ///
+    /// This is intended for development: after this call, [Renderer::reload_shaders]
+    /// recompiles the shaders that were edited without rebuilding the crate.
+    #[cfg(feature = "hot_reload")]
+    pub fn watch_shaders(&mut self) -> Result<()> {
+        let shader_dir = std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shader"));
+        self.shader_watcher = Some(shaders::ShaderWatcher::new(shader_dir, &self.shaders)?);
+        Ok(())
+    }
+
+    /// Reloads shaders that changed on disk since the last call.
+    ///
+    /// Only the pipelines whose preprocessed source changed are replaced. If a shader
+    /// fails to compile, its previous pipeline is kept and the error is returned. Returns
+    /// whether any pipeline was replaced; does nothing unless [Renderer::watch_shaders]
+    /// was called.
+    #[cfg(feature = "hot_reload")]
+    pub fn reload_shaders(&mut self, device: &Device) -> Result<bool> {
+        match &mut self.shader_watcher {
+            Some(watcher) => watcher.reload(device, &mut self.engine, &self.shaders),
+            None => Ok(false),
+        }
     }
 
     /// Renders a scene to the target texture.
@@ -77,11 +295,172 @@ impl Renderer {
         width: u32,
         height: u32,
     ) -> Result<()> {
-        let (recording, target) = render::render_full(&scene, &self.shaders, width, height);
+        let keep_resident = self.keep_scene_resident;
+        let resident_scene = &mut self.resident_scene;
+        let (recording, target, object_id_buf) = render::render_full_with(
+            scene,
+            &self.shaders,
+            width,
+            height,
+            self.object_id_threshold,
+            |recording, packed| {
+                if keep_resident {
+                    ResidentScene::update(resident_scene, device, queue, packed)
+                } else {
+                    recording.upload(packed)
+                }
+            },
+        )?;
+        let mut external_resources = vec![ExternalResource::Image(
+            *target.as_image().unwrap(),
+            texture,
+        )];
+        if let (true, Some(resident)) = (keep_resident, &self.resident_scene) {
+            external_resources.push(ExternalResource::Buf(resident.proxy, &resident.buffer));
+        }
+        #[cfg(debug_assertions)]
+        recording.validate(&self.engine, &external_resources)?;
+        let downloads =
+            self.engine
+                .run_recording(device, queue, &recording, &external_resources)?;
+        self.object_ids = object_id_buf.map(|buf| PendingObjectIds {
+            downloads,
+            buf,
+            width,
+            height,
+        });
+        Ok(())
+    }
+
+    /// Enables or disables keeping the scene resident on the GPU between frames.
+    ///
+    /// While enabled, [Renderer::render_to_texture] and [Renderer::render_to_surface]
+    /// keep the packed scene in a persistent buffer and only upload the parts that
+    /// changed since the previous frame, which suits scenes that are mostly static. The
+    /// buffer is only reallocated when the scene outgrows it, or when the scene shrinks
+    /// to a quarter of the buffer or less.
+    pub fn set_resident_scene(&mut self, enabled: bool) {
+        self.keep_scene_resident = enabled;
+        if !enabled {
+            self.resident_scene = None;
+        }
+    }
+
+    /// Enables or disables the object ID output.
+    ///
+    /// While enabled, [Renderer::render_to_texture] and [Renderer::render_to_surface] also
+    /// record, for each pixel, the topmost draw object whose coverage there exceeds
+    /// `alpha_threshold`. Coverage inside a layer is additionally limited by the layer's
+    /// clip and alpha. Read the result with [Renderer::read_object_ids].
+    pub fn set_object_ids(&mut self, alpha_threshold: Option<f32>) {
+        self.object_id_threshold = alpha_threshold;
+        if alpha_threshold.is_none() {
+            self.object_ids = None;
+        }
+    }
+
+    /// Reads back the object IDs of the last frame rendered with the object ID output
+    /// enabled.
+    ///
+    /// Returns `None` if no such frame has been rendered since the last call. This waits
+    /// for the device to finish the frame.
+    pub async fn read_object_ids(&mut self, device: &Device) -> Result<Option<ObjectIds>> {
+        let pending = match self.object_ids.take() {
+            Some(pending) => pending,
+            None => return Ok(None),
+        };
+        let mapped = pending.downloads.map();
+        device.poll(wgpu::Maintain::Wait);
+        let view = mapped.get_mapped(pending.buf).await?;
+        let len = pending.width as usize * pending.height as usize;
+        let ids = bytemuck::cast_slice(&view)[..len].to_vec();
+        Ok(Some(ObjectIds {
+            ids,
+            width: pending.width,
+            height: pending.height,
+        }))
+    }
+
+    /// Records the commands to render a scene to the target texture into the specified
+    /// encoder.
+    ///
+    /// This has the same requirements on the texture as [Renderer::render_to_texture], but
+    /// leaves submission to the caller, so vello rendering can be interleaved with other
+    /// passes. The returned resources must be kept alive until the encoder has been
+    /// submitted. The object ID output is not written, and the scene is uploaded in full
+    /// even if [Renderer::set_resident_scene] is enabled.
+    pub fn encode_to(
+        &mut self,
+        device: &Device,
+        encoder: &mut CommandEncoder,
+        scene: &Scene,
+        texture: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> Result<TransientResources> {
+        let (recording, target, _) =
+            render::render_full(scene, &self.shaders, width, height, None)?;
         let external_resources = [ExternalResource::Image(
             *target.as_image().unwrap(),
             texture,
         )];
+        #[cfg(debug_assertions)]
+        recording.validate(&self.engine, &external_resources)?;
+        let (_, resources) =
+            self.engine
+                .record_into(device, encoder, &recording, &external_resources)?;
+        Ok(resources)
+    }
+
+    /// Returns the options the renderer was created with.
+    pub fn options(&self) -> &RendererOptions {
+        &self.shaders.options
+    }
+
+    /// Captures the GPU work for rendering a scene at the specified size.
+    ///
+    /// The resulting trace can be saved and later run with [Renderer::replay], which
+    /// reproduces the exact commands and buffer contents without the original scene.
+    pub fn capture(&self, scene: &Scene, width: u32, height: u32) -> Result<Trace> {
+        let (recording, target, _) =
+            render::render_full(scene, &self.shaders, width, height, None)?;
+        let target = *target.as_image().unwrap();
+        Ok(Trace {
+            options: self.shaders.options,
+            data: engine::trace::encode(&recording, &self.engine, &self.shaders.options, &target)?,
+            width,
+            height,
+        })
+    }
+
+    /// Replays a captured trace into the target texture.
+    ///
+    /// The texture has the same requirements as for [Renderer::render_to_texture] and
+    /// must have the dimensions reported by the trace. The renderer must have been created
+    /// with the options reported by the trace.
+    pub fn replay(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        trace: &Trace,
+        texture: &TextureView,
+        width: u32,
+        height: u32,
+    ) -> Result<()> {
+        if trace.options != self.shaders.options {
+            return Err(Error::InvalidTrace(
+                "trace was captured with different renderer options".into(),
+            ));
+        }
+        if (width, height) != (trace.width, trace.height) {
+            return Err(Error::InvalidTrace(format!(
+                "trace was captured at {}x{}, but the texture is {width}x{height}",
+                trace.width, trace.height
+            )));
+        }
+        let (recording, target) = engine::trace::decode(&trace.data, &self.engine)?;
+        let external_resources = [ExternalResource::Image(target, texture)];
+        recording.validate(&self.engine, &external_resources)?;
         let _ = self
             .engine
             .run_recording(device, queue, &recording, &external_resources)?;
@@ -150,6 +529,179 @@ impl Renderer {
     }
 }
 
+/// Serialized GPU commands for rendering a scene, used to reproduce rendering issues.
+///
+/// See [Renderer::capture] and [Renderer::replay].
+pub struct Trace {
+    data: Vec<u8>,
+    options: RendererOptions,
+    width: u32,
+    height: u32,
+}
+
+impl Trace {
+    /// Creates a trace from bytes previously returned by [Trace::as_bytes].
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let (options, target) = engine::trace::decode_header(&data)?;
+        Ok(Self {
+            options,
+            width: target.width(),
+            height: target.height(),
+            data,
+        })
+    }
+
+    /// Returns the serialized trace.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the options of the renderer that captured the trace.
+    pub fn options(&self) -> &RendererOptions {
+        &self.options
+    }
+
+    /// Returns the width of the render target.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height of the render target.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// Per-pixel draw object IDs read back from the GPU.
+///
+/// See [Renderer::set_object_ids].
+pub struct ObjectIds {
+    ids: Vec<u32>,
+    width: u32,
+    height: u32,
+}
+
+impl ObjectIds {
+    /// Returns the index of the topmost draw object at the specified pixel, or `None`
+    /// if no object covers it.
+    ///
+    /// The index matches [DrawId::index] for the same scene.
+    pub fn get(&self, x: u32, y: u32) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        match self.ids[y as usize * self.width as usize + x as usize] {
+            u32::MAX => None,
+            id => Some(id as usize),
+        }
+    }
+
+    /// Returns the raw IDs in row-major order, with `u32::MAX` for uncovered pixels.
+    pub fn as_slice(&self) -> &[u32] {
+        &self.ids
+    }
+
+    /// Returns the width of the render target.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Returns the height of the render target.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+}
+
+/// Packed scene kept on the GPU between frames.
+struct ResidentScene {
+    buffer: Buffer,
+    proxy: BufProxy,
+    /// Contents of the buffer, to compare the next frame against.
+    data: Vec<u8>,
+}
+
+impl ResidentScene {
+    /// Size of the blocks that are compared and uploaded; changed blocks next to each
+    /// other are uploaded together.
+    const BLOCK_SIZE: usize = 256;
+
+    /// Updates the resident scene to the packed scene, returning the buffer holding it.
+    fn update(
+        resident: &mut Option<Self>,
+        device: &Device,
+        queue: &Queue,
+        data: Vec<u8>,
+    ) -> BufProxy {
+        // Buffer writes must be aligned, and the blocks are, so the scene must be too.
+        assert_eq!(data.len() % wgpu::COPY_BUFFER_ALIGNMENT as usize, 0);
+        let size = (data.len() as u64).next_power_of_two().max(256);
+        match resident {
+            Some(resident)
+                if data.len() as u64 <= resident.buffer.size()
+                    && size * 4 > resident.buffer.size() =>
+            {
+                resident.write(queue, data);
+                resident.proxy
+            }
+            _ => {
+                let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("resident scene"),
+                    size,
+                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                queue.write_buffer(&buffer, 0, &data);
+                let proxy = BufProxy::new(size);
+                *resident = Some(Self {
+                    buffer,
+                    proxy,
+                    data,
+                });
+                proxy
+            }
+        }
+    }
+
+    /// Uploads the blocks of the packed scene that differ from the previous frame.
+    fn write(&mut self, queue: &Queue, data: Vec<u8>) {
+        for range in changed_ranges(&self.data, &data, Self::BLOCK_SIZE) {
+            queue.write_buffer(&self.buffer, range.start as u64, &data[range]);
+        }
+        self.data = data;
+    }
+}
+
+/// Returns the byte ranges of `new` that differ from `old`, compared in blocks of
+/// `block_size` bytes. Adjacent changed blocks are merged into one range, and blocks
+/// past the end of `old` count as changed.
+fn changed_ranges(old: &[u8], new: &[u8], block_size: usize) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut start = None;
+    for (i, block) in new.chunks(block_size).enumerate() {
+        let offset = i * block_size;
+        let changed = old.get(offset..offset + block.len()) != Some(block);
+        match (changed, start) {
+            (true, None) => start = Some(offset),
+            (false, Some(range_start)) => {
+                ranges.push(range_start..offset);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(range_start) = start {
+        ranges.push(range_start..new.len());
+    }
+    ranges
+}
+
+struct PendingObjectIds {
+    downloads: Downloads,
+    buf: BufProxy,
+    width: u32,
+    height: u32,
+}
+
 struct TargetTexture {
     view: TextureView,
     width: u32,
@@ -279,3 +831,53 @@ impl BlitPipeline {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the changed ranges for blocks of 16 bytes as (start, end) pairs.
+    fn changed(old: &[u8], new: &[u8]) -> Vec<(usize, usize)> {
+        changed_ranges(old, new, 16)
+            .into_iter()
+            .map(|range| (range.start, range.end))
+            .collect()
+    }
+
+    #[test]
+    fn changed_ranges_blocks() {
+        let old = (0..64).collect::<Vec<u8>>();
+        assert_eq!(changed(&old, &old), []);
+
+        let mut new = old.clone();
+        new[20] = 0;
+        assert_eq!(changed(&old, &new), [(16, 32)]);
+
+        // Adjacent blocks are merged, separate ones are not.
+        new[40] = 0;
+        assert_eq!(changed(&old, &new), [(16, 48)]);
+        new[0] = 1;
+        assert_eq!(changed(&old, &new), [(0, 48)]);
+        new[40] = 40;
+        assert_eq!(changed(&old, &new), [(0, 32)]);
+        new[20] = 20;
+        new[63] = 0;
+        assert_eq!(changed(&old, &new), [(0, 16), (48, 64)]);
+    }
+
+    #[test]
+    fn changed_ranges_resize() {
+        let old = (0..64).collect::<Vec<u8>>();
+        // Shrinking only uploads the changed blocks that remain, including a partial
+        // block at the end.
+        assert_eq!(changed(&old, &old[..40]), []);
+        let mut shrunk = old[..40].to_vec();
+        shrunk[36] = 0;
+        assert_eq!(changed(&old, &shrunk), [(32, 40)]);
+
+        // Growing uploads the new blocks, and a partial block at the old end.
+        let grown = (0..100).collect::<Vec<u8>>();
+        assert_eq!(changed(&old, &grown), [(64, 100)]);
+        assert_eq!(changed(&old[..40], &grown), [(32, 100)]);
+    }
+}
@@ -2,7 +2,7 @@ use peniko::{Color, ColorStop, ColorStops};
 
 use std::collections::HashMap;
 
-const N_SAMPLES: usize = 512;
+pub const N_SAMPLES: usize = 512;
 const RETAINED_COUNT: usize = 64;
 
 #[derive(Default)]
@@ -13,6 +13,7 @@ pub struct RampCache {
 }
 
 impl RampCache {
+    #[allow(unused)]
     pub fn advance(&mut self) {
         self.epoch += 1;
         if self.map.len() > RETAINED_COUNT {
@@ -126,11 +127,11 @@ impl ColorF64 {
         ])
     }
 
-    fn to_premul_u32(&self) -> u32 {
-        let a = self.0[3].min(1.0).max(0.0);
-        let r = ((self.0[0] * a).min(1.0).max(0.0) * 255.0) as u32;
-        let g = ((self.0[1] * a).min(1.0).max(0.0) * 255.0) as u32;
-        let b = ((self.0[2] * a).min(1.0).max(0.0) * 255.0) as u32;
+    fn to_premul_u32(self) -> u32 {
+        let a = self.0[3].clamp(0.0, 1.0);
+        let r = ((self.0[0] * a).clamp(0.0, 1.0) * 255.0) as u32;
+        let g = ((self.0[1] * a).clamp(0.0, 1.0) * 255.0) as u32;
+        let b = ((self.0[2] * a).clamp(0.0, 1.0) * 255.0) as u32;
         let a = (a * 255.0) as u32;
         r | (g << 8) | (b << 16) | (a << 24)
     }
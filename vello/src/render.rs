@@ -4,8 +4,8 @@ use bytemuck::{Pod, Zeroable};
 
 use crate::{
     engine::{BufProxy, ImageFormat, ImageProxy, Recording, ResourceProxy},
-    shaders::{self, FullShaders, Shaders},
-    ResourcePatch, Scene,
+    shaders::{FullShaders, Shaders},
+    Error, RendererOptions, ResourcePatch, Result, Scene, SceneStats,
 };
 
 const TAG_MONOID_SIZE: u64 = 12;
@@ -20,9 +20,25 @@ const CLIP_INP_SIZE: u64 = 8;
 const CLIP_BBOX_SIZE: u64 = 16;
 const PATH_SIZE: u64 = 32;
 const DRAW_BBOX_SIZE: u64 = 16;
-const BUMP_SIZE: u64 = 16;
+const BUMP_SIZE: u64 = 20;
 const BIN_HEADER_SIZE: u64 = 8;
 
+// Initial workgroup counts of the dispatches that are sized on the GPU, see
+// shader/shared/indirect.wgsl. The x and y counts are written by the shaders.
+const INDIRECT_ARGS: [u32; 6] = [0, 1, 1, 0, 0, 1];
+const INDIRECT_PATH_COARSE_OFFSET: u64 = 0;
+const INDIRECT_COARSE_OFFSET: u64 = 12;
+
+// Sizes of the intermediate buffers that are not yet computed from the scene.
+const INFO_BIN_DATA_SIZE: u64 = 1 << 20;
+const TILE_BUF_SIZE: u64 = 1 << 20;
+const SEGMENTS_BUF_SIZE: u64 = 1 << 24;
+const PTCL_BUF_SIZE: u64 = 1 << 24;
+
+// Tags for the draw objects that delimit layers. See shader/shared/drawtag.wgsl.
+const DRAWTAG_BEGIN_CLIP: u32 = 0x9;
+const DRAWTAG_END_CLIP: u32 = 0x21;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Default, Zeroable, Pod)]
 struct Config {
@@ -40,6 +56,8 @@ struct Config {
     drawdata_base: u32,
     transform_base: u32,
     linewidth_base: u32,
+    pathseg_delta_base: u32,
+    object_id_threshold: f32,
 }
 
 #[repr(C)]
@@ -65,10 +83,11 @@ pub const fn next_multiple_of(val: u32, rhs: u32) -> u32 {
 #[allow(unused)]
 fn render(scene: &Scene, shaders: &Shaders) -> (Recording, BufProxy) {
     let mut recording = Recording::default();
+    let options = &shaders.options;
     let data = scene.data();
     let n_pathtag = data.tag_stream.len();
-    let pathtag_padded = align_up(n_pathtag, 4 * shaders::PATHTAG_REDUCE_WG);
-    let pathtag_wgs = pathtag_padded / (4 * shaders::PATHTAG_REDUCE_WG as usize);
+    let pathtag_padded = align_up(n_pathtag, 4 * options.pathtag_reduce_wg);
+    let pathtag_wgs = pathtag_padded / (4 * options.pathtag_reduce_wg as usize);
     let mut scene: Vec<u8> = Vec::with_capacity(pathtag_padded);
     let pathtag_base = size_to_words(scene.len());
     scene.extend(&data.tag_stream);
@@ -79,8 +98,8 @@ fn render(scene: &Scene, shaders: &Shaders) -> (Recording, BufProxy) {
     let config = Config {
         width_in_tiles: 64,
         height_in_tiles: 64,
-        target_width: 64 * 16,
-        target_height: 64 * 16,
+        target_width: 64 * options.tile_width,
+        target_height: 64 * options.tile_height,
         pathtag_base,
         pathdata_base,
         ..Default::default()
@@ -97,15 +116,14 @@ fn render(scene: &Scene, shaders: &Shaders) -> (Recording, BufProxy) {
     );
 
     let tagmonoid_buf =
-        BufProxy::new(pathtag_wgs as u64 * shaders::PATHTAG_REDUCE_WG as u64 * TAG_MONOID_SIZE);
+        BufProxy::new(pathtag_wgs as u64 * options.pathtag_reduce_wg as u64 * TAG_MONOID_SIZE);
     recording.dispatch(
         shaders.pathtag_scan,
         (pathtag_wgs as u32, 1, 1),
         [config_buf, scene_buf, reduced_buf, tagmonoid_buf],
     );
 
-    let path_coarse_wgs =
-        (n_pathtag as u32 + shaders::PATH_COARSE_WG - 1) / shaders::PATH_COARSE_WG;
+    let path_coarse_wgs = (n_pathtag as u32 + options.path_coarse_wg - 1) / options.path_coarse_wg;
     // TODO: more principled size calc
     let tiles_buf = BufProxy::new(4097 * 8);
     let segments_buf = BufProxy::new(256 * 24);
@@ -126,7 +144,8 @@ fn render(scene: &Scene, shaders: &Shaders) -> (Recording, BufProxy) {
         (config.height_in_tiles, 1, 1),
         [config_buf, tiles_buf],
     );
-    let out_buf_size = config.width_in_tiles * config.height_in_tiles * 256;
+    let out_buf_size =
+        config.width_in_tiles * config.height_in_tiles * options.tile_width * options.tile_height;
     let out_buf = BufProxy::new(out_buf_size as u64);
     recording.dispatch(
         shaders.fine,
@@ -138,16 +157,48 @@ fn render(scene: &Scene, shaders: &Shaders) -> (Recording, BufProxy) {
     (recording, out_buf)
 }
 
+/// Records the full pipeline for rendering a scene.
+///
+/// Returns the recording and the target image. If `object_id_threshold` is given,
+/// fine also writes the path index of the topmost draw object whose coverage exceeds
+/// the threshold at each pixel into a buffer of `width * height` u32s, which is
+/// returned as a download.
 pub fn render_full(
     scene: &Scene,
     shaders: &FullShaders,
     width: u32,
     height: u32,
-) -> (Recording, ResourceProxy) {
+    object_id_threshold: Option<f32>,
+) -> Result<(Recording, ResourceProxy, Option<BufProxy>)> {
+    render_full_with(
+        scene,
+        shaders,
+        width,
+        height,
+        object_id_threshold,
+        |recording, packed| recording.upload(packed),
+    )
+}
+
+/// Records the full pipeline like [render_full], but lets the caller provide the buffer
+/// holding the packed scene.
+///
+/// `upload_scene` receives the packed scene and returns the buffer the shaders read it
+/// from, which may be larger than the data.
+pub fn render_full_with(
+    scene: &Scene,
+    shaders: &FullShaders,
+    width: u32,
+    height: u32,
+    object_id_threshold: Option<f32>,
+    upload_scene: impl FnOnce(&mut Recording, Vec<u8>) -> BufProxy,
+) -> Result<(Recording, ResourceProxy, Option<BufProxy>)> {
     let mut recording = Recording::default();
     let mut ramps = crate::ramp::RampCache::default();
     let mut drawdata_patches: Vec<(usize, u32)> = vec![];
+    let options = &shaders.options;
     let data = scene.data();
+    check_layers(&data.drawtag_stream)?;
     let stop_data = &data.resources.stops;
     for patch in &data.resources.patches {
         match patch {
@@ -173,7 +224,8 @@ pub fn render_full(
         ResourceProxy::Image(recording.upload_image(width, height, ImageFormat::Rgba8, data))
     };
     let n_pathtag = data.tag_stream.len();
-    let pathtag_padded = align_up(n_pathtag, 4 * shaders::PATHTAG_REDUCE_WG);
+    let pathtag_padded = align_up(n_pathtag, 4 * options.pathtag_reduce_wg);
+    let sizes = BufferSizes::new(options, n_pathtag, data.n_path, data.n_clip);
     // TODO: can compute size accurately, avoid reallocation
     let mut scene: Vec<u8> = Vec::with_capacity(pathtag_padded);
     let pathtag_base = size_to_words(scene.len());
@@ -205,19 +257,29 @@ pub fn render_full(
     scene.extend(bytemuck::cast_slice(&data.transform_stream));
     let linewidth_base = size_to_words(scene.len());
     scene.extend(bytemuck::cast_slice(&data.linewidth_stream));
+    let pathseg_delta_base = size_to_words(scene.len());
+    scene.extend(bytemuck::cast_slice(&data.pathseg_delta_stream));
     let n_path = data.n_path;
     // TODO: calculate for real when we do rectangles
     let n_drawobj = n_path;
     let n_clip = data.n_clip;
     let bin_data_start = n_drawobj * MAX_DRAWINFO_SIZE as u32;
+    let info_size = bin_data_start as u64 * 4;
+    if info_size > INFO_BIN_DATA_SIZE {
+        return Err(Error::BufferOverflow {
+            buffer: "info_bin_data",
+            required: info_size,
+            capacity: INFO_BIN_DATA_SIZE,
+        });
+    }
 
-    let new_width = next_multiple_of(width, 16);
-    let new_height = next_multiple_of(height, 16);
+    let new_width = next_multiple_of(width, options.tile_width);
+    let new_height = next_multiple_of(height, options.tile_height);
 
     let config = Config {
         // TODO: Replace with div_ceil once stable
-        width_in_tiles: new_width / 16,
-        height_in_tiles: new_height / 16,
+        width_in_tiles: new_width / options.tile_width,
+        height_in_tiles: new_height / options.tile_height,
         target_width: width,
         target_height: height,
         n_drawobj,
@@ -230,13 +292,21 @@ pub fn render_full(
         drawdata_base,
         transform_base,
         linewidth_base,
+        pathseg_delta_base,
+        object_id_threshold: object_id_threshold.map_or(-1.0, |t| t.max(0.0)),
     };
+    let width_in_bins = (config.width_in_tiles + options.n_tile_x - 1) / options.n_tile_x;
+    let height_in_bins = (config.height_in_tiles + options.n_tile_y - 1) / options.n_tile_y;
+    // Binning and coarse rasterization keep one entry per bin in a workgroup array.
+    if width_in_bins * height_in_bins > options.n_tile() {
+        return Err(Error::TargetTooLarge { width, height });
+    }
     // println!("{:?}", config);
-    let scene_buf = ResourceProxy::Buf(recording.upload(scene));
+    let scene_buf = ResourceProxy::Buf(upload_scene(&mut recording, scene));
     let config_buf = ResourceProxy::Buf(recording.upload_uniform(bytemuck::bytes_of(&config)));
 
-    let pathtag_wgs = pathtag_padded / (4 * shaders::PATHTAG_REDUCE_WG as usize);
-    let reduced_buf = ResourceProxy::new_buf(pathtag_wgs as u64 * TAG_MONOID_FULL_SIZE);
+    let pathtag_wgs = pathtag_padded / (4 * options.pathtag_reduce_wg as usize);
+    let reduced_buf = ResourceProxy::new_buf(sizes.pathtag_reduced);
     // TODO: really only need pathtag_wgs - 1
     recording.dispatch(
         shaders.pathtag_reduce,
@@ -244,44 +314,49 @@ pub fn render_full(
         [config_buf, scene_buf, reduced_buf],
     );
 
-    let tagmonoid_buf = ResourceProxy::new_buf(
-        pathtag_wgs as u64 * shaders::PATHTAG_REDUCE_WG as u64 * TAG_MONOID_FULL_SIZE,
-    );
+    let tagmonoid_buf = ResourceProxy::new_buf(sizes.tag_monoids);
     recording.dispatch(
         shaders.pathtag_scan,
         (pathtag_wgs as u32, 1, 1),
         [config_buf, scene_buf, reduced_buf, tagmonoid_buf],
     );
-    let drawobj_wgs = (n_drawobj + shaders::PATH_BBOX_WG - 1) / shaders::PATH_BBOX_WG;
-    let path_bbox_buf = ResourceProxy::new_buf(n_path as u64 * PATH_BBOX_SIZE);
+    // Draw objects are processed a bin's worth of tiles at a time, see RendererOptions.
+    let n_tile = options.n_tile();
+    let drawobj_wgs = (n_drawobj + n_tile - 1) / n_tile;
+    let path_wgs = (n_path + options.path_bbox_wg - 1) / options.path_bbox_wg;
+    let path_bbox_buf = ResourceProxy::new_buf(sizes.path_bboxes);
     recording.dispatch(
         shaders.bbox_clear,
-        (drawobj_wgs, 1, 1),
+        (path_wgs, 1, 1),
         [config_buf, path_bbox_buf],
     );
-    let cubic_buf = ResourceProxy::new_buf(n_pathtag as u64 * CUBIC_SIZE);
-    let path_coarse_wgs =
-        (n_pathtag as u32 + shaders::PATH_COARSE_WG - 1) / shaders::PATH_COARSE_WG;
+    let cubic_buf = ResourceProxy::new_buf(sizes.cubics);
+    let bump_buf = BufProxy::new(sizes.bump);
+    recording.clear_all(bump_buf);
+    let bump_buf = ResourceProxy::Buf(bump_buf);
+    let indirect_buf = recording.upload(bytemuck::bytes_of(&INDIRECT_ARGS));
+    let pathseg_wgs = (n_pathtag as u32 + options.path_coarse_wg - 1) / options.path_coarse_wg;
     recording.dispatch(
         shaders.pathseg,
-        (path_coarse_wgs, 1, 1),
+        (pathseg_wgs, 1, 1),
         [
             config_buf,
             scene_buf,
             tagmonoid_buf,
             path_bbox_buf,
             cubic_buf,
+            bump_buf,
         ],
     );
-    let draw_reduced_buf = ResourceProxy::new_buf(drawobj_wgs as u64 * DRAWMONOID_SIZE);
+    let draw_reduced_buf = ResourceProxy::new_buf(sizes.draw_reduced);
     recording.dispatch(
         shaders.draw_reduce,
         (drawobj_wgs, 1, 1),
         [config_buf, scene_buf, draw_reduced_buf],
     );
-    let draw_monoid_buf = ResourceProxy::new_buf(n_drawobj as u64 * DRAWMONOID_SIZE);
-    let info_bin_data_buf = ResourceProxy::new_buf(1 << 20);
-    let clip_inp_buf = ResourceProxy::new_buf(data.n_clip as u64 * CLIP_INP_SIZE);
+    let draw_monoid_buf = ResourceProxy::new_buf(sizes.draw_monoids);
+    let info_bin_data_buf = ResourceProxy::new_buf(sizes.info_bin_data);
+    let clip_inp_buf = ResourceProxy::new_buf(sizes.clip_inputs);
     recording.dispatch(
         shaders.draw_leaf,
         (drawobj_wgs, 1, 1),
@@ -295,10 +370,11 @@ pub fn render_full(
             clip_inp_buf,
         ],
     );
-    let clip_el_buf = ResourceProxy::new_buf(data.n_clip as u64 * CLIP_EL_SIZE);
-    let clip_bic_buf =
-        ResourceProxy::new_buf((n_clip / shaders::CLIP_REDUCE_WG) as u64 * CLIP_BIC_SIZE);
-    let clip_wg_reduce = n_clip.saturating_sub(1) / shaders::CLIP_REDUCE_WG;
+    let clip_el_buf = BufProxy::new(sizes.clip_els);
+    let clip_reduce_wg = options.clip_reduce_wg;
+    let clip_bic_buf = BufProxy::new(sizes.clip_bics);
+    let clip_wg_reduce = n_clip.saturating_sub(1) / clip_reduce_wg;
+    let clip_wg = (n_clip + clip_reduce_wg - 1) / clip_reduce_wg;
     if clip_wg_reduce > 0 {
         recording.dispatch(
             shaders.clip_reduce,
@@ -307,13 +383,19 @@ pub fn render_full(
                 config_buf,
                 clip_inp_buf,
                 path_bbox_buf,
-                clip_bic_buf,
-                clip_el_buf,
+                ResourceProxy::Buf(clip_bic_buf),
+                ResourceProxy::Buf(clip_el_buf),
             ],
         );
+    } else if clip_wg > 0 {
+        // clip_leaf binds the reduction outputs even when a single workgroup
+        // doesn't need them.
+        recording.clear_all(clip_bic_buf);
+        recording.clear_all(clip_el_buf);
     }
-    let clip_wg = (n_clip + shaders::CLIP_REDUCE_WG - 1) / shaders::CLIP_REDUCE_WG;
-    let clip_bbox_buf = ResourceProxy::new_buf(n_clip as u64 * CLIP_BBOX_SIZE);
+    let clip_bic_buf = ResourceProxy::Buf(clip_bic_buf);
+    let clip_el_buf = ResourceProxy::Buf(clip_el_buf);
+    let clip_bbox_buf = BufProxy::new(sizes.clip_bboxes);
     if clip_wg > 0 {
         recording.dispatch(
             shaders.clip_leaf,
@@ -325,17 +407,16 @@ pub fn render_full(
                 clip_bic_buf,
                 clip_el_buf,
                 draw_monoid_buf,
-                clip_bbox_buf,
+                ResourceProxy::Buf(clip_bbox_buf),
             ],
         );
+    } else {
+        // Binning still reads clip bounding boxes when the scene has no clips.
+        recording.clear_all(clip_bbox_buf);
     }
-    let draw_bbox_buf = ResourceProxy::new_buf(n_path as u64 * DRAW_BBOX_SIZE);
-    let bump_buf = BufProxy::new(BUMP_SIZE);
-    let width_in_bins = (config.width_in_tiles + 15) / 16;
-    let height_in_bins = (config.height_in_tiles + 15) / 16;
-    let bin_header_buf = ResourceProxy::new_buf((256 * drawobj_wgs) as u64 * BIN_HEADER_SIZE);
-    recording.clear_all(bump_buf);
-    let bump_buf = ResourceProxy::Buf(bump_buf);
+    let clip_bbox_buf = ResourceProxy::Buf(clip_bbox_buf);
+    let draw_bbox_buf = ResourceProxy::new_buf(sizes.draw_bboxes);
+    let bin_header_buf = ResourceProxy::new_buf(sizes.bin_headers);
     recording.dispatch(
         shaders.binning,
         (drawobj_wgs, 1, 1),
@@ -348,14 +429,11 @@ pub fn render_full(
             bump_buf,
             info_bin_data_buf,
             bin_header_buf,
+            ResourceProxy::Buf(indirect_buf),
         ],
     );
-    // Note: this only needs to be rounded up because of the workaround to store the tile_offset
-    // in storage rather than workgroup memory.
-    let n_path_aligned = align_up(n_path as usize, 256);
-    let path_buf = ResourceProxy::new_buf(n_path_aligned as u64 * PATH_SIZE);
-    let tile_buf = ResourceProxy::new_buf(1 << 20);
-    let path_wgs = (n_path + shaders::PATH_BBOX_WG - 1) / shaders::PATH_BBOX_WG;
+    let path_buf = ResourceProxy::new_buf(sizes.paths);
+    let tile_buf = ResourceProxy::new_buf(sizes.tiles);
     recording.dispatch(
         shaders.tile_alloc,
         (path_wgs, 1, 1),
@@ -366,17 +444,19 @@ pub fn render_full(
             bump_buf,
             path_buf,
             tile_buf,
+            ResourceProxy::Buf(indirect_buf),
         ],
     );
 
-    let segments_buf = ResourceProxy::new_buf(1 << 24);
-    recording.dispatch(
+    // path_coarse runs on the cubics counted by pathseg, and coarse on the bins
+    // touched by draw objects.
+    let segments_buf = ResourceProxy::new_buf(sizes.segments);
+    recording.dispatch_indirect(
         shaders.path_coarse,
-        (path_coarse_wgs, 1, 1),
+        indirect_buf,
+        INDIRECT_PATH_COARSE_OFFSET,
         [
             config_buf,
-            scene_buf,
-            tagmonoid_buf,
             cubic_buf,
             path_buf,
             bump_buf,
@@ -389,10 +469,11 @@ pub fn render_full(
         (path_wgs, 1, 1),
         [config_buf, path_buf, tile_buf],
     );
-    let ptcl_buf = ResourceProxy::new_buf(1 << 24);
-    recording.dispatch(
+    let ptcl_buf = ResourceProxy::new_buf(sizes.ptcl);
+    recording.dispatch_indirect(
         shaders.coarse,
-        (width_in_bins, height_in_bins, 1),
+        indirect_buf,
+        INDIRECT_COARSE_OFFSET,
         [
             config_buf,
             scene_buf,
@@ -406,6 +487,10 @@ pub fn render_full(
         ],
     );
     let out_image = ImageProxy::new(width, height, ImageFormat::Rgba8);
+    let object_id_buf = match object_id_threshold {
+        Some(_) => BufProxy::new((width as u64 * height as u64 * 4).max(4)),
+        None => BufProxy::new(4),
+    };
     recording.dispatch(
         shaders.fine,
         (config.width_in_tiles, config.height_in_tiles, 1),
@@ -417,11 +502,138 @@ pub fn render_full(
             ptcl_buf,
             gradient_image,
             info_bin_data_buf,
+            ResourceProxy::Buf(object_id_buf),
+            ResourceProxy::Buf(indirect_buf),
         ],
     );
-    (recording, ResourceProxy::Image(out_image))
+    let object_ids = object_id_threshold.map(|_| {
+        recording.download(object_id_buf);
+        object_id_buf
+    });
+    Ok((recording, ResourceProxy::Image(out_image), object_ids))
+}
+
+/// Sizes in bytes of the intermediate buffers that [render_full] allocates.
+struct BufferSizes {
+    pathtag_reduced: u64,
+    tag_monoids: u64,
+    path_bboxes: u64,
+    cubics: u64,
+    draw_reduced: u64,
+    draw_monoids: u64,
+    info_bin_data: u64,
+    clip_inputs: u64,
+    clip_els: u64,
+    clip_bics: u64,
+    clip_bboxes: u64,
+    draw_bboxes: u64,
+    bump: u64,
+    indirect: u64,
+    bin_headers: u64,
+    paths: u64,
+    tiles: u64,
+    segments: u64,
+    ptcl: u64,
+}
+
+impl BufferSizes {
+    fn new(options: &RendererOptions, n_pathtag: usize, n_path: u32, n_clip: u32) -> Self {
+        let pathtag_padded = align_up(n_pathtag, 4 * options.pathtag_reduce_wg);
+        let pathtag_wgs = (pathtag_padded / (4 * options.pathtag_reduce_wg as usize)) as u64;
+        // TODO: calculate for real when we do rectangles
+        let n_drawobj = n_path;
+        let n_tile = options.n_tile();
+        let drawobj_wgs = ((n_drawobj + n_tile - 1) / n_tile) as u64;
+        // Note: this only needs to be rounded up because of the workaround to store the
+        // tile_offset in storage rather than workgroup memory.
+        let n_path_aligned = align_up(n_path as usize, options.path_bbox_wg) as u64;
+        let n_path = n_path as u64;
+        let n_clip = n_clip as u64;
+        Self {
+            pathtag_reduced: pathtag_wgs * TAG_MONOID_FULL_SIZE,
+            tag_monoids: pathtag_wgs * options.pathtag_reduce_wg as u64 * TAG_MONOID_FULL_SIZE,
+            path_bboxes: n_path * PATH_BBOX_SIZE,
+            // pathseg packs the cubics of the segments, which are at most one per tag.
+            cubics: n_pathtag as u64 * CUBIC_SIZE,
+            draw_reduced: drawobj_wgs * DRAWMONOID_SIZE,
+            draw_monoids: n_drawobj as u64 * DRAWMONOID_SIZE,
+            info_bin_data: INFO_BIN_DATA_SIZE,
+            clip_inputs: n_clip * CLIP_INP_SIZE,
+            clip_els: n_clip * CLIP_EL_SIZE,
+            clip_bics: (n_clip / options.clip_reduce_wg as u64) * CLIP_BIC_SIZE,
+            clip_bboxes: n_clip * CLIP_BBOX_SIZE,
+            draw_bboxes: n_path * DRAW_BBOX_SIZE,
+            bump: BUMP_SIZE,
+            indirect: std::mem::size_of_val(&INDIRECT_ARGS) as u64,
+            bin_headers: n_tile as u64 * drawobj_wgs * BIN_HEADER_SIZE,
+            paths: n_path_aligned * PATH_SIZE,
+            tiles: TILE_BUF_SIZE,
+            segments: SEGMENTS_BUF_SIZE,
+            ptcl: PTCL_BUF_SIZE,
+        }
+    }
+}
+
+/// Returns the sizes of the buffers and images that [render_full] allocates for a scene
+/// with the given statistics.
+pub fn buffer_sizes(
+    stats: &SceneStats,
+    options: &RendererOptions,
+    width: u32,
+    height: u32,
+) -> Vec<(&'static str, u64)> {
+    let streams = &stats.stream_sizes;
+    let pathtag_padded = align_up(streams.tag, 4 * options.pathtag_reduce_wg);
+    let scene_size = pathtag_padded + streams.total() - streams.tag - streams.stops;
+    let sizes = BufferSizes::new(options, streams.tag, stats.n_path, stats.n_clip);
+    let gradient_size = if stats.n_ramp == 0 {
+        4
+    } else {
+        (crate::ramp::N_SAMPLES * stats.n_ramp * 4) as u64
+    };
+    vec![
+        ("scene", scene_size as u64),
+        ("config", std::mem::size_of::<Config>() as u64),
+        ("pathtag_reduced", sizes.pathtag_reduced),
+        ("tag_monoids", sizes.tag_monoids),
+        ("path_bboxes", sizes.path_bboxes),
+        ("cubics", sizes.cubics),
+        ("draw_reduced", sizes.draw_reduced),
+        ("draw_monoids", sizes.draw_monoids),
+        ("info_bin_data", sizes.info_bin_data),
+        ("clip_inputs", sizes.clip_inputs),
+        ("clip_els", sizes.clip_els),
+        ("clip_bics", sizes.clip_bics),
+        ("clip_bboxes", sizes.clip_bboxes),
+        ("draw_bboxes", sizes.draw_bboxes),
+        ("bump", sizes.bump),
+        ("indirect", sizes.indirect),
+        ("bin_headers", sizes.bin_headers),
+        ("paths", sizes.paths),
+        ("tiles", sizes.tiles),
+        ("segments", sizes.segments),
+        ("ptcl", sizes.ptcl),
+        ("gradients", gradient_size),
+        ("target", width as u64 * height as u64 * 4),
+    ]
+}
+
+/// Verifies that every begin clip in the draw tag stream has a matching end clip.
+fn check_layers(drawtags: &[u32]) -> Result<()> {
+    let mut depth = 0u32;
+    for tag in drawtags {
+        match *tag {
+            DRAWTAG_BEGIN_CLIP => depth += 1,
+            DRAWTAG_END_CLIP => depth = depth.checked_sub(1).ok_or(Error::UnbalancedLayers)?,
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(Error::UnbalancedLayers);
+    }
+    Ok(())
 }
 
 pub fn align_up(len: usize, alignment: u32) -> usize {
-    len + (len.wrapping_neg() & alignment as usize - 1)
+    len + (len.wrapping_neg() & (alignment as usize - 1))
 }
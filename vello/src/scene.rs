@@ -18,12 +18,33 @@ use peniko::kurbo::{Affine, PathEl, Point, Rect, Shape};
 use peniko::{BlendMode, BrushRef, ColorStop, Fill, Stroke};
 
 use bytemuck::{Pod, Zeroable};
+use std::collections::HashMap;
 use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::Error;
+
+mod bounds;
+pub mod decode;
+mod diagnostics;
+mod hit_test;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod serialize;
+mod stats;
+
+pub use diagnostics::{Diagnostic, DiagnosticKind};
+pub use hit_test::DrawId;
+pub use stats::{GpuMemory, SceneStats, StreamSizes};
 
 /// Raw data streams describing an encoded scene.
 #[derive(Default)]
 pub struct SceneData {
     pub transform_stream: Vec<[f32; 6]>,
+    /// Offset in 32-bit words added to the path segment offsets computed from the tag
+    /// stream, for the segments using the transform at the same index. This is
+    /// nonzero where instances share path data.
+    pub pathseg_delta_stream: Vec<i32>,
     pub tag_stream: Vec<u8>,
     pub pathseg_stream: Vec<u8>,
     pub linewidth_stream: Vec<f32>,
@@ -33,15 +54,57 @@ pub struct SceneData {
     pub n_pathseg: u32,
     pub n_clip: u32,
     pub resources: ResourceBundle,
+    /// Tag of each draw object, as set with [SceneBuilder::set_tag]. May be shorter
+    /// than the draw tag stream, in which case the remaining objects have tag 0.
+    pub user_tags: Vec<u32>,
+    /// Size of the path segment data in 32-bit words as computed from the tag stream,
+    /// which counts shared data once per instance.
+    pathseg_words: usize,
+    /// Offset in 32-bit words of the path data of each instanced fragment, by id.
+    instances: HashMap<u64, usize>,
+    retained: Vec<RetainedFragment>,
 }
 
 impl SceneData {
+    /// Returns an iterator over the paths and draw objects encoded in the streams.
+    pub fn decode(&self) -> decode::Decoder<'_> {
+        decode::Decoder::new(self)
+    }
+
+    /// Returns an iterator over the builder commands that reproduce the encoded
+    /// streams.
+    ///
+    /// Replaying the commands into a [SceneBuilder] yields the same streams for scenes
+    /// and fragments built with the fill, stroke and layer methods. Appended fragments
+    /// are flattened into their individual commands.
+    pub fn commands(&self) -> decode::Commands<'_> {
+        decode::Commands::new(self)
+    }
+
+    /// Returns counts of the encoded objects and the sizes of the streams.
+    ///
+    /// See [SceneStats::gpu_memory] for the memory needed to render them.
+    pub fn stats(&self) -> SceneStats {
+        stats::stats(self)
+    }
+
+    /// Returns a human readable listing of the encoded streams.
+    pub fn dump(&self) -> String {
+        decode::dump(self)
+    }
+
     fn is_empty(&self) -> bool {
         self.pathseg_stream.is_empty()
     }
 
+    /// Returns the delta for the segments encoded next.
+    fn pathseg_delta(&self) -> i32 {
+        (self.pathseg_stream.len() / 4) as i32 - self.pathseg_words as i32
+    }
+
     fn reset(&mut self, is_fragment: bool) {
         self.transform_stream.clear();
+        self.pathseg_delta_stream.clear();
         self.tag_stream.clear();
         self.pathseg_stream.clear();
         self.linewidth_stream.clear();
@@ -51,15 +114,40 @@ impl SceneData {
         self.n_pathseg = 0;
         self.n_clip = 0;
         self.resources.clear();
+        self.user_tags.clear();
+        self.pathseg_words = 0;
+        self.instances.clear();
+        self.retained.clear();
         if !is_fragment {
             self.transform_stream.push([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+            self.pathseg_delta_stream.push(0);
             self.linewidth_stream.push(-1.0);
         }
     }
 
-    fn append(&mut self, other: &SceneData, transform: &Option<Affine>) {
+    /// Appends the streams of another scene.
+    ///
+    /// If `shared` is given, the path segment data of `other` is not copied; instead
+    /// its segments refer to a previous copy at the given offset in 32-bit words.
+    fn append(&mut self, other: &SceneData, transform: &Option<Affine>, shared: Option<usize>) {
         let stops_base = self.resources.stops.len();
         let drawdata_base = self.drawdata_stream.len();
+        let pathseg_start = match shared {
+            Some(start) => start,
+            None => {
+                let start = self.pathseg_stream.len() / 4;
+                self.pathseg_stream.extend_from_slice(&other.pathseg_stream);
+                start
+            }
+        };
+        let delta_base = pathseg_start as i32 - self.pathseg_words as i32;
+        self.pathseg_delta_stream.extend(
+            other
+                .pathseg_delta_stream
+                .iter()
+                .map(|delta| delta + delta_base),
+        );
+        self.pathseg_words += other.pathseg_words;
         if let Some(transform) = *transform {
             self.transform_stream.extend(
                 other
@@ -72,9 +160,12 @@ impl SceneData {
                 .extend_from_slice(&other.transform_stream);
         }
         self.tag_stream.extend_from_slice(&other.tag_stream);
-        self.pathseg_stream.extend_from_slice(&other.pathseg_stream);
         self.linewidth_stream
             .extend_from_slice(&other.linewidth_stream);
+        self.user_tags.resize(self.drawtag_stream.len(), 0);
+        self.user_tags.extend_from_slice(&other.user_tags);
+        self.user_tags
+            .resize(self.drawtag_stream.len() + other.drawtag_stream.len(), 0);
         self.drawtag_stream.extend_from_slice(&other.drawtag_stream);
         self.drawdata_stream
             .extend_from_slice(&other.drawdata_stream);
@@ -110,16 +201,116 @@ impl Scene {
         Self::default()
     }
 
+    /// Creates a scene from fragments, each appended with an optional transform.
+    ///
+    /// This is the same as appending the fragments in order with
+    /// [SceneBuilder::append], but copies the streams on multiple threads.
+    #[cfg(feature = "rayon")]
+    pub fn from_fragments_parallel(fragments: &[(&SceneFragment, Option<Affine>)]) -> Self {
+        let mut scene = Self::new();
+        let mut builder = SceneBuilder::for_scene(&mut scene);
+        builder.append_many(fragments);
+        builder.finish();
+        scene
+    }
+
     /// Returns the raw encoded scene data streams.
     pub fn data(&self) -> &SceneData {
         &self.data
     }
+
+    /// Returns the bounding box of the visible content in pixels, or `None` if the
+    /// scene draws nothing.
+    ///
+    /// This matches the bounds the GPU computes for binning: paths are bounded by their
+    /// transformed control points, strokes are inflated by half their line width, the
+    /// result is rounded out to whole pixels and intersected with enclosing layers.
+    pub fn bounding_box(&self) -> Option<Rect> {
+        bounds::bounding_box(&self.data)
+    }
+
+    /// Returns the topmost draw object under a point in pixels, if any.
+    ///
+    /// Fills are hit using the non-zero winding rule, strokes within half their line
+    /// width of the path, and layers hide the objects they contain outside their shape.
+    /// Layers themselves are never hit.
+    pub fn hit_test(&self, point: Point) -> Option<DrawId> {
+        hit_test::hit_test_all(&self.data, point).into_iter().next()
+    }
+
+    /// Returns all draw objects under a point in pixels, topmost first.
+    ///
+    /// See [Scene::hit_test] for how objects are tested.
+    pub fn hit_test_all(&self, point: Point) -> Vec<DrawId> {
+        hit_test::hit_test_all(&self.data, point)
+    }
+
+    /// Sets the transform of a fragment appended with [SceneBuilder::append_retained].
+    ///
+    /// This replaces the transform given when the fragment was appended and only
+    /// rewrites the transforms of the fragment; its path data is left untouched.
+    pub fn set_transform(&mut self, handle: FragmentHandle, transform: Affine) {
+        if let Some(retained) = self.data.retained.get(handle.0) {
+            let stream = &mut self.data.transform_stream[retained.transforms.clone()];
+            for (dst, src) in stream.iter_mut().zip(&retained.base_transforms) {
+                *dst = affine_to_f32(&(transform * affine_from_f32(src)));
+            }
+        }
+    }
+
+    /// Sets the opacity of a fragment appended with [SceneBuilder::append_retained].
+    ///
+    /// The opacity is applied to the colors of each draw object in the fragment, so
+    /// overlapping objects are not composited as a group.
+    pub fn set_alpha(&mut self, handle: FragmentHandle, alpha: f32) {
+        if let Some(retained) = self.data.retained.get(handle.0) {
+            let alpha = alpha.clamp(0.0, 1.0);
+            for &(offset, rgba_color) in &retained.colors {
+                let rgba_color = u32::from_be_bytes(
+                    rgba_color
+                        .to_be_bytes()
+                        .map(|channel| (channel as f32 * alpha).round() as u8),
+                );
+                self.data.drawdata_stream[offset..offset + 4]
+                    .copy_from_slice(bytemuck::bytes_of(&FillColor { rgba_color }));
+            }
+            let stops = &mut self.data.resources.stops[retained.stops.clone()];
+            for (dst, src) in stops.iter_mut().zip(&retained.base_stops) {
+                dst.color.a = (src.color.a as f32 * alpha).round() as u8;
+            }
+        }
+    }
+}
+
+/// Handle to a fragment appended with [SceneBuilder::append_retained].
+///
+/// The handle remains valid until the scene is rebuilt.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FragmentHandle(usize);
+
+/// Location of the parameters of a retained fragment in the scene streams.
+struct RetainedFragment {
+    /// Range of the fragment's entries in the transform stream, starting with the
+    /// transform of any segments that precede the first transform of the fragment.
+    transforms: Range<usize>,
+    /// Transforms of the range before the fragment transform was applied.
+    base_transforms: Vec<[f32; 6]>,
+    /// Byte offsets in the draw data stream and premultiplied colors of the solid
+    /// fills, before the opacity was applied.
+    colors: Vec<(usize, u32)>,
+    /// Range of the fragment's gradient stops in the resources.
+    stops: Range<usize>,
+    /// Gradient stops of the range before the opacity was applied.
+    base_stops: Vec<ColorStop>,
 }
 
 /// Encoded definition of a scene fragment and associated resources.
 #[derive(Default)]
 pub struct SceneFragment {
     data: SceneData,
+    /// Identifies the content of the fragment for instancing. Renewed whenever the
+    /// fragment is rebuilt.
+    id: u64,
 }
 
 impl SceneFragment {
@@ -128,18 +319,63 @@ impl SceneFragment {
         Self::default()
     }
 
+    fn next_id() -> u64 {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Returns the raw encoded fragment data streams.
+    pub fn data(&self) -> &SceneData {
+        &self.data
+    }
+
     /// Returns true if the fragment does not contain any paths.
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
 
+    /// Returns the bounding box of the visible content in the coordinate space of the
+    /// fragment, or `None` if the fragment draws nothing.
+    ///
+    /// See [Scene::bounding_box] for how the bounds are computed.
+    pub fn bounding_box(&self) -> Option<Rect> {
+        bounds::bounding_box(&self.data)
+    }
+
     /// Returns the the entire sequence of points in the scene fragment.
-    pub fn points(&self) -> &[[f32; 2]] {
-        if self.is_empty() {
-            &[]
-        } else {
-            bytemuck::cast_slice(&self.data.pathseg_stream)
+    ///
+    /// The points are decoded from the path segments, as paths may be encoded with
+    /// 16-bit integer coordinates.
+    pub fn points(&self) -> Vec<[f32; 2]> {
+        let mut points = vec![];
+        let mut subpath_start = true;
+        for object in self.data.decode() {
+            for segment in &object.segments {
+                // Segments share their start point with the end of the previous one,
+                // except at the start of a subpath.
+                let skip = if subpath_start { 0 } else { 1 };
+                points.extend_from_slice(&segment.segment.points()[skip..]);
+                subpath_start = segment.subpath_end;
+            }
         }
+        points
+    }
+
+    /// Serializes the fragment, including its resources, into a versioned binary
+    /// format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serialize::encode(&self.data)
+    }
+
+    /// Deserializes a fragment produced by [SceneFragment::to_bytes].
+    ///
+    /// The streams are validated so that the fragment can be appended to a scene
+    /// without producing out of range offsets on the GPU.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
+            data: serialize::decode(bytes)?,
+            id: Self::next_id(),
+        })
     }
 }
 
@@ -173,9 +409,20 @@ pub enum ResourcePatch {
 }
 
 /// Builder for constructing a scene or scene fragment.
+///
+/// By default, invalid input is encoded as is or silently fixed up. A checked builder,
+/// created with [SceneBuilder::strict] or by enabling the `checked` feature, also
+/// records a [Diagnostic] for each misuse and returns them from
+/// [SceneBuilder::finish_checked].
+///
+/// Checking is not tied to `debug_assertions`, because it turns a successful
+/// [SceneBuilder::finish_checked] into an error: debug and release builds of the same
+/// program would otherwise take different paths for the same scene.
 pub struct SceneBuilder<'a> {
     scene: &'a mut SceneData,
     layer_depth: u32,
+    tag: u32,
+    diagnostics: Option<Vec<Diagnostic>>,
 }
 
 impl<'a> SceneBuilder<'a> {
@@ -188,6 +435,7 @@ impl<'a> SceneBuilder<'a> {
     /// Creates a new builder for filling a scene fragment. Any current content in
     /// the fragment will be cleared.    
     pub fn for_fragment(fragment: &'a mut SceneFragment) -> Self {
+        fragment.id = SceneFragment::next_id();
         Self::new(&mut fragment.data, true)
     }
 
@@ -197,9 +445,28 @@ impl<'a> SceneBuilder<'a> {
         Self {
             scene,
             layer_depth: 0,
+            tag: 0,
+            diagnostics: cfg!(feature = "checked").then(Vec::new),
         }
     }
 
+    /// Makes the builder check its input, recording diagnostics for unbalanced layers,
+    /// non-finite values, degenerate transforms and invalid gradients.
+    ///
+    /// The input is encoded the same as by an unchecked builder, so the diagnostics
+    /// returned by [SceneBuilder::finish_checked] explain any garbage in the rendered
+    /// scene.
+    pub fn strict(mut self) -> Self {
+        self.diagnostics.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// Sets the tag of the draw objects encoded after this call, which identifies them
+    /// in hit testing. The initial tag is 0.
+    pub fn set_tag(&mut self, tag: u32) {
+        self.tag = tag;
+    }
+
     /// Pushes a new layer bound by the specifed shape and composed with
     /// previous layers using the specified blend mode.
     pub fn push_layer(
@@ -210,6 +477,9 @@ impl<'a> SceneBuilder<'a> {
         shape: &impl Shape,
     ) {
         let blend = blend.into();
+        if !alpha.is_finite() {
+            self.diagnose(DiagnosticKind::NonFinite);
+        }
         self.maybe_encode_transform(transform);
         self.linewidth(-1.0);
         if !self.encode_path(shape, true) {
@@ -226,6 +496,8 @@ impl<'a> SceneBuilder<'a> {
         if self.layer_depth > 0 {
             self.end_clip();
             self.layer_depth -= 1;
+        } else {
+            self.diagnose(DiagnosticKind::UnbalancedPop);
         }
     }
 
@@ -242,6 +514,7 @@ impl<'a> SceneBuilder<'a> {
         self.linewidth(-1.0);
         if self.encode_path(shape, true) {
             if let Some(brush_transform) = brush_transform {
+                self.check_transform(&brush_transform);
                 self.encode_transform(transform * brush_transform);
                 self.swap_last_tags();
                 self.encode_brush(brush);
@@ -260,10 +533,14 @@ impl<'a> SceneBuilder<'a> {
         brush_transform: Option<Affine>,
         shape: &impl Shape,
     ) {
+        if !style.width.is_finite() {
+            self.diagnose(DiagnosticKind::NonFinite);
+        }
         self.maybe_encode_transform(transform);
         self.linewidth(style.width);
         if self.encode_path(shape, false) {
             if let Some(brush_transform) = brush_transform {
+                self.check_transform(&brush_transform);
                 self.encode_transform(transform * brush_transform);
                 self.swap_last_tags();
                 self.encode_brush(brush);
@@ -275,29 +552,168 @@ impl<'a> SceneBuilder<'a> {
 
     /// Appends a fragment to the scene.
     pub fn append(&mut self, fragment: &SceneFragment, transform: Option<Affine>) {
-        self.scene.append(&fragment.data, &transform);
+        if let Some(transform) = &transform {
+            self.check_transform(transform);
+        }
+        self.scene.append(&fragment.data, &transform, None);
+    }
+
+    /// Appends several fragments to the scene, each with an optional transform.
+    ///
+    /// This is the same as calling [SceneBuilder::append] for each fragment in order,
+    /// but the offsets of the fragments are computed up front and their streams are
+    /// copied on multiple threads.
+    #[cfg(feature = "rayon")]
+    pub fn append_many(&mut self, fragments: &[(&SceneFragment, Option<Affine>)]) {
+        for (_, transform) in fragments {
+            if let Some(transform) = transform {
+                self.check_transform(transform);
+            }
+        }
+        let others: Vec<_> = fragments
+            .iter()
+            .map(|(fragment, transform)| (&fragment.data, *transform))
+            .collect();
+        self.scene.append_many(&others);
+    }
+
+    /// Appends an instance of a fragment to the scene.
+    ///
+    /// This draws the same as [SceneBuilder::append], but the path data of the fragment
+    /// is only stored once: the first instance copies it and later instances of the
+    /// same fragment refer to that copy, both here and on the GPU. Only the path tags,
+    /// transforms and draw objects are encoded for each instance.
+    pub fn append_instance(&mut self, fragment: &SceneFragment, transform: Option<Affine>) {
+        if let Some(transform) = &transform {
+            self.check_transform(transform);
+        }
+        let shared = self.scene.instances.get(&fragment.id).copied();
+        match shared {
+            Some(start) => {
+                if fragment.data.tag_stream.first() != Some(&PATHTAG_TRANSFORM) {
+                    // Segments before the first transform of the fragment would use the
+                    // delta of the preceding draws, so give them their own transform.
+                    self.encode_transform(transform.unwrap_or(Affine::IDENTITY));
+                    if let Some(delta) = self.scene.pathseg_delta_stream.last_mut() {
+                        *delta = start as i32 - self.scene.pathseg_words as i32;
+                    }
+                }
+            }
+            None => {
+                let start = self.scene.pathseg_stream.len() / 4;
+                self.scene.instances.insert(fragment.id, start);
+            }
+        }
+        self.scene.append(&fragment.data, &transform, shared);
+    }
+
+    /// Appends a fragment to the scene, returning a handle for updating its transform
+    /// and opacity with [Scene::set_transform] and [Scene::set_alpha] after the scene
+    /// has been built.
+    ///
+    /// The fragment is encoded as with [SceneBuilder::append]. The handle refers to the
+    /// scene being built and is not carried over when a fragment built with this method
+    /// is appended elsewhere.
+    pub fn append_retained(
+        &mut self,
+        fragment: &SceneFragment,
+        transform: Option<Affine>,
+    ) -> FragmentHandle {
+        let transform = transform.unwrap_or(Affine::IDENTITY);
+        self.check_transform(&transform);
+        let transform_start = self.scene.transform_stream.len();
+        // Always encode a new transform, as it is patched independently of the
+        // preceding draws.
+        self.encode_transform(transform);
+        let drawdata_base = self.scene.drawdata_stream.len();
+        let stops_base = self.scene.resources.stops.len();
+        self.scene.append(&fragment.data, &Some(transform), None);
+        let mut colors = vec![];
+        let mut offset = 0;
+        for &draw_tag in &fragment.data.drawtag_stream {
+            if draw_tag == DRAWTAG_FILLCOLOR {
+                let color: FillColor = bytemuck::pod_read_unaligned(
+                    &fragment.data.drawdata_stream[offset..offset + 4],
+                );
+                colors.push((drawdata_base + offset, color.rgba_color));
+            }
+            offset += ((draw_tag >> 2) & 0x7) as usize * 4;
+        }
+        let mut base_transforms = vec![affine_to_f32(&Affine::IDENTITY)];
+        base_transforms.extend_from_slice(&fragment.data.transform_stream);
+        let base_stops = fragment.data.resources.stops.clone();
+        self.scene.retained.push(RetainedFragment {
+            transforms: transform_start..self.scene.transform_stream.len(),
+            base_transforms,
+            colors,
+            stops: stops_base..stops_base + base_stops.len(),
+            base_stops,
+        });
+        FragmentHandle(self.scene.retained.len() - 1)
     }
 
     /// Completes construction and finalizes the underlying scene.
-    pub fn finish(mut self) {
+    ///
+    /// Any layers that are still open are popped. Diagnostics recorded by a checked
+    /// builder are discarded; use [SceneBuilder::finish_checked] to retrieve them.
+    pub fn finish(self) {
+        let _ = self.finish_checked();
+    }
+
+    /// Completes construction like [SceneBuilder::finish], returning the diagnostics
+    /// recorded by a checked builder as [Error::SceneDiagnostics].
+    ///
+    /// The scene is encoded either way. An unchecked builder always succeeds.
+    pub fn finish_checked(mut self) -> Result<(), Error> {
+        if self.layer_depth > 0 {
+            self.diagnose(DiagnosticKind::UnclosedLayers(self.layer_depth));
+        }
         for _ in 0..self.layer_depth {
             self.end_clip();
         }
+        match self.diagnostics {
+            Some(diagnostics) if !diagnostics.is_empty() => {
+                Err(Error::SceneDiagnostics(diagnostics))
+            }
+            _ => Ok(()),
+        }
     }
 }
 
 impl<'a> SceneBuilder<'a> {
+    /// Records a diagnostic for the next draw object if the builder is checked.
+    fn diagnose(&mut self, kind: DiagnosticKind) {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.push(Diagnostic {
+                draw_index: self.scene.drawtag_stream.len(),
+                kind,
+            });
+        }
+    }
+
+    fn check_transform(&mut self, transform: &Affine) {
+        if self.diagnostics.is_some() && diagnostics::is_degenerate(transform) {
+            self.diagnose(DiagnosticKind::DegenerateTransform(*transform));
+        }
+    }
+
     /// Encodes a path for the specified shape.
     ///
     /// When the `is_fill` parameter is true, closes any open subpaths by inserting
     /// a line to the start point of the subpath with the end segment bit set.
     fn encode_path(&mut self, shape: &impl Shape, is_fill: bool) -> bool {
+        let pathseg_len = self.scene.pathseg_stream.len();
+        let check = self.diagnostics.is_some();
+        let mut is_finite = true;
         let mut b = PathBuilder::new(
             &mut self.scene.tag_stream,
             &mut self.scene.pathseg_stream,
             is_fill,
         );
         for el in shape.path_elements(0.1) {
+            if check {
+                is_finite &= diagnostics::is_finite_el(&el);
+            }
             match el {
                 PathEl::MoveTo(p0) => b.move_to(p0.x as f32, p0.y as f32),
                 PathEl::LineTo(p0) => b.line_to(p0.x as f32, p0.y as f32),
@@ -316,9 +732,14 @@ impl<'a> SceneBuilder<'a> {
             }
         }
         b.finish();
-        if b.n_pathseg != 0 {
+        let n_pathseg = b.n_pathseg;
+        if !is_finite {
+            self.diagnose(DiagnosticKind::NonFinite);
+        }
+        self.scene.pathseg_words += (self.scene.pathseg_stream.len() - pathseg_len) / 4;
+        if n_pathseg != 0 {
             self.scene.n_path += 1;
-            self.scene.n_pathseg += b.n_pathseg;
+            self.scene.n_pathseg += n_pathseg;
             true
         } else {
             false
@@ -326,7 +747,17 @@ impl<'a> SceneBuilder<'a> {
     }
 
     fn maybe_encode_transform(&mut self, transform: Affine) {
-        if self.scene.transform_stream.last() != Some(&affine_to_f32(&transform)) {
+        self.check_transform(&transform);
+        // The last transform can't be reused if it belongs to a retained fragment, or
+        // if it refers to shared path data.
+        let is_retained = self.scene.retained.last().map_or(false, |retained| {
+            retained.transforms.end == self.scene.transform_stream.len()
+        });
+        let is_shared = self.scene.pathseg_delta_stream.last() != Some(&self.scene.pathseg_delta());
+        if is_retained
+            || is_shared
+            || self.scene.transform_stream.last() != Some(&affine_to_f32(&transform))
+        {
             self.encode_transform(transform);
         }
     }
@@ -334,6 +765,8 @@ impl<'a> SceneBuilder<'a> {
     fn encode_transform(&mut self, transform: Affine) {
         self.scene.tag_stream.push(0x20);
         self.scene.transform_stream.push(affine_to_f32(&transform));
+        let delta = self.scene.pathseg_delta();
+        self.scene.pathseg_delta_stream.push(delta);
     }
 
     // Swap the last two tags in the tag stream; used for transformed
@@ -343,6 +776,11 @@ impl<'a> SceneBuilder<'a> {
         self.scene.tag_stream.swap(len - 1, len - 2);
     }
 
+    fn push_draw_tag(&mut self, draw_tag: u32) {
+        self.scene.drawtag_stream.push(draw_tag);
+        self.scene.user_tags.push(self.tag);
+    }
+
     // -1.0 means "fill"
     fn linewidth(&mut self, linewidth: f32) {
         if self.scene.linewidth_stream.last() != Some(&linewidth) {
@@ -354,15 +792,20 @@ impl<'a> SceneBuilder<'a> {
     fn encode_brush<'b>(&mut self, brush: impl Into<BrushRef<'b>>) {
         match brush.into() {
             BrushRef::Solid(color) => {
-                self.scene.drawtag_stream.push(DRAWTAG_FILLCOLOR);
+                self.push_draw_tag(DRAWTAG_FILLCOLOR);
                 let rgba_color = color.to_premul_u32();
                 self.scene
                     .drawdata_stream
                     .extend(bytemuck::bytes_of(&FillColor { rgba_color }));
             }
             BrushRef::LinearGradient(gradient) => {
+                if self.diagnostics.is_some()
+                    && !diagnostics::is_finite_points(&[gradient.start, gradient.end])
+                {
+                    self.diagnose(DiagnosticKind::NonFinite);
+                }
                 let index = self.add_ramp(&gradient.stops);
-                self.scene.drawtag_stream.push(DRAWTAG_FILLLINGRADIENT);
+                self.push_draw_tag(DRAWTAG_FILLLINGRADIENT);
                 self.scene
                     .drawdata_stream
                     .extend(bytemuck::bytes_of(&FillLinGradient {
@@ -372,8 +815,17 @@ impl<'a> SceneBuilder<'a> {
                     }));
             }
             BrushRef::RadialGradient(gradient) => {
+                if self.diagnostics.is_some()
+                    && !(diagnostics::is_finite_points(&[
+                        gradient.start_center,
+                        gradient.end_center,
+                    ]) && gradient.start_radius.is_finite()
+                        && gradient.end_radius.is_finite())
+                {
+                    self.diagnose(DiagnosticKind::NonFinite);
+                }
                 let index = self.add_ramp(&gradient.stops);
-                self.scene.drawtag_stream.push(DRAWTAG_FILLRADGRADIENT);
+                self.push_draw_tag(DRAWTAG_FILLRADGRADIENT);
                 self.scene
                     .drawdata_stream
                     .extend(bytemuck::bytes_of(&FillRadGradient {
@@ -389,6 +841,13 @@ impl<'a> SceneBuilder<'a> {
     }
 
     fn add_ramp(&mut self, stops: &[ColorStop]) -> u32 {
+        if self.diagnostics.is_some() {
+            if stops.is_empty() {
+                self.diagnose(DiagnosticKind::EmptyGradient);
+            } else if !diagnostics::stops_in_order(stops) {
+                self.diagnose(DiagnosticKind::StopsOutOfOrder);
+            }
+        }
         let offset = self.scene.drawdata_stream.len();
         let resources = &mut self.scene.resources;
         let stops_start = resources.stops.len();
@@ -402,7 +861,7 @@ impl<'a> SceneBuilder<'a> {
 
     /// Start a clip.
     fn begin_clip(&mut self, blend: BlendMode, alpha: f32) {
-        self.scene.drawtag_stream.push(DRAWTAG_BEGINCLIP);
+        self.push_draw_tag(DRAWTAG_BEGINCLIP);
         let element = Clip {
             blend: encode_blend_mode(blend),
             alpha,
@@ -414,7 +873,7 @@ impl<'a> SceneBuilder<'a> {
     }
 
     fn end_clip(&mut self) {
-        self.scene.drawtag_stream.push(DRAWTAG_ENDCLIP);
+        self.push_draw_tag(DRAWTAG_ENDCLIP);
         // This is a dummy path, and will go away with the new clip impl.
         self.scene.tag_stream.push(0x10);
         self.scene.n_path += 1;
@@ -426,6 +885,16 @@ fn encode_blend_mode(mode: BlendMode) -> u32 {
     (mode.mix as u32) << 8 | mode.compose as u32
 }
 
+// Tags for path objects. See shader/shared/pathtag.wgsl for the authoritative source.
+const PATHTAG_SEG_TYPE: u8 = 3;
+const PATHTAG_LINETO: u8 = 1;
+const PATHTAG_QUADTO: u8 = 2;
+const PATHTAG_SUBPATH_END: u8 = 4;
+const PATHTAG_F32: u8 = 8;
+const PATHTAG_PATH: u8 = 0x10;
+const PATHTAG_TRANSFORM: u8 = 0x20;
+const PATHTAG_LINEWIDTH: u8 = 0x40;
+
 // Tags for draw objects. See shader/shared/drawtag.wgsl for the authoritative source.
 const DRAWTAG_FILLCOLOR: u32 = 0x44;
 const DRAWTAG_FILLLINGRADIENT: u32 = 0x114;
@@ -475,10 +944,11 @@ pub struct Clip {
 
 struct PathBuilder<'a> {
     tag_stream: &'a mut Vec<u8>,
-    // If we're never going to use the i16 encoding, it might be
-    // slightly faster to store this as Vec<u32>, we'd get aligned
-    // stores on ARM etc.
+    // Points are written as f32 pairs, and the path is rewritten with the i16
+    // encoding when it's finished if all of its coordinates fit.
     pathseg_stream: &'a mut Vec<u8>,
+    tag_start: usize,
+    pathseg_start: usize,
     first_pt: [f32; 2],
     state: PathState,
     n_pathseg: u32,
@@ -495,6 +965,8 @@ enum PathState {
 impl<'a> PathBuilder<'a> {
     pub fn new(tags: &'a mut Vec<u8>, pathsegs: &'a mut Vec<u8>, is_fill: bool) -> PathBuilder<'a> {
         PathBuilder {
+            tag_start: tags.len(),
+            pathseg_start: pathsegs.len(),
             tag_stream: tags,
             pathseg_stream: pathsegs,
             first_pt: [0.0, 0.0],
@@ -614,9 +1086,43 @@ impl<'a> PathBuilder<'a> {
             if let Some(tag) = self.tag_stream.last_mut() {
                 *tag |= 4;
             }
+            self.compact();
             self.tag_stream.push(0x10);
         }
     }
+
+    /// Switches the path to the i16 encoding if all of its coordinates are integers
+    /// in range, which halves the size of its segment data.
+    ///
+    /// The encoding applies to the whole path, as consecutive segments share points.
+    fn compact(&mut self) {
+        let start = self.pathseg_start;
+        let coord = |bytes: &[u8]| bytemuck::pod_read_unaligned::<f32>(bytes);
+        let fits_i16 = |value: f32| {
+            value.fract() == 0.0 && value >= i16::MIN as f32 && value <= i16::MAX as f32
+        };
+        if !self.pathseg_stream[start..]
+            .chunks_exact(4)
+            .all(|bytes| fits_i16(coord(bytes)))
+        {
+            return;
+        }
+        // Each point shrinks from 8 bytes to 4, so it can be rewritten in place.
+        let n_points = (self.pathseg_stream.len() - start) / 8;
+        for i in 0..n_points {
+            let src = start + i * 8;
+            let point = [
+                coord(&self.pathseg_stream[src..src + 4]) as i16,
+                coord(&self.pathseg_stream[src + 4..src + 8]) as i16,
+            ];
+            let dst = start + i * 4;
+            self.pathseg_stream[dst..dst + 4].copy_from_slice(bytemuck::bytes_of(&point));
+        }
+        self.pathseg_stream.truncate(start + n_points * 4);
+        for tag in &mut self.tag_stream[self.tag_start..] {
+            *tag &= !PATHTAG_F32;
+        }
+    }
 }
 
 fn affine_to_f32(affine: &Affine) -> [f32; 6] {
@@ -630,3 +1136,119 @@ fn affine_from_f32(coeffs: &[f32; 6]) -> Affine {
 fn point_to_f32(point: Point) -> [f32; 2] {
     [point.x as f32, point.y as f32]
 }
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Affine, Point, Rect};
+    use peniko::{Color, Fill, LinearGradient};
+
+    use super::*;
+
+    fn retained_scene() -> (Scene, FragmentHandle) {
+        let mut fragment = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut fragment);
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            Color::rgb8(255, 0, 0),
+            None,
+            &Rect::new(0.0, 0.0, 10.0, 10.0),
+        );
+        let gradient = LinearGradient {
+            start: (0.0, 0.0).into(),
+            end: (10.0, 0.0).into(),
+            stops: [
+                (0.0, Color::rgb8(0, 0, 255)).into(),
+                (1.0, Color::rgba8(0, 255, 0, 128)).into(),
+            ]
+            .into_iter()
+            .collect(),
+            extend: Default::default(),
+        };
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            &gradient,
+            None,
+            &Rect::new(0.0, 10.0, 10.0, 20.0),
+        );
+        builder.finish();
+
+        let mut scene = Scene::new();
+        let mut builder = SceneBuilder::for_scene(&mut scene);
+        let handle = builder.append_retained(&fragment, Some(Affine::translate((100.0, 0.0))));
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            Color::rgb8(0, 0, 0),
+            None,
+            &Rect::new(200.0, 0.0, 210.0, 10.0),
+        );
+        builder.finish();
+        (scene, handle)
+    }
+
+    #[test]
+    fn retained_without_layer() {
+        let (scene, _) = retained_scene();
+        assert_eq!(scene.data().n_clip, 0);
+        assert!(!scene.data().drawtag_stream.contains(&DRAWTAG_BEGINCLIP));
+    }
+
+    #[test]
+    fn set_transform_moves_content() {
+        let (mut scene, handle) = retained_scene();
+        assert_eq!(
+            scene.bounding_box(),
+            Some(Rect::new(100.0, 0.0, 210.0, 20.0))
+        );
+        assert_eq!(
+            scene.hit_test(Point::new(105.0, 5.0)).map(|id| id.index),
+            Some(0)
+        );
+
+        scene.set_transform(handle, Affine::translate((0.0, 50.0)));
+        assert_eq!(scene.bounding_box(), Some(Rect::new(0.0, 0.0, 210.0, 70.0)));
+        assert_eq!(scene.hit_test(Point::new(105.0, 5.0)), None);
+        assert_eq!(
+            scene.hit_test(Point::new(5.0, 55.0)).map(|id| id.index),
+            Some(0)
+        );
+        assert_eq!(
+            scene.hit_test(Point::new(5.0, 65.0)).map(|id| id.index),
+            Some(1)
+        );
+        // Draws after the fragment keep their own transform.
+        assert_eq!(
+            scene.hit_test(Point::new(205.0, 5.0)).map(|id| id.index),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn set_alpha_scales_colors() {
+        let (mut scene, handle) = retained_scene();
+        scene.set_alpha(handle, 0.5);
+        let data = scene.data();
+        let color: FillColor = bytemuck::pod_read_unaligned(&data.drawdata_stream[..4]);
+        assert_eq!(
+            color.rgba_color,
+            Color::rgba8(255, 0, 0, 128).to_premul_u32()
+        );
+        let alphas = data
+            .resources
+            .stops
+            .iter()
+            .map(|stop| stop.color.a)
+            .collect::<Vec<_>>();
+        assert_eq!(alphas, [128, 64]);
+        // The draw after the fragment is unchanged.
+        let offset = data.drawdata_stream.len() - 4;
+        let color: FillColor = bytemuck::pod_read_unaligned(&data.drawdata_stream[offset..]);
+        assert_eq!(color.rgba_color, Color::rgb8(0, 0, 0).to_premul_u32());
+
+        scene.set_alpha(handle, 1.0);
+        let color: FillColor = bytemuck::pod_read_unaligned(&scene.data().drawdata_stream[..4]);
+        assert_eq!(color.rgba_color, Color::rgb8(255, 0, 0).to_premul_u32());
+    }
+}
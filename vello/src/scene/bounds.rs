@@ -0,0 +1,198 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Bounding boxes of encoded scenes, following the GPU pipeline: path bounding boxes
+//! as in pathseg.wgsl, clip bounding boxes as in clip_leaf.wgsl and their
+//! intersection with draw objects as in binning.wgsl.
+
+use peniko::kurbo::Rect;
+
+use super::decode::{Decoder, Draw, DrawObject};
+use super::SceneData;
+
+/// Returns the bounds of the visible draw objects, or `None` if nothing is drawn.
+pub fn bounding_box(data: &SceneData) -> Option<Rect> {
+    let mut bounds: Option<Rect> = None;
+    // Intersection of the bounding boxes of the enclosing clips.
+    let mut clips: Vec<Rect> = vec![];
+    for object in Decoder::new(data) {
+        // An empty path has an empty bounding box, which clips everything.
+        let path_bbox = path_bbox(&object).unwrap_or(Rect::ZERO);
+        let clip_bbox = clips.last().copied();
+        let bbox = match clip_bbox {
+            Some(clip_bbox) => path_bbox.intersect(clip_bbox),
+            None => path_bbox,
+        };
+        match object.draw {
+            Draw::BeginClip { .. } => clips.push(bbox),
+            Draw::EndClip => {
+                clips.pop();
+            }
+            _ => {
+                if bbox.x0 < bbox.x1 && bbox.y0 < bbox.y1 {
+                    bounds = Some(bounds.map_or(bbox, |bounds| bounds.union(bbox)));
+                }
+            }
+        }
+    }
+    bounds
+}
+
+/// Returns the bounding box of a path in device space, rounded out to whole pixels.
+///
+/// Like the GPU, this uses the control points of the segments rather than the tight
+/// bounds of the curves, and inflates strokes by half the line width scaled by the
+/// transform.
+fn path_bbox(object: &DrawObject) -> Option<Rect> {
+    let mut path_bbox: Option<Rect> = None;
+    for segment in &object.segments {
+        let [a, b, c, d, e, f] = segment.transform;
+        let mut bbox = [f32::MAX, f32::MAX, f32::MIN, f32::MIN];
+        for &[x, y] in segment.segment.points() {
+            let x1 = a * x + c * y + e;
+            let y1 = b * x + d * y + f;
+            bbox = [
+                bbox[0].min(x1),
+                bbox[1].min(y1),
+                bbox[2].max(x1),
+                bbox[3].max(y1),
+            ];
+        }
+        if object.linewidth >= 0.0 {
+            let stroke_x = 0.5 * object.linewidth * (a * a + c * c).sqrt();
+            let stroke_y = 0.5 * object.linewidth * (b * b + d * d).sqrt();
+            bbox = [
+                bbox[0] - stroke_x,
+                bbox[1] - stroke_y,
+                bbox[2] + stroke_x,
+                bbox[3] + stroke_y,
+            ];
+        }
+        if bbox[2] > bbox[0] || bbox[3] > bbox[1] {
+            let rect = Rect::new(
+                bbox[0].floor() as f64,
+                bbox[1].floor() as f64,
+                bbox[2].ceil() as f64,
+                bbox[3].ceil() as f64,
+            );
+            path_bbox = Some(path_bbox.map_or(rect, |path_bbox| path_bbox.union(rect)));
+        }
+    }
+    path_bbox
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Affine, Rect};
+    use peniko::{Color, Fill, Mix, Stroke};
+
+    use crate::{Scene, SceneBuilder};
+
+    fn bounds(build: impl FnOnce(&mut SceneBuilder)) -> Option<Rect> {
+        let mut scene = Scene::new();
+        let mut builder = SceneBuilder::for_scene(&mut scene);
+        build(&mut builder);
+        builder.finish();
+        scene.bounding_box()
+    }
+
+    fn fill(builder: &mut SceneBuilder, rect: Rect) {
+        let color = Color::rgb8(255, 0, 0);
+        builder.fill(Fill::NonZero, Affine::IDENTITY, color, None, &rect);
+    }
+
+    fn stroke(builder: &mut SceneBuilder, transform: Affine, rect: Rect) {
+        let color = Color::rgb8(255, 0, 0);
+        builder.stroke(&Stroke::new(4.0), transform, color, None, &rect);
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(bounds(|_| {}), None);
+    }
+
+    #[test]
+    fn fill_rounds_out() {
+        let bbox = bounds(|b| fill(b, Rect::new(0.5, 1.25, 10.5, 20.75)));
+        assert_eq!(bbox, Some(Rect::new(0.0, 1.0, 11.0, 21.0)));
+    }
+
+    #[test]
+    fn stroke_inflation() {
+        let rect = Rect::new(10.0, 10.0, 20.0, 20.0);
+        let bbox = bounds(|b| stroke(b, Affine::IDENTITY, rect));
+        assert_eq!(bbox, Some(Rect::new(8.0, 8.0, 22.0, 22.0)));
+        // The half width is scaled by the transform along each axis.
+        let bbox = bounds(|b| stroke(b, Affine::scale(2.0), rect));
+        assert_eq!(bbox, Some(Rect::new(16.0, 16.0, 44.0, 44.0)));
+        let bbox = bounds(|b| stroke(b, Affine::scale_non_uniform(1.0, 3.0), rect));
+        assert_eq!(bbox, Some(Rect::new(8.0, 24.0, 22.0, 66.0)));
+    }
+
+    #[test]
+    fn clip_intersection() {
+        let bbox = bounds(|b| {
+            b.push_layer(
+                Mix::Normal,
+                1.0,
+                Affine::IDENTITY,
+                &Rect::new(0.0, 0.0, 50.0, 50.0),
+            );
+            fill(b, Rect::new(25.0, 25.0, 100.0, 100.0));
+            b.pop_layer();
+        });
+        assert_eq!(bbox, Some(Rect::new(25.0, 25.0, 50.0, 50.0)));
+    }
+
+    #[test]
+    fn nested_clips() {
+        let bbox = bounds(|b| {
+            b.push_layer(
+                Mix::Normal,
+                1.0,
+                Affine::IDENTITY,
+                &Rect::new(0.0, 0.0, 50.0, 50.0),
+            );
+            b.push_layer(
+                Mix::Normal,
+                1.0,
+                Affine::IDENTITY,
+                &Rect::new(40.0, 40.0, 100.0, 100.0),
+            );
+            fill(b, Rect::new(0.0, 0.0, 100.0, 100.0));
+            b.pop_layer();
+            b.pop_layer();
+            // Content after the layers is not clipped.
+            fill(b, Rect::new(200.0, 200.0, 210.0, 210.0));
+        });
+        assert_eq!(bbox, Some(Rect::new(40.0, 40.0, 210.0, 210.0)));
+    }
+
+    #[test]
+    fn fully_clipped() {
+        let bbox = bounds(|b| {
+            b.push_layer(
+                Mix::Normal,
+                1.0,
+                Affine::IDENTITY,
+                &Rect::new(0.0, 0.0, 10.0, 10.0),
+            );
+            fill(b, Rect::new(20.0, 20.0, 30.0, 30.0));
+            b.pop_layer();
+        });
+        assert_eq!(bbox, None);
+    }
+}
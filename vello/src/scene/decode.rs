@@ -0,0 +1,797 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Decoding of encoded scene data.
+//!
+//! The decoder walks the path tag, draw tag and draw data streams in lockstep, the
+//! same way the monoid scans do on the GPU: the path tags are scanned for transform,
+//! line width and segment offsets, and the draw tags for draw data offsets. Each path
+//! is paired with the draw object of the same index.
+//!
+//! [Commands] goes one step further and reconstructs the [SceneBuilder] calls that
+//! produced each draw object.
+
+use std::fmt::{self, Write};
+
+use peniko::kurbo::BezPath;
+use peniko::{
+    BlendMode, Brush, Color, ColorStop, Compose, Extend, LinearGradient, Mix, RadialGradient,
+};
+
+use super::*;
+
+/// A path segment with its control points.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Segment {
+    Line([[f32; 2]; 2]),
+    Quad([[f32; 2]; 3]),
+    Cubic([[f32; 2]; 4]),
+}
+
+impl Segment {
+    /// Returns the control points, starting with the start point.
+    pub fn points(&self) -> &[[f32; 2]] {
+        match self {
+            Self::Line(points) => points,
+            Self::Quad(points) => points,
+            Self::Cubic(points) => points,
+        }
+    }
+}
+
+/// A segment of a path, in the coordinate space of its transform.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PathSegment {
+    pub segment: Segment,
+    /// Transform applied to the segment.
+    pub transform: [f32; 6],
+    /// True if the segment is the last one of its subpath.
+    pub subpath_end: bool,
+}
+
+/// Payload of a draw object.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Draw<'a> {
+    /// Solid color, as premultiplied RGBA packed with red in the high byte.
+    Color {
+        rgba: u32,
+    },
+    LinearGradient {
+        p0: [f32; 2],
+        p1: [f32; 2],
+        stops: &'a [ColorStop],
+    },
+    RadialGradient {
+        p0: [f32; 2],
+        p1: [f32; 2],
+        r0: f32,
+        r1: f32,
+        stops: &'a [ColorStop],
+    },
+    BeginClip {
+        blend: BlendMode,
+        alpha: f32,
+    },
+    EndClip,
+}
+
+/// A path and the draw object that uses it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DrawObject<'a> {
+    /// Index of the path and draw object.
+    pub index: usize,
+    /// Transform in effect at the end of the path, which applies to the brush.
+    pub transform: [f32; 6],
+    /// True if a transform was encoded between the path segments and the end of the
+    /// path, which happens for brushes with their own transform.
+    pub has_brush_transform: bool,
+    /// Line width of the path; negative for fills.
+    pub linewidth: f32,
+    pub segments: Vec<PathSegment>,
+    pub draw: Draw<'a>,
+}
+
+/// Iterator over the draw objects of scene data.
+///
+/// Decoding stops at the first inconsistency between the streams.
+pub struct Decoder<'a> {
+    data: &'a SceneData,
+    tag_ix: usize,
+    /// Index of the current transform, offset by one since scenes start with an
+    /// identity transform that has no tag, while fragments don't.
+    trans_ix: isize,
+    linewidth_ix: isize,
+    /// Offset of the current point in the path segment stream, in 32-bit words.
+    pathseg_offset: usize,
+    drawdata_offset: usize,
+    draw_ix: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a SceneData) -> Self {
+        let count = |tag| data.tag_stream.iter().filter(|t| **t == tag).count() as isize;
+        Self {
+            data,
+            tag_ix: 0,
+            trans_ix: data.transform_stream.len() as isize - count(PATHTAG_TRANSFORM) - 1,
+            linewidth_ix: data.linewidth_stream.len() as isize - count(PATHTAG_LINEWIDTH) - 1,
+            pathseg_offset: 0,
+            drawdata_offset: 0,
+            draw_ix: 0,
+        }
+    }
+
+    fn transform(&self) -> [f32; 6] {
+        usize::try_from(self.trans_ix)
+            .ok()
+            .and_then(|ix| self.data.transform_stream.get(ix))
+            .copied()
+            .unwrap_or([1.0, 0.0, 0.0, 1.0, 0.0, 0.0])
+    }
+
+    fn linewidth(&self) -> f32 {
+        usize::try_from(self.linewidth_ix)
+            .ok()
+            .and_then(|ix| self.data.linewidth_stream.get(ix))
+            .copied()
+            .unwrap_or(-1.0)
+    }
+
+    fn pathseg_delta(&self) -> isize {
+        usize::try_from(self.trans_ix)
+            .ok()
+            .and_then(|ix| self.data.pathseg_delta_stream.get(ix))
+            .map_or(0, |delta| *delta as isize)
+    }
+
+    fn point(&self, offset: usize, is_f32: bool) -> Option<[f32; 2]> {
+        let offset = usize::try_from(offset as isize + self.pathseg_delta()).ok()?;
+        if is_f32 {
+            let bytes = self.data.pathseg_stream.get(offset * 4..offset * 4 + 8)?;
+            Some(bytemuck::pod_read_unaligned(bytes))
+        } else {
+            let bytes = self.data.pathseg_stream.get(offset * 4..offset * 4 + 4)?;
+            let [x, y]: [i16; 2] = bytemuck::pod_read_unaligned(bytes);
+            Some([x as f32, y as f32])
+        }
+    }
+
+    fn segment(&mut self, tag: u8) -> Option<PathSegment> {
+        let is_f32 = tag & PATHTAG_F32 != 0;
+        // Size of a point in 32-bit words.
+        let stride = if is_f32 { 2 } else { 1 };
+        let mut points = [[0.0; 2]; 4];
+        let n_points = (tag & PATHTAG_SEG_TYPE) as usize + 1;
+        for (i, point) in points.iter_mut().take(n_points).enumerate() {
+            *point = self.point(self.pathseg_offset + i * stride, is_f32)?;
+        }
+        let segment = match tag & PATHTAG_SEG_TYPE {
+            PATHTAG_LINETO => Segment::Line([points[0], points[1]]),
+            PATHTAG_QUADTO => Segment::Quad([points[0], points[1], points[2]]),
+            _ => Segment::Cubic(points),
+        };
+        let subpath_end = tag & PATHTAG_SUBPATH_END != 0;
+        self.pathseg_offset += (n_points - 1 + subpath_end as usize) * stride;
+        Some(PathSegment {
+            segment,
+            transform: self.transform(),
+            subpath_end,
+        })
+    }
+
+    fn drawdata<T: Pod>(&self) -> Option<T> {
+        let size = std::mem::size_of::<T>();
+        let bytes = self
+            .data
+            .drawdata_stream
+            .get(self.drawdata_offset..self.drawdata_offset + size)?;
+        Some(bytemuck::pod_read_unaligned(bytes))
+    }
+
+    fn stops(&self) -> &'a [ColorStop] {
+        let resources = &self.data.resources;
+        resources
+            .patches
+            .iter()
+            .find_map(|patch| match patch {
+                ResourcePatch::Ramp { offset, stops } if *offset == self.drawdata_offset => {
+                    resources.stops.get(stops.clone())
+                }
+                _ => None,
+            })
+            .unwrap_or(&[])
+    }
+
+    fn draw(&self, tag: u32) -> Option<Draw<'a>> {
+        Some(match tag {
+            DRAWTAG_FILLCOLOR => Draw::Color {
+                rgba: self.drawdata::<FillColor>()?.rgba_color,
+            },
+            DRAWTAG_FILLLINGRADIENT => {
+                let gradient = self.drawdata::<FillLinGradient>()?;
+                Draw::LinearGradient {
+                    p0: gradient.p0,
+                    p1: gradient.p1,
+                    stops: self.stops(),
+                }
+            }
+            DRAWTAG_FILLRADGRADIENT => {
+                let gradient = self.drawdata::<FillRadGradient>()?;
+                Draw::RadialGradient {
+                    p0: gradient.p0,
+                    p1: gradient.p1,
+                    r0: gradient.r0,
+                    r1: gradient.r1,
+                    stops: self.stops(),
+                }
+            }
+            DRAWTAG_BEGINCLIP => {
+                let clip = self.drawdata::<Clip>()?;
+                Draw::BeginClip {
+                    blend: decode_blend_mode(clip.blend)?,
+                    alpha: clip.alpha,
+                }
+            }
+            DRAWTAG_ENDCLIP => Draw::EndClip,
+            _ => return None,
+        })
+    }
+}
+
+impl<'a> Iterator for Decoder<'a> {
+    type Item = DrawObject<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let draw_tag = *self.data.drawtag_stream.get(self.draw_ix)?;
+        let mut segments = vec![];
+        let mut has_brush_transform = false;
+        loop {
+            let tag = *self.data.tag_stream.get(self.tag_ix)?;
+            self.tag_ix += 1;
+            match tag {
+                PATHTAG_TRANSFORM => {
+                    self.trans_ix += 1;
+                    has_brush_transform = !segments.is_empty();
+                }
+                PATHTAG_LINEWIDTH => self.linewidth_ix += 1,
+                PATHTAG_PATH => break,
+                _ => segments.push(self.segment(tag)?),
+            }
+        }
+        let draw = self.draw(draw_tag)?;
+        let object = DrawObject {
+            index: self.draw_ix,
+            transform: self.transform(),
+            has_brush_transform,
+            linewidth: self.linewidth(),
+            segments,
+            draw,
+        };
+        // See map_draw_tag in drawtag.wgsl.
+        self.drawdata_offset += ((draw_tag >> 2) & 0x7) as usize * 4;
+        self.draw_ix += 1;
+        Some(object)
+    }
+}
+
+/// A drawing operation reconstructed from scene data.
+///
+/// The variants mirror the methods of [SceneBuilder]. Properties that are not
+/// encoded in the streams are replaced by defaults: fills use the non-zero rule,
+/// strokes only carry their width, and gradients use [Extend::Pad].
+#[derive(Clone, Debug)]
+pub enum Command {
+    Fill {
+        transform: Affine,
+        brush: Brush,
+        brush_transform: Option<Affine>,
+        path: BezPath,
+    },
+    Stroke {
+        style: Stroke,
+        transform: Affine,
+        brush: Brush,
+        brush_transform: Option<Affine>,
+        path: BezPath,
+    },
+    PushLayer {
+        blend: BlendMode,
+        alpha: f32,
+        transform: Affine,
+        path: BezPath,
+    },
+    PopLayer,
+}
+
+impl Command {
+    /// Replays the command into a builder.
+    pub fn apply(&self, builder: &mut SceneBuilder) {
+        match self {
+            Self::Fill {
+                transform,
+                brush,
+                brush_transform,
+                path,
+            } => builder.fill(Fill::NonZero, *transform, brush, *brush_transform, path),
+            Self::Stroke {
+                style,
+                transform,
+                brush,
+                brush_transform,
+                path,
+            } => builder.stroke(style, *transform, brush, *brush_transform, path),
+            Self::PushLayer {
+                blend,
+                alpha,
+                transform,
+                path,
+            } => builder.push_layer(*blend, *alpha, *transform, path),
+            Self::PopLayer => builder.pop_layer(),
+        }
+    }
+}
+
+/// Iterator over the commands that reproduce scene data.
+pub struct Commands<'a> {
+    decoder: Decoder<'a>,
+}
+
+impl<'a> Commands<'a> {
+    pub fn new(data: &'a SceneData) -> Self {
+        Self {
+            decoder: Decoder::new(data),
+        }
+    }
+}
+
+impl<'a> Iterator for Commands<'a> {
+    type Item = Command;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let object = self.decoder.next()?;
+        // Segments carry the transform passed to the builder, while the transform at
+        // the end of the path includes the brush transform, if any.
+        let transform = affine_from_f32(
+            &object
+                .segments
+                .first()
+                .map_or(object.transform, |segment| segment.transform),
+        );
+        let brush_transform = if object.has_brush_transform {
+            Some(transform.inverse() * affine_from_f32(&object.transform))
+        } else {
+            None
+        };
+        let path = bez_path(&object.segments);
+        let brush: Brush = match object.draw {
+            Draw::Color { rgba } => unpremultiply(rgba).into(),
+            Draw::LinearGradient { p0, p1, stops } => LinearGradient {
+                start: to_point(p0),
+                end: to_point(p1),
+                stops: stops.iter().copied().collect(),
+                extend: Extend::Pad,
+            }
+            .into(),
+            Draw::RadialGradient {
+                p0,
+                p1,
+                r0,
+                r1,
+                stops,
+            } => RadialGradient {
+                start_center: to_point(p0),
+                start_radius: r0,
+                end_center: to_point(p1),
+                end_radius: r1,
+                stops: stops.iter().copied().collect(),
+                extend: Extend::Pad,
+            }
+            .into(),
+            Draw::BeginClip { blend, alpha } => {
+                return Some(Command::PushLayer {
+                    blend,
+                    alpha,
+                    transform,
+                    path,
+                })
+            }
+            Draw::EndClip => return Some(Command::PopLayer),
+        };
+        Some(if object.linewidth >= 0.0 {
+            Command::Stroke {
+                style: Stroke::new(object.linewidth),
+                transform,
+                brush,
+                brush_transform,
+                path,
+            }
+        } else {
+            Command::Fill {
+                transform,
+                brush,
+                brush_transform,
+                path,
+            }
+        })
+    }
+}
+
+/// Rebuilds a path from its segments, starting a subpath after each end segment.
+///
+/// Closed subpaths come back as explicit segments, which the builder encodes the same
+/// way as the original close.
+fn bez_path(segments: &[PathSegment]) -> BezPath {
+    let mut path = BezPath::new();
+    let mut subpath_start = true;
+    for segment in segments {
+        if subpath_start {
+            path.move_to(to_point(segment.segment.points()[0]));
+        }
+        match segment.segment {
+            Segment::Line([_, p1]) => path.line_to(to_point(p1)),
+            Segment::Quad([_, p1, p2]) => path.quad_to(to_point(p1), to_point(p2)),
+            Segment::Cubic([_, p1, p2, p3]) => {
+                path.curve_to(to_point(p1), to_point(p2), to_point(p3))
+            }
+        }
+        subpath_start = segment.subpath_end;
+    }
+    path
+}
+
+fn to_point(point: [f32; 2]) -> Point {
+    Point::new(point[0] as f64, point[1] as f64)
+}
+
+/// Inverts `Color::to_premul_u32`. Colors that differ only in the color channels of
+/// translucent pixels collapse to one value, which premultiplies back to the same
+/// encoding.
+fn unpremultiply(rgba: u32) -> Color {
+    let a = (rgba & 0xff) as u8;
+    if a == 0 {
+        return Color::rgba8(0, 0, 0, 0);
+    }
+    let channel = |shift: u32| {
+        let premul = ((rgba >> shift) & 0xff) as f64;
+        (premul * 255.0 / a as f64).round().min(255.0) as u8
+    };
+    Color::rgba8(channel(24), channel(16), channel(8), a)
+}
+
+/// Decodes a blend mode packed by `encode_blend_mode`.
+pub fn decode_blend_mode(blend: u32) -> Option<BlendMode> {
+    const MIX: [Mix; 16] = [
+        Mix::Normal,
+        Mix::Multiply,
+        Mix::Screen,
+        Mix::Overlay,
+        Mix::Darken,
+        Mix::Lighten,
+        Mix::ColorDodge,
+        Mix::ColorBurn,
+        Mix::HardLight,
+        Mix::SoftLight,
+        Mix::Difference,
+        Mix::Exclusion,
+        Mix::Hue,
+        Mix::Saturation,
+        Mix::Color,
+        Mix::Luminosity,
+    ];
+    const COMPOSE: [Compose; 14] = [
+        Compose::Clear,
+        Compose::Copy,
+        Compose::Dest,
+        Compose::SrcOver,
+        Compose::DestOver,
+        Compose::SrcIn,
+        Compose::DestIn,
+        Compose::SrcOut,
+        Compose::DestOut,
+        Compose::SrcAtop,
+        Compose::DestAtop,
+        Compose::Xor,
+        Compose::Plus,
+        Compose::PlusLighter,
+    ];
+    let mix = match blend >> 8 {
+        128 => Mix::Clip,
+        mix => *MIX.get(mix as usize)?,
+    };
+    let compose = *COMPOSE.get((blend & 0xff) as usize)?;
+    Some(BlendMode { mix, compose })
+}
+
+/// Writes a structured listing of the scene data.
+///
+/// The output only depends on the contents of the streams, so it is suitable for
+/// snapshot tests.
+pub fn dump(data: &SceneData) -> String {
+    let mut out = String::new();
+    write_dump(&mut out, data).unwrap();
+    out
+}
+
+fn write_dump(out: &mut String, data: &SceneData) -> fmt::Result {
+    writeln!(
+        out,
+        "paths {}, segments {}, clips {}, transforms {}, linewidths {}, stops {}",
+        data.n_path,
+        data.n_pathseg,
+        data.n_clip,
+        data.transform_stream.len(),
+        data.linewidth_stream.len(),
+        data.resources.stops.len(),
+    )?;
+    let mut decoded = 0;
+    for object in Decoder::new(data) {
+        write!(out, "draw {}: ", object.index)?;
+        match &object.draw {
+            Draw::Color { rgba } => write!(out, "color #{rgba:08x}")?,
+            Draw::LinearGradient { p0, p1, stops } => {
+                write!(out, "linear gradient {} {} ", Pt(*p0), Pt(*p1))?;
+                write_stops(out, stops)?;
+            }
+            Draw::RadialGradient {
+                p0,
+                p1,
+                r0,
+                r1,
+                stops,
+            } => {
+                write!(out, "radial gradient {} {r0} {} {r1} ", Pt(*p0), Pt(*p1))?;
+                write_stops(out, stops)?;
+            }
+            Draw::BeginClip { blend, alpha } => write!(
+                out,
+                "begin clip {:?} {:?} alpha {alpha}",
+                blend.mix, blend.compose
+            )?,
+            Draw::EndClip => write!(out, "end clip")?,
+        }
+        if object.linewidth >= 0.0 && object.draw != Draw::EndClip {
+            writeln!(out, ", stroke {}", object.linewidth)?;
+        } else {
+            writeln!(out)?;
+        }
+        let mut transform = object.transform;
+        if let Some(first) = object.segments.first() {
+            transform = first.transform;
+            writeln!(out, "  transform {}", Tf(transform))?;
+            if object.has_brush_transform {
+                writeln!(out, "  brush transform {}", Tf(object.transform))?;
+            }
+        }
+        for segment in &object.segments {
+            if segment.transform != transform {
+                transform = segment.transform;
+                writeln!(out, "  transform {}", Tf(transform))?;
+            }
+            let name = match segment.segment {
+                Segment::Line(_) => "line",
+                Segment::Quad(_) => "quad",
+                Segment::Cubic(_) => "cubic",
+            };
+            write!(out, "  {name}")?;
+            for point in segment.segment.points() {
+                write!(out, " {}", Pt(*point))?;
+            }
+            writeln!(out, "{}", if segment.subpath_end { " end" } else { "" })?;
+        }
+        decoded += 1;
+    }
+    if decoded != data.drawtag_stream.len() {
+        writeln!(
+            out,
+            "error: decoding stopped after {decoded} of {} draw objects",
+            data.drawtag_stream.len()
+        )?;
+    }
+    Ok(())
+}
+
+fn write_stops(out: &mut String, stops: &[ColorStop]) -> fmt::Result {
+    write!(out, "stops [")?;
+    for (i, stop) in stops.iter().enumerate() {
+        let color = stop.color;
+        if i != 0 {
+            write!(out, ", ")?;
+        }
+        write!(
+            out,
+            "{} #{:02x}{:02x}{:02x}{:02x}",
+            stop.offset, color.r, color.g, color.b, color.a
+        )?;
+    }
+    write!(out, "]")
+}
+
+struct Pt([f32; 2]);
+
+impl fmt::Display for Pt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({} {})", self.0[0], self.0[1])
+    }
+}
+
+struct Tf([f32; 6]);
+
+impl fmt::Display for Tf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "[{a} {b} {c} {d} {e} {g}]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Affine, Circle, Rect};
+    use peniko::{Fill, Join};
+
+    use super::*;
+    use crate::SceneFragment;
+
+    fn gradient(extend: Extend) -> LinearGradient {
+        LinearGradient {
+            start: (0.0, 0.0).into(),
+            end: (10.0, 0.0).into(),
+            stops: [
+                (0.0, Color::rgb8(0, 0, 0)).into(),
+                (1.0, Color::rgb8(255, 255, 255)).into(),
+            ]
+            .into_iter()
+            .collect(),
+            extend,
+        }
+    }
+
+    #[test]
+    fn dump_snapshot() {
+        let mut fragment = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut fragment);
+        builder.fill(
+            Fill::NonZero,
+            Affine::translate((10.0, 20.0)),
+            Color::rgb8(255, 0, 0),
+            None,
+            &Rect::new(0.0, 0.0, 4.0, 2.0),
+        );
+        builder.push_layer(
+            Mix::Multiply,
+            0.5,
+            Affine::IDENTITY,
+            &Rect::new(1.0, 1.0, 9.0, 9.0),
+        );
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            &gradient(Extend::Pad),
+            Some(Affine::scale(2.0)),
+            &Rect::new(2.5, 2.5, 8.0, 8.0),
+        );
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.quad_to((5.0, 5.0), (10.0, 0.0));
+        builder.stroke(
+            &Stroke::new(2.0),
+            Affine::IDENTITY,
+            Color::rgb8(0, 0, 255),
+            None,
+            &path,
+        );
+        builder.pop_layer();
+        builder.finish();
+        let expected = "\
+paths 5, segments 13, clips 2, transforms 4, linewidths 2, stops 2
+draw 0: color #ff0000ff
+  transform [1 0 0 1 10 20]
+  line (0 0) (4 0)
+  line (4 0) (4 2)
+  line (4 2) (0 2)
+  line (0 2) (0 0) end
+draw 1: begin clip Multiply SrcOver alpha 0.5
+  transform [1 0 0 1 0 0]
+  line (1 1) (9 1)
+  line (9 1) (9 9)
+  line (9 9) (1 9)
+  line (1 9) (1 1) end
+draw 2: linear gradient (0 0) (10 0) stops [0 #000000ff, 1 #ffffffff]
+  transform [1 0 0 1 0 0]
+  brush transform [2 0 0 2 0 0]
+  line (2.5 2.5) (8 2.5)
+  line (8 2.5) (8 8)
+  line (8 8) (2.5 8)
+  line (2.5 8) (2.5 2.5) end
+draw 3: color #0000ffff, stroke 2
+  transform [1 0 0 1 0 0]
+  quad (0 0) (5 5) (10 0) end
+draw 4: end clip
+";
+        assert_eq!(dump(&fragment.data), expected);
+    }
+
+    /// Replaying the commands encodes the same streams, including for the properties
+    /// that [Command] doesn't preserve: the fill rule, the stroke style apart from its
+    /// width, the gradient extend mode and the color channels of translucent colors.
+    #[test]
+    fn commands_round_trip() {
+        let mut fragment = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut fragment);
+        builder.set_tag(3);
+        builder.fill(
+            Fill::EvenOdd,
+            Affine::translate((10.0, 20.0)),
+            Color::rgba8(255, 128, 7, 100),
+            None,
+            &Circle::new((5.0, 5.0), 4.5),
+        );
+        builder.push_layer(
+            BlendMode::new(Mix::Screen, Compose::SrcAtop),
+            0.75,
+            Affine::rotate(0.5),
+            &Rect::new(0.0, 0.0, 100.0, 100.0),
+        );
+        builder.fill(
+            Fill::NonZero,
+            Affine::translate((1.0, 2.0)),
+            &gradient(Extend::Repeat),
+            Some(Affine::scale(2.0)),
+            &Rect::new(2.5, 2.5, 8.0, 8.0),
+        );
+        let radial = RadialGradient {
+            start_center: (1.0, 1.0).into(),
+            start_radius: 0.5,
+            end_center: (2.0, 2.0).into(),
+            end_radius: 8.0,
+            stops: gradient(Extend::Pad).stops,
+            extend: Extend::Reflect,
+        };
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.curve_to((1.0, 5.0), (9.0, 5.0), (10.0, 0.0));
+        path.close_path();
+        path.move_to((20.0, 0.0));
+        path.line_to((30.0, 0.5));
+        let mut style = Stroke::new(3.0);
+        style.join = Join::Miter;
+        builder.stroke(&style, Affine::IDENTITY, &radial, None, &path);
+        builder.pop_layer();
+        builder.finish();
+
+        let data = &fragment.data;
+        let mut replayed = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut replayed);
+        builder.set_tag(3);
+        for command in Commands::new(data) {
+            command.apply(&mut builder);
+        }
+        builder.finish();
+
+        let replayed = &replayed.data;
+        assert_eq!(replayed.transform_stream, data.transform_stream);
+        assert_eq!(replayed.tag_stream, data.tag_stream);
+        assert_eq!(replayed.pathseg_stream, data.pathseg_stream);
+        assert_eq!(replayed.linewidth_stream, data.linewidth_stream);
+        assert_eq!(replayed.drawtag_stream, data.drawtag_stream);
+        assert_eq!(replayed.drawdata_stream, data.drawdata_stream);
+        assert_eq!(replayed.user_tags, data.user_tags);
+        assert_eq!(
+            (replayed.n_path, replayed.n_pathseg, replayed.n_clip),
+            (data.n_path, data.n_pathseg, data.n_clip)
+        );
+        assert_eq!(replayed.resources.stops, data.resources.stops);
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Diagnostics recorded by a checked scene builder.
+
+use std::fmt;
+
+use peniko::kurbo::{Affine, PathEl, Point};
+use peniko::ColorStop;
+
+/// Misuse of a [SceneBuilder](super::SceneBuilder) recorded in checked mode.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    /// Index of the draw object that was being encoded, which is the number of draw
+    /// objects encoded before the offending call.
+    pub draw_index: usize,
+    pub kind: DiagnosticKind,
+}
+
+/// Kind of a [Diagnostic].
+#[derive(Clone, PartialEq, Debug)]
+#[non_exhaustive]
+pub enum DiagnosticKind {
+    /// A layer was popped without a matching push. The pop was ignored.
+    UnbalancedPop,
+    /// Layers were still open when the builder was finished. They were closed.
+    UnclosedLayers(u32),
+    /// A path, gradient, line width or alpha contains a NaN or infinite value.
+    NonFinite,
+    /// A transform contains a NaN or infinite value, or can't be inverted.
+    DegenerateTransform(Affine),
+    /// A gradient has no stops.
+    EmptyGradient,
+    /// The offsets of the stops of a gradient are not in increasing order, or are
+    /// not finite.
+    StopsOutOfOrder,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "draw object {}: ", self.draw_index)?;
+        match &self.kind {
+            DiagnosticKind::UnbalancedPop => write!(f, "pop without a matching push"),
+            DiagnosticKind::UnclosedLayers(n) => write!(f, "{n} layer(s) left open"),
+            DiagnosticKind::NonFinite => write!(f, "non-finite value"),
+            DiagnosticKind::DegenerateTransform(transform) => {
+                write!(f, "degenerate transform {:?}", transform.as_coeffs())
+            }
+            DiagnosticKind::EmptyGradient => write!(f, "gradient without stops"),
+            DiagnosticKind::StopsOutOfOrder => write!(f, "gradient stops out of order"),
+        }
+    }
+}
+
+pub fn is_degenerate(transform: &Affine) -> bool {
+    let det = transform.determinant();
+    !transform.is_finite() || !det.is_finite() || det == 0.0
+}
+
+pub fn is_finite_el(el: &PathEl) -> bool {
+    match el {
+        PathEl::MoveTo(p0) | PathEl::LineTo(p0) => p0.is_finite(),
+        PathEl::QuadTo(p0, p1) => p0.is_finite() && p1.is_finite(),
+        PathEl::CurveTo(p0, p1, p2) => p0.is_finite() && p1.is_finite() && p2.is_finite(),
+        PathEl::ClosePath => true,
+    }
+}
+
+pub fn is_finite_points(points: &[Point]) -> bool {
+    points.iter().all(|p| p.is_finite())
+}
+
+pub fn stops_in_order(stops: &[ColorStop]) -> bool {
+    stops.iter().all(|stop| stop.offset.is_finite())
+        && stops
+            .windows(2)
+            .all(|pair| pair[0].offset <= pair[1].offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Affine, BezPath, Rect, Shape};
+    use peniko::{Color, Fill, Mix};
+
+    use super::{Diagnostic, DiagnosticKind};
+    use crate::{Error, Scene, SceneBuilder};
+
+    fn diagnostics(build: impl FnOnce(&mut SceneBuilder)) -> (Scene, Vec<Diagnostic>) {
+        let mut scene = Scene::new();
+        let mut builder = SceneBuilder::for_scene(&mut scene).strict();
+        build(&mut builder);
+        let diagnostics = match builder.finish_checked() {
+            Ok(()) => vec![],
+            Err(Error::SceneDiagnostics(diagnostics)) => diagnostics,
+            Err(e) => panic!("unexpected error: {e}"),
+        };
+        (scene, diagnostics)
+    }
+
+    fn fill(builder: &mut SceneBuilder, shape: &impl Shape) {
+        let color = Color::rgb8(255, 0, 0);
+        builder.fill(Fill::NonZero, Affine::IDENTITY, color, None, shape);
+    }
+
+    fn nan_path() -> BezPath {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((f64::NAN, 10.0));
+        path.line_to((10.0, 10.0));
+        path.close_path();
+        path
+    }
+
+    fn diagnostic(draw_index: usize, kind: DiagnosticKind) -> Diagnostic {
+        Diagnostic { draw_index, kind }
+    }
+
+    #[test]
+    fn valid_scene() {
+        let (_, diagnostics) = diagnostics(|b| {
+            b.push_layer(
+                Mix::Normal,
+                1.0,
+                Affine::IDENTITY,
+                &Rect::new(0.0, 0.0, 10.0, 10.0),
+            );
+            fill(b, &Rect::new(0.0, 0.0, 10.0, 10.0));
+            b.pop_layer();
+        });
+        assert_eq!(diagnostics, []);
+    }
+
+    #[test]
+    fn unbalanced_pop() {
+        let (scene, diagnostics) = diagnostics(|b| {
+            fill(b, &Rect::new(0.0, 0.0, 10.0, 10.0));
+            b.pop_layer();
+        });
+        assert_eq!(diagnostics, [diagnostic(1, DiagnosticKind::UnbalancedPop)]);
+        // The pop is ignored.
+        assert_eq!(scene.data().n_clip, 0);
+    }
+
+    #[test]
+    fn unclosed_layers() {
+        let (scene, diagnostics) = diagnostics(|b| {
+            b.push_layer(
+                Mix::Normal,
+                1.0,
+                Affine::IDENTITY,
+                &Rect::new(0.0, 0.0, 10.0, 10.0),
+            );
+            fill(b, &Rect::new(0.0, 0.0, 10.0, 10.0));
+        });
+        assert_eq!(
+            diagnostics,
+            [diagnostic(2, DiagnosticKind::UnclosedLayers(1))]
+        );
+        // The layer is closed.
+        assert_eq!(scene.data().n_clip, 2);
+    }
+
+    #[test]
+    fn nan_coordinates() {
+        let (scene, diagnostics) = diagnostics(|b| {
+            fill(b, &Rect::new(0.0, 0.0, 10.0, 10.0));
+            fill(b, &nan_path());
+        });
+        assert_eq!(diagnostics, [diagnostic(1, DiagnosticKind::NonFinite)]);
+        // The path is encoded as is.
+        assert_eq!(scene.data().n_path, 2);
+    }
+
+    #[test]
+    #[cfg(not(feature = "checked"))]
+    fn unchecked_builder() {
+        let mut scene = Scene::new();
+        let mut builder = SceneBuilder::for_scene(&mut scene);
+        fill(&mut builder, &nan_path());
+        builder.pop_layer();
+        assert!(builder.finish_checked().is_ok());
+    }
+}
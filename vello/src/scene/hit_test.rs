@@ -0,0 +1,197 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Hit testing of encoded scenes.
+
+use peniko::kurbo::{BezPath, ParamCurveNearest, Point, Shape};
+
+use super::decode::{Decoder, Draw, DrawObject, Segment};
+use super::{affine_from_f32, SceneData};
+
+/// Identifies a draw object in a scene.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct DrawId {
+    /// Index of the draw object in the scene, counting layer boundaries.
+    pub index: usize,
+    /// Tag set with [SceneBuilder::set_tag](super::SceneBuilder::set_tag).
+    pub tag: u32,
+}
+
+/// Returns the draw objects containing the point, topmost first.
+pub fn hit_test_all(data: &SceneData, point: Point) -> Vec<DrawId> {
+    let mut hits = vec![];
+    // Whether the point is inside each of the enclosing clips and their parents.
+    let mut clips: Vec<bool> = vec![];
+    for object in Decoder::new(data) {
+        let inside_clips = clips.last().copied().unwrap_or(true);
+        match object.draw {
+            Draw::BeginClip { .. } => {
+                clips.push(inside_clips && device_path(&object).winding(point) != 0);
+            }
+            Draw::EndClip => {
+                clips.pop();
+            }
+            _ => {
+                if inside_clips && contains(&object, point) {
+                    hits.push(DrawId {
+                        index: object.index,
+                        tag: data.user_tags.get(object.index).copied().unwrap_or(0),
+                    });
+                }
+            }
+        }
+    }
+    hits.reverse();
+    hits
+}
+
+fn contains(object: &DrawObject, point: Point) -> bool {
+    let path = device_path(object);
+    if object.linewidth < 0.0 {
+        return path.winding(point) != 0;
+    }
+    // The line width is scaled the same way as in draw_leaf.wgsl.
+    let scale = match object.segments.first() {
+        Some(segment) => affine_from_f32(&segment.transform)
+            .determinant()
+            .abs()
+            .sqrt(),
+        None => return false,
+    };
+    let half_width = 0.5 * object.linewidth as f64 * scale;
+    let mut segments = path.segments();
+    segments.any(|segment| segment.nearest(point, 1e-6).distance_sq <= half_width * half_width)
+}
+
+/// Returns the path of a draw object in device space.
+fn device_path(object: &DrawObject) -> BezPath {
+    let mut path = BezPath::new();
+    let mut subpath_start = true;
+    for segment in &object.segments {
+        let transform = affine_from_f32(&segment.transform);
+        let p = |ix: usize| {
+            let point = segment.segment.points()[ix];
+            transform * Point::new(point[0] as f64, point[1] as f64)
+        };
+        if subpath_start {
+            path.move_to(p(0));
+        }
+        match segment.segment {
+            Segment::Line(_) => path.line_to(p(1)),
+            Segment::Quad(_) => path.quad_to(p(1), p(2)),
+            Segment::Cubic(_) => path.curve_to(p(1), p(2), p(3)),
+        }
+        subpath_start = segment.subpath_end;
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Affine, BezPath, Point, Rect, Shape};
+    use peniko::{Color, Fill, Mix, Stroke};
+
+    use super::DrawId;
+    use crate::{Scene, SceneBuilder};
+
+    fn scene(build: impl FnOnce(&mut SceneBuilder)) -> Scene {
+        let mut scene = Scene::new();
+        let mut builder = SceneBuilder::for_scene(&mut scene);
+        build(&mut builder);
+        builder.finish();
+        scene
+    }
+
+    fn fill(builder: &mut SceneBuilder, shape: &impl Shape) {
+        let color = Color::rgb8(255, 0, 0);
+        builder.fill(Fill::NonZero, Affine::IDENTITY, color, None, shape);
+    }
+
+    fn hit(scene: &Scene, x: f64, y: f64) -> bool {
+        !scene.hit_test_all(Point::new(x, y)).is_empty()
+    }
+
+    /// Returns a square with an inner square, wound the other way if `hole` is set.
+    fn square_with_inner(hole: bool) -> BezPath {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((100.0, 0.0));
+        path.line_to((100.0, 100.0));
+        path.line_to((0.0, 100.0));
+        path.close_path();
+        let inner = [(25.0, 25.0), (75.0, 25.0), (75.0, 75.0), (25.0, 75.0)];
+        path.move_to(inner[0]);
+        for ix in 1..4 {
+            path.line_to(inner[if hole { 4 - ix } else { ix }]);
+        }
+        path.close_path();
+        path
+    }
+
+    #[test]
+    fn winding() {
+        let scene_with_hole = scene(|b| fill(b, &square_with_inner(true)));
+        assert!(hit(&scene_with_hole, 10.0, 10.0));
+        assert!(!hit(&scene_with_hole, 50.0, 50.0));
+        assert!(!hit(&scene_with_hole, 150.0, 50.0));
+        let scene = scene(|b| fill(b, &square_with_inner(false)));
+        assert!(hit(&scene, 10.0, 10.0));
+        assert!(hit(&scene, 50.0, 50.0));
+    }
+
+    #[test]
+    fn stroke_half_width() {
+        let rect = Rect::new(0.0, 0.0, 100.0, 100.0);
+        let color = Color::rgb8(0, 0, 255);
+        let stroked = scene(|b| b.stroke(&Stroke::new(4.0), Affine::IDENTITY, color, None, &rect));
+        assert!(hit(&stroked, 50.0, 1.9));
+        assert!(hit(&stroked, 50.0, -1.9));
+        assert!(!hit(&stroked, 50.0, 2.1));
+        // The interior of a stroke is not part of it.
+        assert!(!hit(&stroked, 50.0, 50.0));
+        // The half width is scaled by the transform.
+        let scaled = scene(|b| b.stroke(&Stroke::new(4.0), Affine::scale(2.0), color, None, &rect));
+        assert!(hit(&scaled, 100.0, 3.9));
+        assert!(!hit(&scaled, 100.0, 4.1));
+    }
+
+    #[test]
+    fn clip_masking() {
+        let scene = scene(|b| {
+            b.push_layer(
+                Mix::Normal,
+                1.0,
+                Affine::IDENTITY,
+                &Rect::new(0.0, 0.0, 50.0, 50.0),
+            );
+            b.set_tag(1);
+            fill(b, &Rect::new(0.0, 0.0, 100.0, 100.0));
+            b.pop_layer();
+            b.set_tag(2);
+            fill(b, &Rect::new(25.0, 25.0, 75.0, 75.0));
+        });
+        // Draw objects are indexed with the layer boundaries, and hits are topmost first.
+        let hits = scene.hit_test_all(Point::new(30.0, 30.0));
+        let expected = [DrawId { index: 3, tag: 2 }, DrawId { index: 1, tag: 1 }];
+        assert_eq!(hits, expected);
+        // The layer masks the fill it contains, and is never hit itself.
+        let hits = scene.hit_test_all(Point::new(60.0, 60.0));
+        assert_eq!(hits, [DrawId { index: 3, tag: 2 }]);
+        assert!(!hit(&scene, 90.0, 90.0));
+        let hits = scene.hit_test_all(Point::new(10.0, 10.0));
+        assert_eq!(hits, [DrawId { index: 1, tag: 1 }]);
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Merging of scene fragments on multiple threads.
+//!
+//! The offsets of each fragment in the merged streams are computed up front, so that
+//! the streams can be grown once and each fragment copied into its own part of them
+//! independently.
+
+use rayon::prelude::*;
+
+use super::*;
+
+/// Destination of a fragment in the merged streams.
+struct Chunk<'a> {
+    transforms: &'a mut [[f32; 6]],
+    pathseg_deltas: &'a mut [i32],
+    tags: &'a mut [u8],
+    pathsegs: &'a mut [u8],
+    linewidths: &'a mut [f32],
+    drawtags: &'a mut [u32],
+    drawdata: &'a mut [u8],
+    user_tags: &'a mut [u32],
+}
+
+impl SceneData {
+    /// Appends the streams of several scenes in order, copying them in parallel.
+    ///
+    /// The result is the same as appending each scene with [SceneData::append].
+    pub(super) fn append_many(&mut self, others: &[(&SceneData, Option<Affine>)]) {
+        // Delta of the path segment offsets of each scene; see append.
+        let mut delta_bases = Vec::with_capacity(others.len());
+        let mut pathseg_len = self.pathseg_stream.len();
+        let mut drawdata_len = self.drawdata_stream.len();
+        for (other, _) in others {
+            delta_bases.push((pathseg_len / 4) as i32 - self.pathseg_words as i32);
+            let stops_base = self.resources.stops.len();
+            self.resources
+                .stops
+                .extend_from_slice(&other.resources.stops);
+            self.resources
+                .patches
+                .extend(other.resources.patches.iter().map(|patch| match patch {
+                    ResourcePatch::Ramp { offset, stops } => ResourcePatch::Ramp {
+                        offset: drawdata_len + offset,
+                        stops: stops.start + stops_base..stops.end + stops_base,
+                    },
+                }));
+            pathseg_len += other.pathseg_stream.len();
+            drawdata_len += other.drawdata_stream.len();
+            self.pathseg_words += other.pathseg_words;
+            self.n_path += other.n_path;
+            self.n_pathseg += other.n_pathseg;
+            self.n_clip += other.n_clip;
+        }
+        self.user_tags.resize(self.drawtag_stream.len(), 0);
+        let mut transforms = split(&mut self.transform_stream, others, |data| {
+            data.transform_stream.len()
+        });
+        let mut pathseg_deltas = split(&mut self.pathseg_delta_stream, others, |data| {
+            data.pathseg_delta_stream.len()
+        });
+        let mut tags = split(&mut self.tag_stream, others, |data| data.tag_stream.len());
+        let mut pathsegs = split(&mut self.pathseg_stream, others, |data| {
+            data.pathseg_stream.len()
+        });
+        let mut linewidths = split(&mut self.linewidth_stream, others, |data| {
+            data.linewidth_stream.len()
+        });
+        let mut drawtags = split(&mut self.drawtag_stream, others, |data| {
+            data.drawtag_stream.len()
+        });
+        let mut drawdata = split(&mut self.drawdata_stream, others, |data| {
+            data.drawdata_stream.len()
+        });
+        let mut user_tags = split(&mut self.user_tags, others, |data| {
+            data.drawtag_stream.len()
+        });
+        let chunks: Vec<_> = others
+            .iter()
+            .map(|_| Chunk {
+                transforms: transforms.next().unwrap(),
+                pathseg_deltas: pathseg_deltas.next().unwrap(),
+                tags: tags.next().unwrap(),
+                pathsegs: pathsegs.next().unwrap(),
+                linewidths: linewidths.next().unwrap(),
+                drawtags: drawtags.next().unwrap(),
+                drawdata: drawdata.next().unwrap(),
+                user_tags: user_tags.next().unwrap(),
+            })
+            .collect();
+        chunks
+            .into_par_iter()
+            .zip(others.par_iter())
+            .zip(delta_bases.par_iter())
+            .for_each(|((chunk, (other, transform)), delta_base)| {
+                match transform {
+                    Some(transform) => {
+                        for (dst, src) in chunk.transforms.iter_mut().zip(&other.transform_stream) {
+                            *dst = affine_to_f32(&(*transform * affine_from_f32(src)));
+                        }
+                    }
+                    None => chunk.transforms.copy_from_slice(&other.transform_stream),
+                }
+                for (dst, src) in chunk
+                    .pathseg_deltas
+                    .iter_mut()
+                    .zip(&other.pathseg_delta_stream)
+                {
+                    *dst = src + delta_base;
+                }
+                chunk.tags.copy_from_slice(&other.tag_stream);
+                chunk.pathsegs.copy_from_slice(&other.pathseg_stream);
+                chunk.linewidths.copy_from_slice(&other.linewidth_stream);
+                chunk.drawtags.copy_from_slice(&other.drawtag_stream);
+                chunk.drawdata.copy_from_slice(&other.drawdata_stream);
+                // The remaining tags are already zero.
+                let n_user_tags = other.user_tags.len().min(chunk.user_tags.len());
+                chunk.user_tags[..n_user_tags].copy_from_slice(&other.user_tags[..n_user_tags]);
+            });
+    }
+}
+
+/// Grows a stream by the combined length of a stream of each scene, and returns the
+/// new part of it split by scene.
+fn split<'a, T: Clone + Default>(
+    stream: &'a mut Vec<T>,
+    others: &[(&SceneData, Option<Affine>)],
+    len: impl Fn(&SceneData) -> usize,
+) -> impl Iterator<Item = &'a mut [T]> {
+    let start = stream.len();
+    let total: usize = others.iter().map(|(other, _)| len(other)).sum();
+    stream.resize(start + total, T::default());
+    let mut rest = &mut stream[start..];
+    let mut parts = Vec::with_capacity(others.len());
+    for (other, _) in others {
+        let (part, tail) = std::mem::take(&mut rest).split_at_mut(len(other));
+        parts.push(part);
+        rest = tail;
+    }
+    parts.into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Circle, Rect};
+    use peniko::{Color, Fill, LinearGradient, Stroke};
+
+    use super::*;
+    use crate::SceneFragment;
+
+    fn gradient() -> LinearGradient {
+        LinearGradient {
+            start: (0.0, 0.0).into(),
+            end: (10.0, 0.0).into(),
+            stops: [
+                (0.0, Color::rgb8(255, 0, 0)).into(),
+                (1.0, Color::rgb8(0, 0, 255)).into(),
+            ]
+            .into_iter()
+            .collect(),
+            extend: Default::default(),
+        }
+    }
+
+    /// Builds fragments with gradients, user tags on some draws only, strokes and
+    /// instanced path data.
+    fn fragments() -> Vec<SceneFragment> {
+        let mut shape = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut shape);
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            &gradient(),
+            None,
+            &Circle::new((5.0, 5.0), 4.5),
+        );
+        builder.finish();
+
+        let mut tagged = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut tagged);
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            Color::rgb8(0, 255, 0),
+            None,
+            &Rect::new(0.0, 0.0, 10.0, 10.0),
+        );
+        builder.set_tag(3);
+        builder.stroke(
+            &Stroke::new(2.0),
+            Affine::scale(2.0),
+            &gradient(),
+            None,
+            &Rect::new(1.5, 1.5, 8.5, 8.5),
+        );
+        builder.set_tag(0);
+        builder.fill(
+            Fill::EvenOdd,
+            Affine::IDENTITY,
+            Color::rgb8(0, 0, 0),
+            None,
+            &Circle::new((5.0, 5.0), 2.0),
+        );
+        builder.finish();
+
+        let mut instanced = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut instanced);
+        builder.set_tag(5);
+        builder.append_instance(&shape, None);
+        builder.append_instance(&shape, Some(Affine::translate((20.0, 0.0))));
+        builder.finish();
+
+        vec![shape, tagged, instanced]
+    }
+
+    fn prefix(scene: &mut Scene) -> SceneBuilder<'_> {
+        let mut builder = SceneBuilder::for_scene(scene);
+        builder.set_tag(9);
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            &gradient(),
+            None,
+            &Rect::new(0.0, 0.0, 100.0, 100.0),
+        );
+        builder
+    }
+
+    fn patches(data: &SceneData) -> Vec<(usize, Range<usize>)> {
+        data.resources
+            .patches
+            .iter()
+            .map(|patch| match patch {
+                ResourcePatch::Ramp { offset, stops } => (*offset, stops.clone()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn append_many_matches_append() {
+        let fragments = fragments();
+        let transforms = [None, Some(Affine::translate((50.0, 0.0))), None];
+        let items = fragments.iter().zip(transforms).collect::<Vec<_>>();
+
+        let mut sequential = Scene::new();
+        let mut builder = prefix(&mut sequential);
+        for (fragment, transform) in &items {
+            builder.append(fragment, *transform);
+        }
+        builder.finish();
+
+        let mut parallel = Scene::new();
+        let mut builder = prefix(&mut parallel);
+        builder.append_many(&items);
+        builder.finish();
+
+        let (expected, actual) = (sequential.data(), parallel.data());
+        assert!(expected
+            .pathseg_delta_stream
+            .iter()
+            .any(|&delta| delta != 0));
+        assert_eq!(actual.transform_stream, expected.transform_stream);
+        assert_eq!(actual.pathseg_delta_stream, expected.pathseg_delta_stream);
+        assert_eq!(actual.tag_stream, expected.tag_stream);
+        assert_eq!(actual.pathseg_stream, expected.pathseg_stream);
+        assert_eq!(actual.linewidth_stream, expected.linewidth_stream);
+        assert_eq!(actual.drawtag_stream, expected.drawtag_stream);
+        assert_eq!(actual.drawdata_stream, expected.drawdata_stream);
+        assert_eq!(actual.user_tags, expected.user_tags);
+        assert_eq!(actual.resources.stops, expected.resources.stops);
+        assert_eq!(patches(actual), patches(expected));
+        assert_eq!(
+            (actual.n_path, actual.n_pathseg, actual.n_clip),
+            (expected.n_path, expected.n_pathseg, expected.n_clip)
+        );
+        assert_eq!(actual.pathseg_words, expected.pathseg_words);
+    }
+}
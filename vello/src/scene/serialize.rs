@@ -0,0 +1,534 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Binary serialization of scene fragments.
+//!
+//! The format starts with the magic bytes `VSCN` and a version, followed by the path,
+//! segment and clip counts, the streams and the resources. Each stream is prefixed with
+//! its length in elements. All integers and floats are little endian; the draw data
+//! stream, which is bytes in [SceneData], is stored as 32-bit words and the path
+//! segment stream as f32 or i16 coordinates, according to the path tags that refer
+//! to them.
+//!
+//! On load, the streams are checked for consistency the same way the GPU decodes them,
+//! so that a corrupt file can't produce out of range offsets.
+
+use std::collections::HashSet;
+
+use peniko::{Color, ColorStop};
+
+use super::*;
+use crate::codec::{Reader, Writer};
+use crate::Error;
+
+const MAGIC: &[u8; 4] = b"VSCN";
+const VERSION: u32 = 1;
+
+const PATCH_RAMP: u8 = 0;
+
+/// Serializes the streams of a scene fragment.
+pub fn encode(data: &SceneData) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.data.extend_from_slice(MAGIC);
+    w.u32(VERSION);
+    w.u32(data.n_path);
+    w.u32(data.n_pathseg);
+    w.u32(data.n_clip);
+    w.len(data.transform_stream.len());
+    for transform in &data.transform_stream {
+        for value in transform {
+            w.f32(*value);
+        }
+    }
+    for delta in &data.pathseg_delta_stream {
+        w.u32(*delta as u32);
+    }
+    w.len(data.tag_stream.len());
+    w.data.extend_from_slice(&data.tag_stream);
+    w.pathsegs(data);
+    w.len(data.linewidth_stream.len());
+    for linewidth in &data.linewidth_stream {
+        w.f32(*linewidth);
+    }
+    w.len(data.drawtag_stream.len());
+    for tag in &data.drawtag_stream {
+        w.u32(*tag);
+    }
+    w.len(data.user_tags.len());
+    for tag in &data.user_tags {
+        w.u32(*tag);
+    }
+    w.words(&data.drawdata_stream);
+    w.len(data.resources.stops.len());
+    for stop in &data.resources.stops {
+        w.f32(stop.offset);
+        w.data
+            .extend_from_slice(&[stop.color.r, stop.color.g, stop.color.b, stop.color.a]);
+    }
+    w.len(data.resources.patches.len());
+    for patch in &data.resources.patches {
+        match patch {
+            ResourcePatch::Ramp { offset, stops } => {
+                w.u8(PATCH_RAMP);
+                w.len(*offset);
+                w.len(stops.start);
+                w.len(stops.end);
+            }
+        }
+    }
+    w.data
+}
+
+/// Deserializes and validates the streams of a scene fragment.
+pub fn decode(bytes: &[u8]) -> Result<SceneData, Error> {
+    let mut r = Reader::new(bytes, invalid);
+    if r.take(MAGIC.len())? != MAGIC {
+        return Err(invalid("not a vello scene fragment"));
+    }
+    let version = r.u32()?;
+    if version != VERSION {
+        return Err(Error::InvalidScene(format!(
+            "unsupported scene version {version}"
+        )));
+    }
+    let mut data = SceneData {
+        n_path: r.u32()?,
+        n_pathseg: r.u32()?,
+        n_clip: r.u32()?,
+        ..Default::default()
+    };
+    let n_transform = r.len(24)?;
+    data.transform_stream.reserve(n_transform);
+    for _ in 0..n_transform {
+        let mut transform = [0.0; 6];
+        for value in &mut transform {
+            *value = r.f32()?;
+        }
+        data.transform_stream.push(transform);
+    }
+    for _ in 0..n_transform {
+        data.pathseg_delta_stream.push(r.u32()? as i32);
+    }
+    let n_tag = r.len(1)?;
+    data.tag_stream = r.take(n_tag)?.to_vec();
+    data.pathseg_stream = r.pathsegs(&data)?;
+    let n_linewidth = r.len(4)?;
+    data.linewidth_stream.reserve(n_linewidth);
+    for _ in 0..n_linewidth {
+        data.linewidth_stream.push(r.f32()?);
+    }
+    let n_drawtag = r.len(4)?;
+    data.drawtag_stream.reserve(n_drawtag);
+    for _ in 0..n_drawtag {
+        data.drawtag_stream.push(r.u32()?);
+    }
+    let n_user_tag = r.len(4)?;
+    data.user_tags.reserve(n_user_tag);
+    for _ in 0..n_user_tag {
+        data.user_tags.push(r.u32()?);
+    }
+    data.drawdata_stream = r.words()?;
+    let n_stop = r.len(8)?;
+    data.resources.stops.reserve(n_stop);
+    for _ in 0..n_stop {
+        let offset = r.f32()?;
+        let rgba = r.take(4)?;
+        data.resources.stops.push(ColorStop {
+            offset,
+            color: Color::rgba8(rgba[0], rgba[1], rgba[2], rgba[3]),
+        });
+    }
+    let n_patch = r.len(25)?;
+    for _ in 0..n_patch {
+        match r.take(1)?[0] {
+            PATCH_RAMP => {
+                let offset = r.usize()?;
+                let start = r.usize()?;
+                let end = r.usize()?;
+                data.resources.patches.push(ResourcePatch::Ramp {
+                    offset,
+                    stops: start..end,
+                });
+            }
+            kind => return Err(Error::InvalidScene(format!("unknown patch kind {kind}"))),
+        }
+    }
+    if !r.is_empty() {
+        return Err(invalid("trailing data"));
+    }
+    data.pathseg_words = validate(&data)?;
+    Ok(data)
+}
+
+/// Checks that the streams of a fragment agree with each other, returning the size of
+/// the path segment data computed from the tag stream.
+fn validate(data: &SceneData) -> Result<usize, Error> {
+    let mut n_transform = 0;
+    let mut n_linewidth = 0;
+    let mut n_path = 0;
+    let mut n_pathseg = 0;
+    // Size of the path segment data in 32-bit words, as computed by reduce_tag
+    // in pathtag.wgsl.
+    let mut pathseg_words = 0;
+    let stream_words = data.pathseg_stream.len() as i64 / 4;
+    if data.pathseg_delta_stream.len() != data.transform_stream.len() {
+        return Err(invalid(
+            "path segment delta count doesn't match the transform count",
+        ));
+    }
+    for &tag in &data.tag_stream {
+        match tag {
+            PATHTAG_TRANSFORM => n_transform += 1,
+            PATHTAG_LINEWIDTH => n_linewidth += 1,
+            PATHTAG_PATH => n_path += 1,
+            _ if is_segment(tag) => {
+                // Points are two words as f32 pairs, or one as i16 pairs.
+                let stride = if tag & PATHTAG_F32 != 0 { 2 } else { 1 };
+                // Segments before the first transform have no delta.
+                let delta = match n_transform {
+                    0 => 0,
+                    n => data.pathseg_delta_stream.get(n - 1).copied().unwrap_or(0),
+                };
+                let start = pathseg_words as i64 + delta as i64;
+                let end = start + ((tag & PATHTAG_SEG_TYPE) as i64 + 1) * stride;
+                if start < 0 || end > stream_words {
+                    return Err(invalid("path segment out of range"));
+                }
+                let n_points = (tag & PATHTAG_SEG_TYPE) + (tag & PATHTAG_SUBPATH_END != 0) as u8;
+                pathseg_words += n_points as usize * stride as usize;
+                n_pathseg += 1;
+            }
+            _ => return Err(Error::InvalidScene(format!("invalid path tag {tag:#x}"))),
+        }
+    }
+    if n_transform != data.transform_stream.len() {
+        return Err(invalid("transform count doesn't match the tag stream"));
+    }
+    if n_linewidth != data.linewidth_stream.len() {
+        return Err(invalid("linewidth count doesn't match the tag stream"));
+    }
+    let is_shared = data.pathseg_delta_stream.iter().any(|delta| *delta != 0);
+    if !is_shared && pathseg_words * 4 != data.pathseg_stream.len() {
+        return Err(invalid("path segment data doesn't match the tag stream"));
+    }
+    if n_path != data.n_path || n_pathseg != data.n_pathseg {
+        return Err(invalid("path counts don't match the tag stream"));
+    }
+    if data.drawtag_stream.len() != data.n_path as usize {
+        return Err(invalid("draw object count doesn't match the path count"));
+    }
+    if !data.user_tags.is_empty() && data.user_tags.len() != data.drawtag_stream.len() {
+        return Err(invalid(
+            "user tag count doesn't match the draw object count",
+        ));
+    }
+    let mut n_clip = 0;
+    let mut drawdata_words = 0;
+    let mut gradient_offsets = HashSet::new();
+    for &tag in &data.drawtag_stream {
+        match tag {
+            DRAWTAG_FILLCOLOR | DRAWTAG_BEGINCLIP | DRAWTAG_ENDCLIP => {}
+            DRAWTAG_FILLLINGRADIENT | DRAWTAG_FILLRADGRADIENT => {
+                gradient_offsets.insert(drawdata_words * 4);
+            }
+            _ => return Err(Error::InvalidScene(format!("invalid draw tag {tag:#x}"))),
+        }
+        // See map_draw_tag in drawtag.wgsl.
+        n_clip += tag & 1;
+        drawdata_words += ((tag >> 2) & 0x7) as usize;
+    }
+    if n_clip != data.n_clip {
+        return Err(invalid("clip count doesn't match the draw tag stream"));
+    }
+    if drawdata_words * 4 != data.drawdata_stream.len() {
+        return Err(invalid("draw data doesn't match the draw tag stream"));
+    }
+    for patch in &data.resources.patches {
+        match patch {
+            ResourcePatch::Ramp { offset, stops } => {
+                if !gradient_offsets.contains(offset) {
+                    return Err(invalid("ramp patch doesn't refer to a gradient"));
+                }
+                if stops.start >= stops.end {
+                    return Err(invalid("ramp patch has no stops"));
+                }
+                if stops.end > data.resources.stops.len() {
+                    return Err(invalid("ramp patch stops out of range"));
+                }
+            }
+        }
+    }
+    Ok(pathseg_words)
+}
+
+fn is_segment(tag: u8) -> bool {
+    tag & !0xf == 0 && tag & PATHTAG_SEG_TYPE != 0
+}
+
+/// Marks the words of the path segment stream that hold i16 points, walking the tag
+/// stream the same way as [validate]. Words that no segment refers to are f32.
+fn compact_words(data: &SceneData, n_words: usize) -> Vec<bool> {
+    let mut compact = vec![false; n_words];
+    let mut n_transform = 0;
+    let mut pathseg_words = 0;
+    for &tag in &data.tag_stream {
+        if tag == PATHTAG_TRANSFORM {
+            n_transform += 1;
+        } else if is_segment(tag) {
+            let stride = if tag & PATHTAG_F32 != 0 { 2 } else { 1 };
+            let delta = match n_transform {
+                0 => 0,
+                n => data.pathseg_delta_stream.get(n - 1).copied().unwrap_or(0),
+            };
+            let start = pathseg_words as i64 + delta as i64;
+            let end = start + ((tag & PATHTAG_SEG_TYPE) as i64 + 1) * stride;
+            if stride == 1 && start >= 0 && end <= n_words as i64 {
+                compact[start as usize..end as usize].fill(true);
+            }
+            let n_points = (tag & PATHTAG_SEG_TYPE) + (tag & PATHTAG_SUBPATH_END != 0) as u8;
+            pathseg_words += n_points as usize * stride as usize;
+        }
+    }
+    compact
+}
+
+/// Converts a path segment word between native and little endian byte order, as two
+/// i16 coordinates if it is compact or as one f32 coordinate otherwise. The conversion
+/// is its own inverse.
+fn swap_pathseg_word(word: &[u8], compact: bool) -> [u8; 4] {
+    if compact {
+        let x = i16::from_ne_bytes([word[0], word[1]]).to_le_bytes();
+        let y = i16::from_ne_bytes([word[2], word[3]]).to_le_bytes();
+        [x[0], x[1], y[0], y[1]]
+    } else {
+        u32::from_ne_bytes(word.try_into().unwrap()).to_le_bytes()
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidScene(message.into())
+}
+
+impl Writer {
+    /// Writes a byte stream made of native endian 32-bit words.
+    fn words(&mut self, bytes: &[u8]) {
+        self.len(bytes.len() / 4);
+        for word in bytes.chunks_exact(4) {
+            self.u32(u32::from_ne_bytes(word.try_into().unwrap()));
+        }
+    }
+
+    /// Writes the path segment stream of a fragment.
+    fn pathsegs(&mut self, data: &SceneData) {
+        let n_words = data.pathseg_stream.len() / 4;
+        self.len(n_words);
+        let compact = compact_words(data, n_words);
+        for (word, compact) in data.pathseg_stream.chunks_exact(4).zip(compact) {
+            self.data
+                .extend_from_slice(&swap_pathseg_word(word, compact));
+        }
+    }
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a stream of 32-bit words into native endian bytes.
+    fn words(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.len(4)?;
+        let bytes = self.take(len * 4)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .flat_map(|word| u32::from_le_bytes(word.try_into().unwrap()).to_ne_bytes())
+            .collect())
+    }
+
+    /// Reads the path segment stream of a fragment whose tag and delta streams have
+    /// already been read.
+    fn pathsegs(&mut self, data: &SceneData) -> Result<Vec<u8>, Error> {
+        let n_words = self.len(4)?;
+        let bytes = self.take(n_words * 4)?;
+        let compact = compact_words(data, n_words);
+        Ok(bytes
+            .chunks_exact(4)
+            .zip(compact)
+            .flat_map(|(word, compact)| swap_pathseg_word(word, compact))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Affine, BezPath, Circle, Rect};
+    use peniko::{Color, Extend, Fill, LinearGradient, Mix, Stroke};
+
+    use super::*;
+    use crate::{SceneBuilder, SceneFragment};
+
+    /// Builds a fragment using every stream: f32 and i16 paths, a gradient, a clip,
+    /// line widths, user tags and shared path data.
+    fn fragment() -> SceneFragment {
+        let mut shape = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut shape);
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            Color::rgb8(0, 255, 0),
+            None,
+            &Circle::new((5.0, 5.0), 4.5),
+        );
+        builder.finish();
+
+        let mut fragment = SceneFragment::new();
+        let mut builder = SceneBuilder::for_fragment(&mut fragment);
+        builder.set_tag(7);
+        builder.fill(
+            Fill::NonZero,
+            Affine::translate((10.0, 20.0)),
+            Color::rgb8(255, 0, 0),
+            None,
+            &Rect::new(0.0, 0.0, 50.0, 40.0),
+        );
+        builder.push_layer(
+            Mix::Multiply,
+            0.5,
+            Affine::IDENTITY,
+            &Circle::new((20.0, 20.0), 15.0),
+        );
+        let gradient = LinearGradient {
+            start: (0.0, 0.0).into(),
+            end: (10.0, 0.0).into(),
+            stops: [
+                (0.0, Color::rgb8(0, 0, 0)).into(),
+                (1.0, Color::rgb8(255, 255, 255)).into(),
+            ]
+            .into_iter()
+            .collect(),
+            extend: Extend::Pad,
+        };
+        builder.fill(
+            Fill::NonZero,
+            Affine::IDENTITY,
+            &gradient,
+            Some(Affine::scale(2.0)),
+            &Circle::new((30.0, 30.0), 10.0),
+        );
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.quad_to((5.0, 5.0), (10.0, 0.0));
+        path.line_to((3.5, 3.0));
+        builder.stroke(
+            &Stroke::new(2.0),
+            Affine::IDENTITY,
+            Color::rgb8(0, 0, 255),
+            None,
+            &path,
+        );
+        builder.pop_layer();
+        builder.append_instance(&shape, Some(Affine::translate((100.0, 0.0))));
+        builder.append_instance(&shape, Some(Affine::translate((200.0, 0.0))));
+        builder.finish();
+        fragment
+    }
+
+    fn error(data: &SceneData) -> String {
+        match decode(&encode(data)) {
+            Err(Error::InvalidScene(message)) => message,
+            Err(error) => panic!("unexpected error {error:?}"),
+            Ok(_) => panic!("invalid scene was accepted"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let fragment = fragment();
+        let data = &fragment.data;
+        assert!(data
+            .tag_stream
+            .iter()
+            .any(|&tag| is_segment(tag) && tag & PATHTAG_F32 == 0));
+        assert!(data.pathseg_delta_stream.iter().any(|&delta| delta != 0));
+        let bytes = encode(data);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(decoded.transform_stream, data.transform_stream);
+        assert_eq!(decoded.pathseg_delta_stream, data.pathseg_delta_stream);
+        assert_eq!(decoded.tag_stream, data.tag_stream);
+        assert_eq!(decoded.pathseg_stream, data.pathseg_stream);
+        assert_eq!(decoded.linewidth_stream, data.linewidth_stream);
+        assert_eq!(decoded.drawtag_stream, data.drawtag_stream);
+        assert_eq!(decoded.drawdata_stream, data.drawdata_stream);
+        assert_eq!(decoded.user_tags, data.user_tags);
+        assert_eq!(decoded.pathseg_words, data.pathseg_words);
+        assert_eq!(decoded.dump(), data.dump());
+        assert_eq!(encode(&decoded), bytes);
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = encode(&fragment().data);
+        for len in 0..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "accepted {len} bytes");
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut bytes = encode(&fragment().data);
+        bytes[0] = b'X';
+        assert!(matches!(
+            decode(&bytes),
+            Err(Error::InvalidScene(message)) if message == "not a vello scene fragment"
+        ));
+    }
+
+    #[test]
+    fn segment_out_of_range() {
+        let mut truncated = fragment();
+        let len = truncated.data.pathseg_stream.len();
+        truncated.data.pathseg_stream.truncate(len - 4);
+        assert_eq!(error(&truncated.data), "path segment out of range");
+
+        let mut shared = fragment();
+        *shared.data.pathseg_delta_stream.last_mut().unwrap() = 1 << 20;
+        assert_eq!(error(&shared.data), "path segment out of range");
+    }
+
+    #[test]
+    fn draw_data_size_mismatch() {
+        let mut fragment = fragment();
+        fragment.data.drawdata_stream.extend_from_slice(&[0; 4]);
+        assert_eq!(
+            error(&fragment.data),
+            "draw data doesn't match the draw tag stream"
+        );
+    }
+
+    #[test]
+    fn user_tag_count_mismatch() {
+        let mut fragment = fragment();
+        fragment.data.user_tags.pop();
+        assert_eq!(
+            error(&fragment.data),
+            "user tag count doesn't match the draw object count"
+        );
+    }
+
+    #[test]
+    fn empty_ramp() {
+        let mut fragment = fragment();
+        let ResourcePatch::Ramp { stops, .. } = &mut fragment.data.resources.patches[0];
+        stops.end = stops.start;
+        assert_eq!(error(&fragment.data), "ramp patch has no stops");
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2022 The piet-gpu authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Statistics of encoded scenes, for budgeting content.
+
+use std::collections::HashSet;
+
+use super::*;
+use crate::RendererOptions;
+
+/// Counts of the objects in a scene and sizes of its streams.
+#[derive(Clone, Default, Debug)]
+pub struct SceneStats {
+    /// Number of paths, including the paths that delimit layers.
+    pub n_path: u32,
+    pub n_line: u32,
+    pub n_quad: u32,
+    pub n_cubic: u32,
+    /// Number of clip draw objects, two for each layer.
+    pub n_clip: u32,
+    /// Maximum number of nested layers.
+    pub max_layer_depth: u32,
+    pub n_transform: usize,
+    /// Number of draw objects with a gradient brush.
+    pub n_gradient: usize,
+    /// Number of distinct gradient ramps, each taking a row of the ramp texture.
+    pub n_ramp: usize,
+    pub stream_sizes: StreamSizes,
+}
+
+/// Sizes of the streams of a scene in bytes.
+#[derive(Clone, Default, Debug)]
+pub struct StreamSizes {
+    pub transform: usize,
+    pub pathseg_delta: usize,
+    pub tag: usize,
+    pub pathseg: usize,
+    pub linewidth: usize,
+    pub drawtag: usize,
+    pub drawdata: usize,
+    pub stops: usize,
+}
+
+impl StreamSizes {
+    /// Returns the combined size of the streams.
+    pub fn total(&self) -> usize {
+        self.transform
+            + self.pathseg_delta
+            + self.tag
+            + self.pathseg
+            + self.linewidth
+            + self.drawtag
+            + self.drawdata
+            + self.stops
+    }
+}
+
+/// Estimated GPU memory used to render a scene.
+#[derive(Clone, Default, Debug)]
+pub struct GpuMemory {
+    /// Size in bytes of each buffer and image of the pipeline, by name.
+    pub buffers: Vec<(&'static str, u64)>,
+}
+
+impl GpuMemory {
+    /// Returns the combined size of the buffers and images.
+    pub fn total(&self) -> u64 {
+        self.buffers.iter().map(|(_, size)| size).sum()
+    }
+}
+
+impl SceneStats {
+    /// Estimates the GPU memory needed to render the scene into a target of the given
+    /// size with a renderer created with the given options.
+    ///
+    /// The sizes are those allocated by the renderer, including the intermediate buffers
+    /// that don't yet depend on the scene.
+    pub fn gpu_memory(&self, options: &RendererOptions, width: u32, height: u32) -> GpuMemory {
+        GpuMemory {
+            buffers: crate::render::buffer_sizes(self, options, width, height),
+        }
+    }
+}
+
+pub fn stats(data: &SceneData) -> SceneStats {
+    let mut stats = SceneStats {
+        n_path: data.n_path,
+        n_clip: data.n_clip,
+        n_transform: data.transform_stream.len(),
+        ..Default::default()
+    };
+    for &tag in &data.tag_stream {
+        if tag & !0xf == 0 {
+            match tag & PATHTAG_SEG_TYPE {
+                PATHTAG_LINETO => stats.n_line += 1,
+                PATHTAG_QUADTO => stats.n_quad += 1,
+                0 => {}
+                _ => stats.n_cubic += 1,
+            }
+        }
+    }
+    let mut depth = 0;
+    for &tag in &data.drawtag_stream {
+        match tag {
+            DRAWTAG_FILLLINGRADIENT | DRAWTAG_FILLRADGRADIENT => stats.n_gradient += 1,
+            DRAWTAG_BEGINCLIP => {
+                depth += 1;
+                stats.max_layer_depth = stats.max_layer_depth.max(depth);
+            }
+            DRAWTAG_ENDCLIP => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    // Ramps are shared by gradients with the same stops, see RampCache.
+    let resources = &data.resources;
+    let ramps: HashSet<&[ColorStop]> = resources
+        .patches
+        .iter()
+        .filter_map(|patch| match patch {
+            ResourcePatch::Ramp { stops, .. } => resources.stops.get(stops.clone()),
+        })
+        .collect();
+    stats.n_ramp = ramps.len();
+    stats.stream_sizes = StreamSizes {
+        transform: std::mem::size_of_val(data.transform_stream.as_slice()),
+        pathseg_delta: std::mem::size_of_val(data.pathseg_delta_stream.as_slice()),
+        tag: data.tag_stream.len(),
+        pathseg: data.pathseg_stream.len(),
+        linewidth: std::mem::size_of_val(data.linewidth_stream.as_slice()),
+        drawtag: std::mem::size_of_val(data.drawtag_stream.as_slice()),
+        drawdata: data.drawdata_stream.len(),
+        stops: std::mem::size_of_val(resources.stops.as_slice()),
+    };
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use peniko::kurbo::{Affine, BezPath, Rect};
+    use peniko::{Color, Fill, LinearGradient, Mix};
+
+    use crate::{Scene, SceneBuilder};
+
+    fn scene(build: impl FnOnce(&mut SceneBuilder)) -> Scene {
+        let mut scene = Scene::new();
+        let mut builder = SceneBuilder::for_scene(&mut scene);
+        build(&mut builder);
+        builder.finish();
+        scene
+    }
+
+    fn gradient(end: Color) -> LinearGradient {
+        LinearGradient {
+            start: (0.0, 0.0).into(),
+            end: (10.0, 0.0).into(),
+            stops: [(0.0, Color::rgb8(0, 0, 0)).into(), (1.0, end).into()]
+                .into_iter()
+                .collect(),
+            extend: Default::default(),
+        }
+    }
+
+    #[test]
+    fn segment_counts() {
+        let mut path = BezPath::new();
+        path.move_to((0.0, 0.0));
+        path.line_to((10.0, 0.0));
+        path.quad_to((20.0, 0.0), (20.0, 10.0));
+        path.curve_to((20.0, 20.0), (10.0, 20.0), (0.0, 20.0));
+        path.close_path();
+        let scene = scene(|b| {
+            let color = Color::rgb8(255, 0, 0);
+            b.fill(Fill::NonZero, Affine::IDENTITY, color, None, &path);
+        });
+        let stats = scene.data().stats();
+        assert_eq!(stats.n_path, 1);
+        // Closing the path adds a line back to its start.
+        assert_eq!((stats.n_line, stats.n_quad, stats.n_cubic), (2, 1, 1));
+    }
+
+    #[test]
+    fn ramps_are_shared() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let scene = scene(|b| {
+            let red = gradient(Color::rgb8(255, 0, 0));
+            let blue = gradient(Color::rgb8(0, 0, 255));
+            b.fill(Fill::NonZero, Affine::IDENTITY, &red, None, &rect);
+            b.fill(Fill::NonZero, Affine::IDENTITY, &blue, None, &rect);
+            b.fill(Fill::NonZero, Affine::IDENTITY, &red, None, &rect);
+            b.fill(
+                Fill::NonZero,
+                Affine::IDENTITY,
+                Color::rgb8(0, 0, 0),
+                None,
+                &rect,
+            );
+        });
+        let stats = scene.data().stats();
+        assert_eq!(stats.n_gradient, 3);
+        assert_eq!(stats.n_ramp, 2);
+    }
+
+    #[test]
+    fn layer_depth() {
+        let rect = Rect::new(0.0, 0.0, 10.0, 10.0);
+        let scene = scene(|b| {
+            b.push_layer(Mix::Normal, 1.0, Affine::IDENTITY, &rect);
+            b.push_layer(Mix::Normal, 1.0, Affine::IDENTITY, &rect);
+            b.pop_layer();
+            b.pop_layer();
+            b.push_layer(Mix::Normal, 1.0, Affine::IDENTITY, &rect);
+            b.pop_layer();
+        });
+        let stats = scene.data().stats();
+        assert_eq!(stats.max_layer_depth, 2);
+        assert_eq!(stats.n_clip, 6);
+        assert_eq!(stats.n_path, 6);
+    }
+}
@@ -16,25 +16,28 @@
 
 //! Load rendering shaders.
 
+mod bundle;
+mod cache;
+#[cfg(feature = "hot_reload")]
+mod hot_reload;
 mod preprocess;
 
-use std::collections::HashSet;
+use std::collections::HashMap;
 
 use wgpu::Device;
 
-use crate::engine::{BindType, Engine, Error, ImageFormat, ShaderId};
+use crate::engine::{BindType, Engine, ImageFormat, ShaderId};
+use crate::{Error, RendererOptions};
 
-pub const PATHTAG_REDUCE_WG: u32 = 256;
-pub const PATH_BBOX_WG: u32 = 256;
-pub const PATH_COARSE_WG: u32 = 256;
-pub const PATH_DRAWOBJ_WG: u32 = 256;
-pub const CLIP_REDUCE_WG: u32 = 256;
+pub use bundle::{BundleTargets, BundledShader, ShaderBundle};
+pub use cache::ShaderCache;
+#[cfg(feature = "hot_reload")]
+pub use hot_reload::ShaderWatcher;
 
-macro_rules! shader {
-    ($name:expr) => {
-        include_str!(concat!("../shader/", $name, ".wgsl"))
-    };
-}
+/// Number of horizontally adjacent pixels processed by each thread in fine rasterization.
+///
+/// This must match `PIXELS_PER_THREAD` in fine.wgsl.
+pub const FINE_PIXELS_PER_THREAD: u32 = 4;
 
 pub struct Shaders {
     pub pathtag_reduce: ShaderId,
@@ -42,6 +45,7 @@ pub struct Shaders {
     pub path_coarse: ShaderId,
     pub backdrop: ShaderId,
     pub fine: ShaderId,
+    pub options: RendererOptions,
 }
 
 // Shaders for the full pipeline
@@ -60,22 +64,46 @@ pub struct FullShaders {
     pub backdrop: ShaderId,
     pub coarse: ShaderId,
     pub fine: ShaderId,
+    /// Options the shaders were compiled with.
+    pub options: RendererOptions,
 }
 
+impl FullShaders {
+    /// Returns the shader with the given label.
+    #[cfg(feature = "hot_reload")]
+    pub fn get(&self, label: &str) -> Option<ShaderId> {
+        Some(match label {
+            "pathtag_reduce" => self.pathtag_reduce,
+            "pathtag_scan" => self.pathtag_scan,
+            "bbox_clear" => self.bbox_clear,
+            "pathseg" => self.pathseg,
+            "draw_reduce" => self.draw_reduce,
+            "draw_leaf" => self.draw_leaf,
+            "clip_reduce" => self.clip_reduce,
+            "clip_leaf" => self.clip_leaf,
+            "binning" => self.binning,
+            "tile_alloc" => self.tile_alloc,
+            "path_coarse" => self.path_coarse,
+            "backdrop" => self.backdrop,
+            "coarse" => self.coarse,
+            "fine" => self.fine,
+            _ => return None,
+        })
+    }
+}
+
+#[allow(unused)]
 pub fn init_shaders(device: &Device, engine: &mut Engine) -> Result<Shaders, Error> {
-    let imports = SHARED_SHADERS
-        .iter()
-        .copied()
-        .collect::<std::collections::HashMap<_, _>>();
-    let empty = HashSet::new();
-    let pathtag_reduce = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("pathtag_reduce"), &empty, &imports).into(),
+    let options = RendererOptions::default();
+    let mut loader = ShaderLoader::new(device, simple_sources(&options), None, false);
+    let pathtag_reduce = loader.add(
+        engine,
+        "pathtag_reduce",
         &[BindType::Uniform, BindType::BufReadOnly, BindType::Buffer],
     )?;
-    let pathtag_scan = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("pathtag_scan"), &empty, &imports).into(),
+    let pathtag_scan = loader.add(
+        engine,
+        "pathtag_scan",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -83,12 +111,9 @@ pub fn init_shaders(device: &Device, engine: &mut Engine) -> Result<Shaders, Err
             BindType::Buffer,
         ],
     )?;
-    let path_coarse_config = HashSet::new();
-    // path_coarse_config.add("cubics_out");
-
-    let path_coarse = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("path_coarse"), &path_coarse_config, &imports).into(),
+    let path_coarse = loader.add(
+        engine,
+        "path_coarse",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -97,14 +122,10 @@ pub fn init_shaders(device: &Device, engine: &mut Engine) -> Result<Shaders, Err
             BindType::Buffer,
         ],
     )?;
-    let backdrop = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("backdrop"), &empty, &imports).into(),
-        &[BindType::Uniform, BindType::Buffer],
-    )?;
-    let fine = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("fine"), &empty, &imports).into(),
+    let backdrop = loader.add(engine, "backdrop", &[BindType::Uniform, BindType::Buffer])?;
+    let fine = loader.add(
+        engine,
+        "fine",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -118,25 +139,50 @@ pub fn init_shaders(device: &Device, engine: &mut Engine) -> Result<Shaders, Err
         path_coarse,
         backdrop,
         fine,
+        options,
     })
 }
 
-pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders, Error> {
-    let imports = SHARED_SHADERS
-        .iter()
-        .copied()
-        .collect::<std::collections::HashMap<_, _>>();
-    let empty = HashSet::new();
-    let mut full_config = HashSet::new();
-    full_config.insert("full".into());
-    let pathtag_reduce = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("pathtag_reduce"), &full_config, &imports).into(),
+/// Creates the shaders of the full pipeline.
+///
+/// If a bundle is provided, the preprocessed shaders are taken from it, and it must have
+/// been built with the same options.
+pub fn full_shaders(
+    device: &Device,
+    engine: &mut Engine,
+    options: &RendererOptions,
+    bundle: Option<&ShaderBundle>,
+) -> Result<FullShaders, Error> {
+    let mut loader = ShaderLoader::new(device, full_sources(options), bundle, false);
+    add_full_shaders(&mut loader, engine, options)
+}
+
+/// Creates the shaders of the full pipeline, compiling the pipelines concurrently.
+pub async fn full_shaders_async(
+    device: &Device,
+    engine: &mut Engine,
+    options: &RendererOptions,
+    bundle: Option<&ShaderBundle>,
+) -> Result<FullShaders, Error> {
+    let mut loader = ShaderLoader::new(device, full_sources(options), bundle, true);
+    let shaders = add_full_shaders(&mut loader, engine, options)?;
+    loader.finish(engine).await?;
+    Ok(shaders)
+}
+
+fn add_full_shaders(
+    loader: &mut ShaderLoader,
+    engine: &mut Engine,
+    options: &RendererOptions,
+) -> Result<FullShaders, Error> {
+    let pathtag_reduce = loader.add(
+        engine,
+        "pathtag_reduce",
         &[BindType::Uniform, BindType::BufReadOnly, BindType::Buffer],
     )?;
-    let pathtag_scan = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("pathtag_scan"), &full_config, &imports).into(),
+    let pathtag_scan = loader.add(
+        engine,
+        "pathtag_scan",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -144,30 +190,27 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::Buffer,
         ],
     )?;
-    let bbox_clear = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("bbox_clear"), &empty, &imports).into(),
-        &[BindType::Uniform, BindType::Buffer],
-    )?;
-    let pathseg = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("pathseg"), &full_config, &imports).into(),
+    let bbox_clear = loader.add(engine, "bbox_clear", &[BindType::Uniform, BindType::Buffer])?;
+    let pathseg = loader.add(
+        engine,
+        "pathseg",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
             BindType::BufReadOnly,
             BindType::Buffer,
             BindType::Buffer,
+            BindType::Buffer,
         ],
     )?;
-    let draw_reduce = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("draw_reduce"), &empty, &imports).into(),
+    let draw_reduce = loader.add(
+        engine,
+        "draw_reduce",
         &[BindType::Uniform, BindType::BufReadOnly, BindType::Buffer],
     )?;
-    let draw_leaf = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("draw_leaf"), &empty, &imports).into(),
+    let draw_leaf = loader.add(
+        engine,
+        "draw_leaf",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -178,9 +221,9 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::Buffer,
         ],
     )?;
-    let clip_reduce = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("clip_reduce"), &empty, &imports).into(),
+    let clip_reduce = loader.add(
+        engine,
+        "clip_reduce",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -189,9 +232,9 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::Buffer,
         ],
     )?;
-    let clip_leaf = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("clip_leaf"), &empty, &imports).into(),
+    let clip_leaf = loader.add(
+        engine,
+        "clip_leaf",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -202,9 +245,9 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::Buffer,
         ],
     )?;
-    let binning = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("binning"), &empty, &imports).into(),
+    let binning = loader.add(
+        engine,
+        "binning",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -214,11 +257,12 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::Buffer,
             BindType::Buffer,
             BindType::Buffer,
+            BindType::Buffer,
         ],
     )?;
-    let tile_alloc = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("tile_alloc"), &empty, &imports).into(),
+    let tile_alloc = loader.add(
+        engine,
+        "tile_alloc",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -226,31 +270,30 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::Buffer,
             BindType::Buffer,
             BindType::Buffer,
+            BindType::Buffer,
         ],
     )?;
 
-    let path_coarse = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("path_coarse_full"), &full_config, &imports).into(),
+    let path_coarse = loader.add(
+        engine,
+        "path_coarse",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
             BindType::BufReadOnly,
-            BindType::BufReadOnly,
-            BindType::BufReadOnly,
             BindType::Buffer,
             BindType::Buffer,
             BindType::Buffer,
         ],
     )?;
-    let backdrop = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("backdrop_dyn"), &empty, &imports).into(),
+    let backdrop = loader.add(
+        engine,
+        "backdrop",
         &[BindType::Uniform, BindType::BufReadOnly, BindType::Buffer],
     )?;
-    let coarse = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("coarse"), &empty, &imports).into(),
+    let coarse = loader.add(
+        engine,
+        "coarse",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -263,9 +306,9 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::Buffer,
         ],
     )?;
-    let fine = engine.add_shader(
-        device,
-        preprocess::preprocess(shader!("fine"), &full_config, &imports).into(),
+    let fine = loader.add(
+        engine,
+        "fine",
         &[
             BindType::Uniform,
             BindType::BufReadOnly,
@@ -274,6 +317,8 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
             BindType::BufReadOnly,
             BindType::ImageRead(ImageFormat::Rgba8),
             BindType::BufReadOnly,
+            BindType::Buffer,
+            BindType::BufReadOnly,
         ],
     )?;
     Ok(FullShaders {
@@ -291,9 +336,330 @@ pub fn full_shaders(device: &Device, engine: &mut Engine) -> Result<FullShaders,
         backdrop,
         coarse,
         fine,
+        options: *options,
     })
 }
 
+/// A shader source file together with the defines it is preprocessed with.
+pub struct ShaderSource {
+    /// Name of the shader in the engine.
+    pub label: &'static str,
+    /// Name of the source file, without extension.
+    pub name: &'static str,
+    pub defines: HashMap<String, String>,
+}
+
+impl ShaderSource {
+    fn new(label: &'static str, name: &'static str, defines: HashMap<String, String>) -> Self {
+        Self {
+            label,
+            name,
+            defines,
+        }
+    }
+
+    /// Runs the preprocessor on the embedded sources, returning the WGSL and its source
+    /// map.
+    pub fn preprocess(&self) -> Result<(String, preprocess::SourceMap), Error> {
+        let source = SHADERS
+            .iter()
+            .find(|(name, _)| *name == self.name)
+            .map(|(_, source)| *source)
+            .expect("unknown shader");
+        let imports = SHARED_SHADERS.iter().copied().collect();
+        self.preprocess_with(source, &imports)
+    }
+
+    /// Runs the preprocessor on the given source and shared shaders.
+    pub fn preprocess_with(
+        &self,
+        source: &str,
+        imports: &HashMap<&str, &str>,
+    ) -> Result<(String, preprocess::SourceMap), Error> {
+        let file = format!("{}.wgsl", self.name);
+        preprocess::preprocess(&file, source, &self.defines, imports).map_err(|e| {
+            Error::ShaderCompilation {
+                shader: self.label,
+                location: Some(e.location),
+                message: e.message,
+            }
+        })
+    }
+}
+
+/// Returns the sources of the full pipeline.
+pub fn full_sources(options: &RendererOptions) -> Vec<ShaderSource> {
+    let config = |full, wg_size| defines(options, full, wg_size);
+    vec![
+        ShaderSource::new(
+            "pathtag_reduce",
+            "pathtag_reduce",
+            config(true, options.pathtag_reduce_wg),
+        ),
+        ShaderSource::new(
+            "pathtag_scan",
+            "pathtag_scan",
+            config(true, options.pathtag_reduce_wg),
+        ),
+        ShaderSource::new(
+            "bbox_clear",
+            "bbox_clear",
+            config(false, options.path_bbox_wg),
+        ),
+        ShaderSource::new("pathseg", "pathseg", config(true, options.path_coarse_wg)),
+        ShaderSource::new(
+            "draw_reduce",
+            "draw_reduce",
+            config(false, options.n_tile()),
+        ),
+        ShaderSource::new("draw_leaf", "draw_leaf", config(false, options.n_tile())),
+        ShaderSource::new(
+            "clip_reduce",
+            "clip_reduce",
+            config(false, options.clip_reduce_wg),
+        ),
+        ShaderSource::new(
+            "clip_leaf",
+            "clip_leaf",
+            config(false, options.clip_reduce_wg),
+        ),
+        ShaderSource::new("binning", "binning", config(false, options.n_tile())),
+        ShaderSource::new(
+            "tile_alloc",
+            "tile_alloc",
+            config(false, options.path_bbox_wg),
+        ),
+        ShaderSource::new(
+            "path_coarse",
+            "path_coarse_full",
+            config(true, options.path_coarse_wg),
+        ),
+        ShaderSource::new(
+            "backdrop",
+            "backdrop_dyn",
+            config(false, options.path_bbox_wg),
+        ),
+        ShaderSource::new("coarse", "coarse", config(false, options.n_tile())),
+        ShaderSource::new("fine", "fine", fine_defines(options, true)),
+    ]
+}
+
+fn simple_sources(options: &RendererOptions) -> Vec<ShaderSource> {
+    let path_coarse_config = defines(options, false, options.path_coarse_wg);
+    // path_coarse_config.insert("cubics_out".into(), String::new());
+    vec![
+        ShaderSource::new(
+            "pathtag_reduce",
+            "pathtag_reduce",
+            defines(options, false, options.pathtag_reduce_wg),
+        ),
+        ShaderSource::new(
+            "pathtag_scan",
+            "pathtag_scan",
+            defines(options, false, options.pathtag_reduce_wg),
+        ),
+        ShaderSource::new("path_coarse", "path_coarse", path_coarse_config),
+        ShaderSource::new("backdrop", "backdrop", defines(options, false, 64)),
+        ShaderSource::new("fine", "fine", fine_defines(options, false)),
+    ]
+}
+
+/// Returns the defines for a shader with a one dimensional workgroup.
+///
+/// These describe the tile geometry, documented in config.wgsl, and the workgroup
+/// size. `full` selects the full pipeline variant of shaders that have one.
+fn defines(options: &RendererOptions, full: bool, wg_size: u32) -> HashMap<String, String> {
+    let n_slice = options.n_tile() / 32;
+    let mut defines: HashMap<String, String> = [
+        ("TILE_WIDTH", options.tile_width),
+        ("TILE_HEIGHT", options.tile_height),
+        ("N_TILE_X", options.n_tile_x),
+        ("N_TILE_Y", options.n_tile_y),
+        ("N_TILE", options.n_tile()),
+        ("N_SLICE", n_slice),
+        ("N_SUBSLICE", n_slice / 2),
+        ("WG_SIZE", wg_size),
+        ("PATH_COARSE_WG", options.path_coarse_wg),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_owned(), format!("{value}u")))
+    .collect();
+    if full {
+        defines.insert("full".into(), String::new());
+    }
+    defines
+}
+
+/// Returns the defines for fine rasterization, which runs a workgroup per tile.
+fn fine_defines(options: &RendererOptions, full: bool) -> HashMap<String, String> {
+    let wg_size_x = options.tile_width / FINE_PIXELS_PER_THREAD;
+    let mut defines = defines(options, full, wg_size_x * options.tile_height);
+    defines.insert("WG_SIZE_X".into(), format!("{wg_size_x}u"));
+    defines.insert("WG_SIZE_Y".into(), format!("{}u", options.tile_height));
+    defines
+}
+
+/// Preprocesses shader sources, or takes them from a bundle, and compiles them into
+/// the engine.
+///
+/// In deferred mode, compilation is postponed until [ShaderLoader::finish].
+struct ShaderLoader<'a> {
+    device: &'a Device,
+    sources: Vec<ShaderSource>,
+    bundle: Option<&'a ShaderBundle>,
+    deferred: bool,
+    /// Preprocessed source and source map of deferred shaders, to locate errors.
+    source_maps: HashMap<&'static str, (String, preprocess::SourceMap)>,
+}
+
+impl<'a> ShaderLoader<'a> {
+    fn new(
+        device: &'a Device,
+        sources: Vec<ShaderSource>,
+        bundle: Option<&'a ShaderBundle>,
+        deferred: bool,
+    ) -> Self {
+        Self {
+            device,
+            sources,
+            bundle,
+            deferred,
+            source_maps: HashMap::new(),
+        }
+    }
+
+    fn add(
+        &mut self,
+        engine: &mut Engine,
+        label: &'static str,
+        layout: &[BindType],
+    ) -> Result<ShaderId, Error> {
+        let (wgsl, source_map) = match self.bundle {
+            Some(bundle) => {
+                let shader = bundle
+                    .get(label)
+                    .ok_or_else(|| Error::InvalidBundle(format!("missing shader `{label}`")))?;
+                (shader.wgsl().to_owned(), None)
+            }
+            None => {
+                let source = self
+                    .sources
+                    .iter()
+                    .find(|source| source.label == label)
+                    .expect("unknown shader");
+                let (wgsl, source_map) = source.preprocess()?;
+                (wgsl, Some(source_map))
+            }
+        };
+        if self.deferred {
+            let id = engine.add_shader_deferred(label, wgsl.clone().into(), layout);
+            if let Some(source_map) = source_map {
+                self.source_maps.insert(label, (wgsl, source_map));
+            }
+            return Ok(id);
+        }
+        engine
+            .add_shader(self.device, label, wgsl.clone().into(), layout)
+            .map_err(|e| match source_map {
+                Some(source_map) => locate_error(e, &wgsl, &source_map),
+                None => e,
+            })
+    }
+
+    /// Compiles the shaders added in deferred mode.
+    async fn finish(self, engine: &mut Engine) -> Result<(), Error> {
+        engine.compile_pending(self.device).await.map_err(|e| {
+            let source = match &e {
+                Error::ShaderCompilation { shader, .. } => self.source_maps.get(shader),
+                _ => None,
+            };
+            match source {
+                Some((wgsl, source_map)) => locate_error(e, wgsl, source_map),
+                None => e,
+            }
+        })
+    }
+}
+
+/// Fills in the location of a shader compilation error reported by wgpu.
+///
+/// wgpu reports errors against the preprocessed source, so the source is compiled again
+/// with naga and the error mapped back through the source map. This only happens on
+/// failure, so it doesn't slow down startup.
+fn locate_error(e: Error, wgsl: &str, source_map: &preprocess::SourceMap) -> Error {
+    match e {
+        Error::ShaderCompilation {
+            shader,
+            location: None,
+            message,
+        } => Error::ShaderCompilation {
+            shader,
+            location: validate(shader, wgsl, source_map)
+                .err()
+                .and_then(|e| match e {
+                    Error::ShaderCompilation { location, .. } => location,
+                    _ => None,
+                }),
+            message,
+        },
+        e => e,
+    }
+}
+
+/// Parses and validates preprocessed WGSL with naga.
+///
+/// Errors are mapped back to the original sources through the source map.
+pub fn validate(
+    label: &'static str,
+    wgsl: &str,
+    source_map: &preprocess::SourceMap,
+) -> Result<(naga::Module, naga::valid::ModuleInfo), Error> {
+    let locate = |location: Option<naga::SourceLocation>| {
+        location.and_then(|location| source_map.lookup(location.line_number as usize))
+    };
+    let module = naga::front::wgsl::parse_str(wgsl).map_err(|e| Error::ShaderCompilation {
+        shader: label,
+        location: locate(e.location(wgsl)),
+        message: e.message().to_owned(),
+    })?;
+    let info = naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|e| Error::ShaderCompilation {
+        shader: label,
+        location: locate(e.location(wgsl)),
+        message: e.to_string(),
+    })?;
+    Ok((module, info))
+}
+
+macro_rules! shader {
+    ($name:expr) => {
+        ($name, include_str!(concat!("../shader/", $name, ".wgsl")))
+    };
+}
+
+const SHADERS: &[(&str, &str)] = &[
+    shader!("backdrop"),
+    shader!("backdrop_dyn"),
+    shader!("bbox_clear"),
+    shader!("binning"),
+    shader!("clip_leaf"),
+    shader!("clip_reduce"),
+    shader!("coarse"),
+    shader!("draw_leaf"),
+    shader!("draw_reduce"),
+    shader!("fine"),
+    shader!("path_coarse"),
+    shader!("path_coarse_full"),
+    shader!("pathseg"),
+    shader!("pathtag_reduce"),
+    shader!("pathtag_scan"),
+    shader!("tile_alloc"),
+];
+
 macro_rules! shared_shader {
     ($name:expr) => {
         (
@@ -311,6 +677,7 @@ const SHARED_SHADERS: &[(&str, &str)] = &[
     shared_shader!("config"),
     shared_shader!("cubic"),
     shared_shader!("drawtag"),
+    shared_shader!("indirect"),
     shared_shader!("pathtag"),
     shared_shader!("ptcl"),
     shared_shader!("segment"),
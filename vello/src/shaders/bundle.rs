@@ -0,0 +1,257 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Ahead-of-time shader bundles.
+//!
+//! A bundle holds the preprocessed WGSL of every shader in the full pipeline for one
+//! set of [RendererOptions], validated with naga, and optionally translated to SPIR-V
+//! and MSL. Building a bundle doesn't need a GPU, so it can run in a build script or in
+//! a test to check every shader.
+//!
+//! The binary format starts with the magic bytes `VSHB` and a version, followed by the
+//! options and the shaders. All integers are little endian.
+
+use naga::back::{msl, spv};
+
+use super::{full_sources, validate};
+use crate::codec::{Reader, Writer};
+use crate::{Error, RendererOptions};
+
+const MAGIC: &[u8; 4] = b"VSHB";
+const VERSION: u32 = 1;
+
+const HAS_SPIRV: u8 = 1;
+const HAS_MSL: u8 = 2;
+
+/// Selects the shader languages emitted in addition to WGSL.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BundleTargets {
+    pub spirv: bool,
+    pub msl: bool,
+}
+
+/// A shader of the full pipeline in a bundle.
+#[derive(Clone, Debug)]
+pub struct BundledShader {
+    label: String,
+    wgsl: String,
+    spirv: Option<Vec<u32>>,
+    msl: Option<String>,
+}
+
+impl BundledShader {
+    /// Name of the shader in the pipeline.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// Preprocessed WGSL source.
+    pub fn wgsl(&self) -> &str {
+        &self.wgsl
+    }
+
+    /// SPIR-V translation, if requested when building the bundle.
+    pub fn spirv(&self) -> Option<&[u32]> {
+        self.spirv.as_deref()
+    }
+
+    /// MSL translation, if requested when building the bundle.
+    pub fn msl(&self) -> Option<&str> {
+        self.msl.as_deref()
+    }
+}
+
+/// Preprocessed and validated shaders for one set of renderer options.
+#[derive(Clone, Debug)]
+pub struct ShaderBundle {
+    options: RendererOptions,
+    shaders: Vec<BundledShader>,
+}
+
+impl ShaderBundle {
+    /// Preprocesses and validates every shader of the full pipeline.
+    ///
+    /// The options are checked against the constraints of the shaders, but not against
+    /// device limits, which are checked when the bundle is loaded.
+    pub fn build(options: &RendererOptions, targets: BundleTargets) -> Result<Self, Error> {
+        options.validate(None)?;
+        let mut shaders = vec![];
+        for source in full_sources(options) {
+            let label = source.label;
+            let (wgsl, source_map) = source.preprocess()?;
+            let (module, info) = validate(label, &wgsl, &source_map)?;
+            let backend_error = |message: String| Error::ShaderCompilation {
+                shader: label,
+                location: None,
+                message,
+            };
+            let spirv = if targets.spirv {
+                let words = spv::write_vec(&module, &info, &spv::Options::default(), None)
+                    .map_err(|e| backend_error(format!("SPIR-V: {e}")))?;
+                Some(words)
+            } else {
+                None
+            };
+            let msl = if targets.msl {
+                let (source, _) = msl::write_string(
+                    &module,
+                    &info,
+                    &msl::Options::default(),
+                    &msl::PipelineOptions::default(),
+                )
+                .map_err(|e| backend_error(format!("MSL: {e}")))?;
+                Some(source)
+            } else {
+                None
+            };
+            shaders.push(BundledShader {
+                label: label.to_owned(),
+                wgsl,
+                spirv,
+                msl,
+            });
+        }
+        Ok(Self {
+            options: *options,
+            shaders,
+        })
+    }
+
+    /// Options the bundle was built with.
+    pub fn options(&self) -> &RendererOptions {
+        &self.options
+    }
+
+    /// Returns all shaders in the bundle.
+    pub fn shaders(&self) -> &[BundledShader] {
+        &self.shaders
+    }
+
+    /// Returns the shader with the given label.
+    pub fn get(&self, label: &str) -> Option<&BundledShader> {
+        self.shaders.iter().find(|shader| shader.label == label)
+    }
+
+    /// Serializes the bundle.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.data.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+        w.options(&self.options);
+        w.u32(self.shaders.len() as u32);
+        for shader in &self.shaders {
+            w.bytes(shader.label.as_bytes());
+            w.bytes(shader.wgsl.as_bytes());
+            let mut flags = 0;
+            if shader.spirv.is_some() {
+                flags |= HAS_SPIRV;
+            }
+            if shader.msl.is_some() {
+                flags |= HAS_MSL;
+            }
+            w.u8(flags);
+            if let Some(spirv) = &shader.spirv {
+                w.len(spirv.len());
+                for word in spirv {
+                    w.u32(*word);
+                }
+            }
+            if let Some(msl) = &shader.msl {
+                w.bytes(msl.as_bytes());
+            }
+        }
+        w.data
+    }
+
+    /// Deserializes a bundle produced by [ShaderBundle::to_bytes].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(bytes, invalid);
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a vello shader bundle"));
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(Error::InvalidBundle(format!(
+                "unsupported bundle version {version}"
+            )));
+        }
+        let options = r.options()?;
+        let n_shaders = r.u32()?;
+        let mut shaders = vec![];
+        for _ in 0..n_shaders {
+            let label = r.string()?;
+            let wgsl = r.string()?;
+            let flags = r.u8()?;
+            let spirv = if flags & HAS_SPIRV != 0 {
+                let len = r.len(4)?;
+                let bytes = r.take(len * 4)?;
+                Some(
+                    bytes
+                        .chunks_exact(4)
+                        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                        .collect(),
+                )
+            } else {
+                None
+            };
+            let msl = if flags & HAS_MSL != 0 {
+                Some(r.string()?)
+            } else {
+                None
+            };
+            shaders.push(BundledShader {
+                label,
+                wgsl,
+                spirv,
+                msl,
+            });
+        }
+        if !r.is_empty() {
+            return Err(invalid("trailing data"));
+        }
+        Ok(Self { options, shaders })
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::InvalidBundle(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Validates every shader of the full pipeline with naga and translates it, for the
+    /// default and downlevel options.
+    #[test]
+    fn build() {
+        let targets = BundleTargets {
+            spirv: true,
+            msl: true,
+        };
+        for options in [RendererOptions::default(), RendererOptions::downlevel()] {
+            let bundle = ShaderBundle::build(&options, targets).unwrap();
+            assert_eq!(bundle.shaders().len(), full_sources(&options).len());
+            for shader in bundle.shaders() {
+                assert!(shader.spirv().is_some() && shader.msl().is_some());
+            }
+            let bytes = bundle.to_bytes();
+            let loaded = ShaderBundle::from_bytes(&bytes).unwrap();
+            assert_eq!(loaded.options(), &options);
+            assert_eq!(loaded.to_bytes(), bytes);
+        }
+    }
+}
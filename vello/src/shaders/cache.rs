@@ -0,0 +1,116 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! On-disk cache of shader bundles.
+
+use std::fs;
+use std::path::PathBuf;
+
+use wgpu::AdapterInfo;
+
+use super::{BundleTargets, ShaderBundle, SHADERS, SHARED_SHADERS};
+use crate::codec::options_to_array;
+use crate::{Error, RendererOptions};
+
+/// A directory of shader bundles, reused across launches.
+///
+/// Entries are keyed by a hash of the shader sources, the renderer options and the
+/// adapter. Since preprocessing is deterministic, the sources and options determine the
+/// preprocessed output. wgpu 0.14 has no API to persist compiled pipelines, so a cache
+/// hit skips preprocessing and validation, but not compilation by the driver.
+pub struct ShaderCache {
+    dir: PathBuf,
+    adapter_hash: u64,
+}
+
+impl ShaderCache {
+    /// Creates a cache in the given directory for the given adapter.
+    ///
+    /// The directory is created when the first entry is written.
+    pub fn new(dir: impl Into<PathBuf>, adapter_info: &AdapterInfo) -> Self {
+        let mut hasher = Fnv1a::default();
+        hasher.write_str(&adapter_info.name);
+        hasher.write_u64(adapter_info.vendor as u64);
+        hasher.write_u64(adapter_info.device as u64);
+        hasher.write_str(&format!("{:?}", adapter_info.device_type));
+        hasher.write_str(&adapter_info.driver);
+        hasher.write_str(&adapter_info.driver_info);
+        hasher.write_str(&format!("{:?}", adapter_info.backend));
+        Self {
+            dir: dir.into(),
+            adapter_hash: hasher.0,
+        }
+    }
+
+    /// Returns the cached bundle for the options, building and storing it on a miss.
+    ///
+    /// Entries that can't be read or are corrupt are rebuilt. The cache is only an
+    /// optimization, so failing to write an entry is not an error.
+    pub fn load_or_build(&self, options: &RendererOptions) -> Result<ShaderBundle, Error> {
+        let path = self.dir.join(format!("{:016x}.vshb", self.key(options)));
+        if let Ok(bytes) = fs::read(&path) {
+            if let Ok(bundle) = ShaderBundle::from_bytes(&bytes) {
+                if bundle.options() == options {
+                    return Ok(bundle);
+                }
+            }
+        }
+        let bundle = ShaderBundle::build(options, BundleTargets::default())?;
+        let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&path, bundle.to_bytes()));
+        Ok(bundle)
+    }
+
+    fn key(&self, options: &RendererOptions) -> u64 {
+        let mut hasher = Fnv1a::default();
+        hasher.write_str(env!("CARGO_PKG_VERSION"));
+        for (name, source) in SHADERS.iter().chain(SHARED_SHADERS) {
+            hasher.write_str(name);
+            hasher.write_str(source);
+        }
+        for value in options_to_array(options) {
+            hasher.write_u64(value as u64);
+        }
+        hasher.write_u64(self.adapter_hash);
+        hasher.0
+    }
+}
+
+/// 64-bit FNV-1a, used because the std hasher is not stable across releases.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write(value.as_bytes());
+    }
+}
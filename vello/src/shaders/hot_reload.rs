@@ -0,0 +1,128 @@
+// Copyright 2022 Google LLC
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+// Also licensed under MIT license, at your choice.
+
+//! Reloading of shaders from disk during development.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use notify::{RecursiveMode, Watcher};
+use wgpu::Device;
+
+use super::{full_sources, locate_error, preprocess, FullShaders};
+use crate::engine::Engine;
+use crate::Error;
+
+/// Watches the shader directory and swaps changed shaders into the engine.
+pub struct ShaderWatcher {
+    shader_dir: PathBuf,
+    _watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    /// Preprocessed source of the shaders currently in the engine, by label.
+    loaded: HashMap<&'static str, String>,
+    dirty: bool,
+}
+
+impl ShaderWatcher {
+    /// Starts watching `shader_dir`, which must have the layout of the `shader`
+    /// directory of this crate.
+    pub fn new(shader_dir: &Path, shaders: &FullShaders) -> Result<Self, Error> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+        watcher
+            .watch(shader_dir, RecursiveMode::Recursive)
+            .map_err(watch_error)?;
+        let mut loaded = HashMap::new();
+        for source in full_sources(&shaders.options) {
+            if let Ok((wgsl, _)) = source.preprocess() {
+                loaded.insert(source.label, wgsl);
+            }
+        }
+        Ok(Self {
+            shader_dir: shader_dir.to_owned(),
+            _watcher: watcher,
+            events,
+            loaded,
+            // Pick up edits made since the crate was built.
+            dirty: true,
+        })
+    }
+
+    /// Reloads the shaders whose preprocessed source changed since the last call.
+    ///
+    /// Returns whether any pipeline was replaced. A shader that fails to compile keeps
+    /// its previous pipeline; the other shaders are still reloaded and the first error
+    /// is returned.
+    pub fn reload(
+        &mut self,
+        device: &Device,
+        engine: &mut Engine,
+        shaders: &FullShaders,
+    ) -> Result<bool, Error> {
+        for event in self.events.try_iter() {
+            let event = event.map_err(watch_error)?;
+            let is_shader = event
+                .paths
+                .iter()
+                .any(|path| path.extension() == Some(OsStr::new("wgsl")));
+            if is_shader && !event.kind.is_access() {
+                self.dirty = true;
+            }
+        }
+        if !self.dirty {
+            return Ok(false);
+        }
+        self.dirty = false;
+        let shared = preprocess::get_imports(&self.shader_dir)?;
+        let imports = shared
+            .iter()
+            .map(|(name, source)| (name.as_str(), source.as_str()))
+            .collect();
+        let mut reloaded = false;
+        let mut first_error = None;
+        for source in full_sources(&shaders.options) {
+            let result = fs::read_to_string(self.shader_dir.join(format!("{}.wgsl", source.name)))
+                .map_err(Error::from)
+                .and_then(|text| source.preprocess_with(&text, &imports))
+                .and_then(|(wgsl, source_map)| {
+                    if self.loaded.get(source.label) == Some(&wgsl) {
+                        return Ok(());
+                    }
+                    let id = shaders.get(source.label).expect("unknown shader");
+                    engine
+                        .replace_shader(device, id, wgsl.clone().into())
+                        .map_err(|e| locate_error(e, &wgsl, &source_map))?;
+                    self.loaded.insert(source.label, wgsl);
+                    reloaded = true;
+                    Ok(())
+                });
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(reloaded),
+        }
+    }
+}
+
+fn watch_error(e: notify::Error) -> Error {
+    Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
+}
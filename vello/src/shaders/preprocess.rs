@@ -1,156 +1,661 @@
-use std::{
-    collections::{HashMap, HashSet},
-    fs,
-    path::Path,
-    vec,
-};
-
-pub fn get_imports(shader_dir: &Path) -> HashMap<String, String> {
+use std::{collections::HashMap, fmt, fs, io, path::Path};
+
+use crate::SourceLocation;
+
+/// Maximum nesting of macro expansions, both in substitution and in `#if` expressions.
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// Reads the shared shaders that can be imported from the `shared` subdirectory of the
+/// shader directory.
+#[allow(unused)]
+pub fn get_imports(shader_dir: &Path) -> io::Result<HashMap<String, String>> {
     let mut imports = HashMap::new();
     let imports_dir = shader_dir.join("shared");
-    for entry in imports_dir
-        .read_dir()
-        .expect("Can read shader import directory")
-    {
-        let entry = entry.expect("Can continue reading shader import directory");
-        if entry.file_type().unwrap().is_file() {
+    for entry in imports_dir.read_dir()? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
             let file_name = entry.file_name();
             if let Some(name) = file_name.to_str() {
-                let suffix = ".wgsl";
-                if name.ends_with(suffix) {
-                    let import_name = name[..(name.len() - suffix.len())].to_owned();
-                    let contents = fs::read_to_string(imports_dir.join(file_name))
-                        .expect("Could read shader {import_name} contents");
+                if let Some(import_name) = name.strip_suffix(".wgsl") {
+                    let import_name = import_name.to_owned();
+                    let contents = fs::read_to_string(imports_dir.join(&file_name))?;
                     imports.insert(import_name, contents);
                 }
             }
         }
     }
-    imports
+    Ok(imports)
+}
+
+/// Error produced while preprocessing a shader.
+#[derive(Debug)]
+pub struct PreprocessError {
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Maps lines of preprocessed output back to the file and line they came from.
+#[derive(Clone, Default, Debug)]
+pub struct SourceMap {
+    files: Vec<String>,
+    /// For each output line, the index into `files` and the 1-based source line.
+    lines: Vec<(u32, u32)>,
+}
+
+impl SourceMap {
+    /// Returns the original location of a 1-based line of the output.
+    pub fn lookup(&self, line: usize) -> Option<SourceLocation> {
+        let (file, line) = *self.lines.get(line.checked_sub(1)?)?;
+        Some(SourceLocation {
+            file: self.files[file as usize].clone(),
+            line: line as usize,
+        })
+    }
 }
 
 pub struct StackItem {
+    /// Lines in this branch are emitted.
     active: bool,
+    /// Some branch of this conditional has been taken (or the enclosing
+    /// region is inactive), so later `#elif`/`#else` branches are skipped.
+    taken: bool,
     else_passed: bool,
+    /// Line of the opening directive, for reporting unterminated conditionals.
+    line: usize,
 }
 
-pub fn preprocess(input: &str, defines: &HashSet<String>, imports: &HashMap<&str, &str>) -> String {
-    let mut output = String::with_capacity(input.len());
-    let mut stack = vec![];
-    'all_lines: for (line_number, mut line) in input.lines().enumerate() {
-        loop {
-            if line.is_empty() {
-                break;
-            }
-            let hash_index = line.find('#');
-            let comment_index = line.find("//");
-            let hash_index = match (hash_index, comment_index) {
-                (Some(hash_index), None) => hash_index,
-                (Some(hash_index), Some(comment_index)) if hash_index < comment_index => hash_index,
-                // Add this line to the output - all directives are commented out or there are no directives
-                _ => break,
-            };
-            let directive_start = &line[hash_index + '#'.len_utf8()..];
-            let directive_len = directive_start
-                // The first character which can't be part of the directive name marks the end of the directive
-                // In practise this should always be whitespace, but in theory a 'unit' directive
-                // could be added
-                .find(|c: char| !c.is_alphanumeric())
-                .unwrap_or(directive_start.len());
-            let directive = &directive_start[..directive_len];
-            let directive_is_at_start = line.trim_start().starts_with('#');
+/// Preprocesses the shader `name` with the given source.
+///
+/// Supported directives, which must be the first item on their line:
+///
+/// * `#define NAME value` and `#undef NAME`. Defines with a value are
+///   substituted for the identifier `NAME` in the output. Defines without a
+///   value are flags, usable in conditionals only.
+/// * `#ifdef NAME`, `#ifndef NAME`, `#if expr`, `#elif expr`, `#else` and
+///   `#endif`. Expressions are integer arithmetic, comparisons, logical
+///   operators and `defined(NAME)`; a flag evaluates to 1.
+/// * `#import name`, which splices in the shared shader `name`.
+///
+/// `defines` provides the initial set of defines; the directives above can
+/// modify it for the remainder of the shader, including imports.
+pub fn preprocess(
+    name: &str,
+    input: &str,
+    defines: &HashMap<String, String>,
+    imports: &HashMap<&str, &str>,
+) -> Result<(String, SourceMap), PreprocessError> {
+    let mut preprocessor = Preprocessor {
+        imports,
+        defines: defines.clone(),
+        output: String::with_capacity(input.len()),
+        source_map: SourceMap::default(),
+        import_stack: vec![],
+    };
+    preprocessor.process(name, input)?;
+    Ok((preprocessor.output, preprocessor.source_map))
+}
+
+struct Preprocessor<'a> {
+    imports: &'a HashMap<&'a str, &'a str>,
+    defines: HashMap<String, String>,
+    output: String,
+    source_map: SourceMap,
+    import_stack: Vec<String>,
+}
 
+impl<'a> Preprocessor<'a> {
+    fn process(&mut self, file: &str, input: &str) -> Result<(), PreprocessError> {
+        let file_ix = self.source_map.files.len() as u32;
+        self.source_map.files.push(file.to_owned());
+        let error = |line: usize, message: String| PreprocessError {
+            location: SourceLocation {
+                file: file.to_owned(),
+                line,
+            },
+            message,
+        };
+        let mut stack: Vec<StackItem> = vec![];
+        for (line_ix, line) in input.lines().enumerate() {
+            let line_number = line_ix + 1;
+            let active = stack.iter().all(|item| item.active);
+            let directive_line = match line.trim_start().strip_prefix('#') {
+                Some(directive_line) => directive_line,
+                None => {
+                    if active {
+                        self.emit(line, file_ix, line_number as u32);
+                    }
+                    continue;
+                }
+            };
+            let directive_line = directive_line
+                .find("//")
+                .map(|ix| &directive_line[..ix])
+                .unwrap_or(directive_line);
+            let directive_len = directive_line
+                .find(|c: char| !is_ident_char(c))
+                .unwrap_or(directive_line.len());
+            let directive = &directive_line[..directive_len];
+            let args = directive_line[directive_len..].trim();
             match directive {
-                if_item @ ("ifdef" | "ifndef" | "else" | "endif") if !directive_is_at_start => {
-                    eprintln!("#{if_item} directives must be the first non_whitespace items on their line, ignoring (line {line_number})");
-                    break;
+                "ifdef" | "ifndef" => {
+                    let name = single_ident(args).map_err(|e| error(line_number, e))?;
+                    let value = self.defines.contains_key(name) == (directive == "ifdef");
+                    stack.push(StackItem {
+                        active: active && value,
+                        taken: !active || value,
+                        else_passed: false,
+                        line: line_number,
+                    });
                 }
-                def_test @ ("ifdef" | "ifndef") => {
-                    let def = directive_start[directive_len..].trim();
-                    let exists = defines.contains(def);
-                    let mode = def_test == "ifdef";
+                "if" => {
+                    // Expressions in inactive regions may refer to names that
+                    // aren't defined, so don't evaluate them.
+                    let value = active && self.eval(args).map_err(|e| error(line_number, e))? != 0;
                     stack.push(StackItem {
-                        active: mode == exists,
+                        active: value,
+                        taken: !active || value,
                         else_passed: false,
+                        line: line_number,
                     });
-                    // Don't add this line to the output; instead process the next line
-                    continue 'all_lines;
                 }
-                "else" => {
-                    let item = stack.last_mut();
-                    if let Some(item) = item {
-                        if item.else_passed {
-                            eprintln!("Second else for same ifdef/ifndef (line {line_number}); ignoring second else")
-                        } else {
-                            item.else_passed = true;
-                            item.active = !item.active;
-                        }
+                "elif" => {
+                    let parent_active = stack.iter().rev().skip(1).all(|item| item.active);
+                    let item = match stack.last_mut() {
+                        Some(item) => item,
+                        None => return Err(error(line_number, "#elif without #if".into())),
+                    };
+                    if item.else_passed {
+                        return Err(error(line_number, "#elif after #else".into()));
                     }
-                    let remainder = directive_start[directive_len..].trim();
-                    if !remainder.is_empty() {
-                        eprintln!("#else directives don't take an argument. `{remainder}` will not be in output (line {line_number})");
+                    if item.taken || !parent_active {
+                        item.active = false;
+                    } else {
+                        let value = self.eval(args).map_err(|e| error(line_number, e))? != 0;
+                        item.active = value;
+                        item.taken = value;
+                    }
+                }
+                "else" | "endif" if !args.is_empty() => {
+                    return Err(error(
+                        line_number,
+                        format!("#{directive} doesn't take an argument, found `{args}`"),
+                    ));
+                }
+                "else" => {
+                    let parent_active = stack.iter().rev().skip(1).all(|item| item.active);
+                    let item = match stack.last_mut() {
+                        Some(item) => item,
+                        None => return Err(error(line_number, "#else without #if".into())),
+                    };
+                    if item.else_passed {
+                        return Err(error(line_number, "second #else for the same #if".into()));
                     }
-                    // Don't add this line to the output; it should be empty (see warning above)
-                    continue 'all_lines;
+                    item.else_passed = true;
+                    item.active = parent_active && !item.taken;
+                    item.taken = true;
                 }
                 "endif" => {
-                    if let None = stack.pop() {
-                        eprintln!("Mismatched endif (line {line_number})");
+                    if stack.pop().is_none() {
+                        return Err(error(line_number, "#endif without #if".into()));
+                    }
+                }
+                "define" => {
+                    let name_len = args.find(|c: char| !is_ident_char(c)).unwrap_or(args.len());
+                    let (name, value) = args.split_at(name_len);
+                    if !is_ident(name)
+                        || !value.is_empty() && !value.starts_with(char::is_whitespace)
+                    {
+                        return Err(error(line_number, format!("invalid #define `{args}`")));
                     }
-                    let remainder = directive_start[directive_len..].trim();
-                    if !remainder.is_empty() {
-                        eprintln!("#endif directives don't take an argument. `{remainder}` will not be in output (line {line_number})");
+                    if active {
+                        self.defines
+                            .insert(name.to_owned(), value.trim().to_owned());
+                    }
+                }
+                "undef" => {
+                    let name = single_ident(args).map_err(|e| error(line_number, e))?;
+                    if active {
+                        self.defines.remove(name);
                     }
-                    // Don't add this line to the output; it should be empty (see warning above)
-                    continue 'all_lines;
                 }
                 "import" => {
-                    output.push_str(&line[..hash_index]);
-                    let directive_end = &directive_start[directive_len..];
-                    let import_name_start = if let Some(import_name_start) =
-                        directive_end.find(|c: char| !c.is_whitespace())
-                    {
-                        import_name_start
-                    } else {
-                        eprintln!("#import needs a non_whitespace argument (line {line_number})");
-                        continue 'all_lines;
+                    let name = single_ident(args).map_err(|e| error(line_number, e))?;
+                    if !active {
+                        continue;
+                    }
+                    let import = match self.imports.get(name) {
+                        Some(import) => *import,
+                        None => return Err(error(line_number, format!("unknown import `{name}`"))),
                     };
-                    let import_name_start = &directive_end[import_name_start..];
-                    let import_name_end_index = import_name_start
-                        // The first character which can't be part of the import name marks the end of the import
-                        .find(|c: char| !(c == '_' || c.is_alphanumeric()))
-                        .unwrap_or(import_name_start.len());
-                    let import_name = &import_name_start[..import_name_end_index];
-                    line = &import_name_start[import_name_end_index..];
-                    let import = imports.get(import_name);
-                    if let Some(import) = import {
-                        // In theory, we can cache this until the top item of the stack changes
-                        // However, in practise there will only ever be at most 2 stack items, so it's reasonable to just recompute it every time
-                        if stack.iter().all(|item| item.active) {
-                            output.push_str(&preprocess(import, defines, imports));
-                        }
-                    } else {
-                        eprintln!("Unknown import `{import_name}` (line {line_number})");
+                    if self.import_stack.iter().any(|item| item == name) {
+                        let mut cycle = self.import_stack.join(" -> ");
+                        cycle.push_str(" -> ");
+                        cycle.push_str(name);
+                        return Err(error(line_number, format!("import cycle: {cycle}")));
                     }
-                    continue;
+                    self.import_stack.push(name.to_owned());
+                    self.process(&format!("shared/{name}.wgsl"), import)?;
+                    self.import_stack.pop();
                 }
-                val => {
-                    eprintln!("Unknown preprocessor directive `{val}` (line {line_number})");
+                _ => {
+                    return Err(error(
+                        line_number,
+                        format!("unknown preprocessor directive `#{directive}`"),
+                    ));
                 }
             }
         }
-        if stack.iter().all(|item| item.active) {
-            // Naga does not yet recognize `const` but web does not allow global `let`. We
-            // use `let` in our canonical sources to satisfy wgsl-analyzer but replace with
-            // `const` when targeting web.
-            if cfg!(target_arch = "wasm32") && line.starts_with("let ") {
-                output.push_str("const");
-                output.push_str(&line[3..]);
+        if let Some(item) = stack.last() {
+            return Err(error(item.line, "unterminated conditional".into()));
+        }
+        Ok(())
+    }
+
+    /// Appends a line to the output, substituting defines.
+    fn emit(&mut self, line: &str, file_ix: u32, line_number: u32) {
+        // Naga does not yet recognize `const` but web does not allow global `let`. We
+        // use `let` in our canonical sources to satisfy wgsl-analyzer but replace with
+        // `const` when targeting web.
+        let line = if cfg!(target_arch = "wasm32") && line.starts_with("let ") {
+            self.output.push_str("const");
+            &line[3..]
+        } else {
+            line
+        };
+        let mut output = std::mem::take(&mut self.output);
+        self.substitute(line, &mut vec![], &mut output);
+        output.push('\n');
+        self.output = output;
+        self.source_map.lines.push((file_ix, line_number));
+    }
+
+    /// Appends `text` to `output`, replacing identifiers that have a value.
+    ///
+    /// `expanding` holds the names currently being expanded, which are left
+    /// as is to avoid infinite recursion.
+    fn substitute<'s>(&'s self, text: &str, expanding: &mut Vec<&'s str>, output: &mut String) {
+        let mut rest = text;
+        while let Some(start) =
+            rest.find(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '/')
+        {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with("//") {
+                break;
+            }
+            let len = rest
+                .find(|c: char| !is_ident_char(c))
+                .unwrap_or(rest.len())
+                .max(1);
+            let (token, tail) = rest.split_at(len);
+            rest = tail;
+            // Numeric literals such as `1u` or `0x10` are not identifiers.
+            if token.starts_with(|c: char| c.is_ascii_digit()) {
+                output.push_str(token);
+                continue;
+            }
+            match self.defines.get_key_value(token) {
+                Some((name, value))
+                    if !value.is_empty()
+                        && !expanding.contains(&name.as_str())
+                        && expanding.len() < MAX_EXPANSION_DEPTH =>
+                {
+                    expanding.push(name);
+                    self.substitute(value, expanding, output);
+                    expanding.pop();
+                }
+                _ => output.push_str(token),
+            }
+        }
+        output.push_str(rest);
+    }
+
+    fn eval(&self, expr: &str) -> Result<i64, String> {
+        if expr.is_empty() {
+            return Err("#if requires an expression".into());
+        }
+        let mut parser = ExprParser {
+            defines: &self.defines,
+            tokens: tokenize(expr)?,
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(value),
+            Some(token) => Err(format!("unexpected `{token}` in expression `{expr}`")),
+        }
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+
+fn is_ident(s: &str) -> bool {
+    !s.is_empty() && !s.starts_with(|c: char| c.is_ascii_digit()) && s.chars().all(is_ident_char)
+}
+
+fn single_ident(args: &str) -> Result<&str, String> {
+    if is_ident(args) {
+        Ok(args)
+    } else if args.is_empty() {
+        Err("expected a name".into())
+    } else {
+        Err(format!("expected a single name, found `{args}`"))
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<&str>, String> {
+    const OPERATORS: &[&str] = &[
+        "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")",
+    ];
+    let mut tokens = vec![];
+    let mut rest = expr.trim_start();
+    while !rest.is_empty() {
+        let len = if rest.starts_with(is_ident_char) {
+            rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len())
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            op.len()
+        } else {
+            let c = rest.chars().next().unwrap();
+            return Err(format!("unexpected character `{c}` in expression `{expr}`"));
+        };
+        tokens.push(&rest[..len]);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser and evaluator for `#if` expressions.
+struct ExprParser<'a> {
+    defines: &'a HashMap<String, String>,
+    tokens: Vec<&'a str>,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.peek() == Some(token);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn or(&mut self) -> Result<i64, String> {
+        let mut value = self.and()?;
+        while self.eat("||") {
+            let rhs = self.and()?;
+            value = (value != 0 || rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> Result<i64, String> {
+        let mut value = self.comparison()?;
+        while self.eat("&&") {
+            let rhs = self.comparison()?;
+            value = (value != 0 && rhs != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn comparison(&mut self) -> Result<i64, String> {
+        let mut value = self.sum()?;
+        while let Some(op @ ("==" | "!=" | "<" | "<=" | ">" | ">=")) = self.peek() {
+            self.pos += 1;
+            let rhs = self.sum()?;
+            value = match op {
+                "==" => value == rhs,
+                "!=" => value != rhs,
+                "<" => value < rhs,
+                "<=" => value <= rhs,
+                ">" => value > rhs,
+                _ => value >= rhs,
+            } as i64;
+        }
+        Ok(value)
+    }
+
+    fn sum(&mut self) -> Result<i64, String> {
+        let mut value = self.product()?;
+        while let Some(op @ ("+" | "-")) = self.peek() {
+            self.pos += 1;
+            let rhs = self.product()?;
+            value = if op == "+" {
+                value.wrapping_add(rhs)
             } else {
-                output.push_str(line);
+                value.wrapping_sub(rhs)
+            };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, String> {
+        let mut value = self.unary()?;
+        while let Some(op @ ("*" | "/" | "%")) = self.peek() {
+            self.pos += 1;
+            let rhs = self.unary()?;
+            value = match op {
+                "*" => value.wrapping_mul(rhs),
+                _ if rhs == 0 => return Err("division by zero".into()),
+                "/" => value.wrapping_div(rhs),
+                _ => value.wrapping_rem(rhs),
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("!") {
+            Ok((self.unary()? == 0) as i64)
+        } else if self.eat("-") {
+            Ok(self.unary()?.wrapping_neg())
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        let token = self.peek().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        if token == "(" {
+            let value = self.or()?;
+            if !self.eat(")") {
+                return Err("expected `)`".into());
+            }
+            Ok(value)
+        } else if token == "defined" {
+            let parens = self.eat("(");
+            let name = self
+                .peek()
+                .filter(|name| is_ident(name))
+                .ok_or("expected a name after `defined`")?;
+            self.pos += 1;
+            if parens && !self.eat(")") {
+                return Err("expected `)`".into());
+            }
+            Ok(self.defines.contains_key(name) as i64)
+        } else if token.starts_with(|c: char| c.is_ascii_digit()) {
+            parse_int(token)
+        } else if is_ident(token) {
+            match self.defines.get(token) {
+                Some(value) if value.is_empty() => Ok(1),
+                Some(value) => {
+                    if self.depth >= MAX_EXPANSION_DEPTH {
+                        return Err(format!("`{token}` expands recursively"));
+                    }
+                    let mut parser = ExprParser {
+                        defines: self.defines,
+                        tokens: tokenize(value)?,
+                        pos: 0,
+                        depth: self.depth + 1,
+                    };
+                    let value = parser.or()?;
+                    if parser.pos != parser.tokens.len() {
+                        return Err(format!("`{token}` is not an integer expression"));
+                    }
+                    Ok(value)
+                }
+                None => Err(format!("`{token}` is not defined")),
             }
-            output.push('\n');
+        } else {
+            Err(format!("unexpected `{token}` in expression"))
+        }
+    }
+}
+
+/// Parses a decimal or hexadecimal integer literal with an optional `u` or `i` suffix.
+fn parse_int(token: &str) -> Result<i64, String> {
+    let digits = token
+        .strip_suffix(|c| c == 'u' || c == 'i')
+        .unwrap_or(token);
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    };
+    value.map_err(|_| format!("invalid integer literal `{token}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str, imports: &[(&str, &str)]) -> Result<(String, SourceMap), PreprocessError> {
+        let defines = [("N", "4"), ("FLAG", "")]
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect();
+        let imports = imports.iter().copied().collect();
+        preprocess("test.wgsl", input, &defines, &imports)
+    }
+
+    fn output(input: &str) -> String {
+        run(input, &[]).unwrap().0
+    }
+
+    fn location(file: &str, line: usize) -> SourceLocation {
+        SourceLocation {
+            file: file.into(),
+            line,
         }
     }
-    output
+
+    #[test]
+    fn if_expressions() {
+        let input = "\
+#if defined(FLAG) && N * 2 + 1 == 9
+a
+#endif
+#if defined MISSING || (N - 6) / 2 != -1
+b
+#endif
+#if !FLAG || N % 3 > 1
+c
+#endif
+#define M N + 1
+#if M * 2 == 10 && M >= 5 && 0x10 / M == 3u
+d
+#endif
+";
+        assert_eq!(output(input), "a\nd\n");
+        let error = run("#if N / (N - 4)\n#endif\n", &[]).unwrap_err();
+        assert_eq!(error.message, "division by zero");
+    }
+
+    #[test]
+    fn branches_of_inactive_parent() {
+        // Expressions in inactive regions are not evaluated, so they may refer to
+        // names that aren't defined.
+        let input = "\
+#ifdef MISSING
+#if UNDEFINED
+a
+#elif UNDEFINED
+b
+#else
+c
+#endif
+#elif N == 4
+#if 0
+d
+#elif N
+e
+#else
+f
+#endif
+#else
+#if 1
+g
+#else
+h
+#endif
+#endif
+";
+        assert_eq!(output(input), "e\n");
+    }
+
+    #[test]
+    fn unterminated_conditional() {
+        let error = run("a\n#ifdef FLAG\n#if 0\n#endif\nb\n", &[]).unwrap_err();
+        assert_eq!(error.location, location("test.wgsl", 2));
+        assert_eq!(error.message, "unterminated conditional");
+
+        // Conditionals don't extend across imports.
+        let imports = [("open", "#ifndef FLAG\n")];
+        let error = run("#import open\n#endif\n", &imports).unwrap_err();
+        assert_eq!(error.location, location("shared/open.wgsl", 1));
+        assert_eq!(error.message, "unterminated conditional");
+    }
+
+    #[test]
+    fn import_cycle() {
+        let imports = [("a", "#import b\n"), ("b", "a\n#import a\n")];
+        let error = run("#import a\n", &imports).unwrap_err();
+        assert_eq!(error.location, location("shared/b.wgsl", 2));
+        assert_eq!(error.message, "import cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn recursive_define() {
+        let input = "#define A B + 1\n#define B A\nlet x = A;\n";
+        assert_eq!(output(input), "let x = A + 1;\n");
+        let error = run(&format!("{input}#if A\n#endif\n"), &[]).unwrap_err();
+        assert_eq!(error.location, location("test.wgsl", 4));
+        assert!(error.message.ends_with("expands recursively"));
+    }
+
+    #[test]
+    fn source_map_across_import() {
+        let imports = [("common", "#define X 2\nlet c = X;\n\nfn f() {}\n")];
+        let (output, source_map) = run("a\n#import common\nb\n", &imports).unwrap();
+        assert_eq!(output, "a\nlet c = 2;\n\nfn f() {}\nb\n");
+        assert_eq!(source_map.lookup(0), None);
+        assert_eq!(source_map.lookup(1), Some(location("test.wgsl", 1)));
+        assert_eq!(
+            source_map.lookup(2),
+            Some(location("shared/common.wgsl", 2))
+        );
+        assert_eq!(
+            source_map.lookup(4),
+            Some(location("shared/common.wgsl", 4))
+        );
+        assert_eq!(source_map.lookup(5), Some(location("test.wgsl", 3)));
+        assert_eq!(source_map.lookup(6), None);
+    }
 }
@@ -16,7 +16,7 @@
 
 //! Simple helpers for managing wgpu state and surfaces.
 
-use super::Result;
+use super::{Error, Result};
 
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use wgpu::{Device, Instance, Limits, Queue, Surface, SurfaceConfiguration};
@@ -31,9 +31,12 @@ pub struct RenderContext {
 impl RenderContext {
     pub async fn new() -> Result<Self> {
         let instance = Instance::new(wgpu::Backends::PRIMARY);
-        let adapter = instance.request_adapter(&Default::default()).await.unwrap();
+        let adapter = instance
+            .request_adapter(&Default::default())
+            .await
+            .ok_or(Error::NoCompatibleAdapter)?;
         let features = adapter.features();
-        let mut limits = Limits::default();
+        let limits = Limits::default();
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {